@@ -0,0 +1,160 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Alternative routes by the via-node method, see Abraham, Delling, Goldberg,
+// Werneck: "Alternative Routes in Road Networks" (2010).
+//
+// A forward Dijkstra from s and a backward Dijkstra from t (on the reversed
+// arcs) give, for every node v settled by both, the shortest route from s to t
+// via v, with cost d(s, v) + d(v, t). Most of these are the optimal route or
+// differ from it only by a small detour, so a via-route is only taken as an
+// alternative if it is "admissible":
+//
+// - limited sharing: it shares at most max_sharing * d(s, t) of its cost with
+//   the optimal route and with every alternative taken before,
+// - local optimality: every part of it of cost up to local_optimality * d(s, t)
+//   is a shortest path (checked with the "T-test" around v only),
+// - bounded stretch: it costs at most (1 + max_stretch) * d(s, t).
+//
+// Candidates are tried in order of cost. Nodes on a route that was already
+// tried give the same route again, so they are skipped.
+
+use std::collections::HashSet;
+use crate::Arc;
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::shortest_path_algorithm::Route;
+
+#[derive(Copy, Clone, Debug)]
+pub struct AlternativeRouteParameters {
+    // The maximum number of routes returned, including the optimal one.
+    pub max_routes: usize,
+
+    // See above, all relative to the cost of the optimal route.
+    pub max_sharing: f64,
+    pub local_optimality: f64,
+    pub max_stretch: f64,
+}
+
+impl Default for AlternativeRouteParameters {
+    // The values suggested in the paper above.
+    fn default() -> Self {
+        AlternativeRouteParameters { max_routes: 3, max_sharing: 0.8, local_optimality: 0.25, max_stretch: 0.25 }
+    }
+}
+
+pub struct AlternativeRoutes {
+    pub parameters: AlternativeRouteParameters,
+
+    // The arcs of the network reversed, for the backward search: the arcs in
+    // reverse_arcs[v] point to the tails of the arcs into v. Their costs and
+    // closures are not used, the search looks up the current ones of the arc
+    // at reverse_positions[v][k] in the adjacency list of the tail (live
+    // traffic changes these, but not which arcs there are).
+    reverse_arcs: Vec<Vec<Arc>>,
+    reverse_positions: Vec<Vec<usize>>,
+}
+
+impl AlternativeRoutes {
+    pub fn new(rn: &RoadNetwork, parameters: AlternativeRouteParameters) -> AlternativeRoutes {
+        let mut reverse_arcs = vec![vec![]; rn.nodes.len()];
+        let mut reverse_positions = vec![vec![]; rn.nodes.len()];
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for (j, arc) in arcs.iter().enumerate() {
+                reverse_arcs[arc.idx].push(Arc { head_node_id: rn.nodes[u].osm_id, idx: u, closed: false, ..*arc });
+                reverse_positions[arc.idx].push(j);
+            }
+        }
+        AlternativeRoutes { parameters, reverse_arcs, reverse_positions }
+    }
+
+    // The optimal route from s to t followed by up to max_routes - 1
+    // alternatives, by increasing cost. Empty if t is not reachable.
+    // NOTE: this allocates three SearchWorkspaces, use
+    // compute_routes_with_workspaces for repeated queries.
+    pub fn compute_routes(&self, rn: &RoadNetwork, s: usize, t: usize) -> Vec<Route> {
+        let mut workspaces = [SearchWorkspace::new(rn.nodes.len()), SearchWorkspace::new(rn.nodes.len()), SearchWorkspace::new(rn.nodes.len())];
+        self.compute_routes_with_workspaces(rn, &mut workspaces, s, t)
+    }
+
+    // Same as compute_routes, with workspaces for the forward search, the
+    // backward search and the local optimality tests.
+    pub fn compute_routes_with_workspaces(&self, rn: &RoadNetwork, workspaces: &mut [SearchWorkspace; 3], s: usize, t: usize) -> Vec<Route> {
+        let [forward, backward, scratch] = workspaces;
+        let dijkstra = Dijkstra { arc_flags: None };
+        let optimal_cost = match dijkstra.compute_shortest_path_with_workspace(scratch, &rn.adjacent_arcs, s, Some(t), |_,_| 0) {
+            Some(cost) => cost,
+            None => return vec![],
+        };
+        let optimal = Route { cost: optimal_cost, nodes: scratch.path(t).unwrap() };
+
+        let max_cost = (optimal_cost as f64 * (1.0 + self.parameters.max_stretch)).floor() as usize;
+        dijkstra.compute_within_budget_with_workspace(forward, &rn.adjacent_arcs, s, max_cost);
+        dijkstra.compute_within_budget_with_arc_costs(backward, &self.reverse_arcs, t, max_cost, |v, k, _| {
+            let arc = &rn.adjacent_arcs[self.reverse_arcs[v][k].idx][self.reverse_positions[v][k]];
+            if arc.closed { usize::MAX } else { arc.cost }
+        });
+
+        let mut candidates: Vec<(usize, usize)> = forward.settled_nodes().iter()
+            .filter(|&&v| backward.is_settled(v))
+            .map(|&v| (forward.distance(v).unwrap() + backward.distance(v).unwrap(), v))
+            .filter(|&(cost, _)| cost <= max_cost)
+            .collect();
+        candidates.sort_unstable();
+
+        let mut tried: HashSet<usize> = optimal.nodes.iter().copied().collect();
+        let mut routes = vec![optimal];
+        for (cost, v) in candidates {
+            if routes.len() >= self.parameters.max_routes { break; }
+            if tried.contains(&v) { continue; }
+
+            let mut nodes = forward.path(v).unwrap();
+            let mut to_t = backward.path(v).unwrap();
+            to_t.reverse();
+            nodes.extend_from_slice(&to_t[1..]);
+            tried.extend(nodes.iter().copied());
+
+            let route = Route { cost, nodes };
+            if self.is_admissible(rn, scratch, &route, v, &routes) {
+                routes.push(route);
+            }
+        }
+        routes
+    }
+
+    // Check the conditions at the top of this file for the route via v, given
+    // the routes taken so far (the first one being the optimal route).
+    fn is_admissible(&self, rn: &RoadNetwork, scratch: &mut SearchWorkspace, route: &Route, v: usize, routes: &[Route]) -> bool {
+        // A via-route can contain a loop if the paths to and from v meet.
+        let mut seen = HashSet::new();
+        if !route.nodes.iter().all(|&u| seen.insert(u)) { return false; }
+
+        let optimal_cost = routes[0].cost as f64;
+        if route.cost as f64 > optimal_cost * (1.0 + self.parameters.max_stretch) { return false; }
+
+        // prefix[i] is the cost from s to route.nodes[i].
+        let mut prefix = vec![0];
+        for pair in route.nodes.windows(2) {
+            prefix.push(prefix.last().unwrap() + rn.arc_cost(pair[0], pair[1]).unwrap());
+        }
+
+        for other in routes {
+            let arcs: HashSet<(usize, usize)> = other.nodes.windows(2).map(|pair| (pair[0], pair[1])).collect();
+            let shared: usize = route.nodes.windows(2).enumerate()
+                .filter(|(_, pair)| arcs.contains(&(pair[0], pair[1])))
+                .map(|(i, _)| prefix[i + 1] - prefix[i])
+                .sum();
+            if shared as f64 > optimal_cost * self.parameters.max_sharing { return false; }
+        }
+
+        // T-test: the part of the route from local_optimality * d(s, t) before
+        // v to local_optimality * d(s, t) after v must be a shortest path.
+        let window = optimal_cost * self.parameters.local_optimality;
+        let i_v = route.nodes.iter().position(|&u| u == v).unwrap();
+        let i = (0..=i_v).rev().find(|&i| (prefix[i_v] - prefix[i]) as f64 >= window).unwrap_or(0);
+        let j = (i_v..route.nodes.len()).find(|&j| (prefix[j] - prefix[i_v]) as f64 >= window).unwrap_or(route.nodes.len() - 1);
+        let dijkstra = Dijkstra { arc_flags: None };
+        let cost = dijkstra.compute_shortest_path_with_workspace(scratch, &rn.adjacent_arcs, route.nodes[i], Some(route.nodes[j]), |_,_| 0);
+        cost == Some(prefix[j] - prefix[i])
+    }
+}
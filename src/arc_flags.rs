@@ -9,104 +9,210 @@
 // be a lot of additional work, with relatively little additional insight.
 
 
-use std::f64::consts::PI;
-use std::collections::HashSet;
-use std::time::{Duration, Instant};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Instant;
 use crate::Arc;
 use crate::Node;
+use crate::RoadNetwork;
 use crate::dijkstra::Dijkstra;
+use crate::dijkstra::{QueryStats, SearchWorkspace};
+use crate::priority_queue::PriorityQueue;
+use crate::parallel;
+use crate::region::Region;
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
+use crate::traffic::CostChanges;
 
 pub struct ArcFlagsAlgorithm {
+    // The number of threads used by precompute_arc_flags.
+    pub num_threads: usize,
+
+    // The precomputed arc flags, one per arc in the same layout as the
+    // adjacency lists: arc_flags[u][j] is the flag of adjacent_arcs[u][j].
+    // They are kept here and not in the Arcs, so that the graph itself stays
+    // read-only during queries and can be shared between threads.
+    arc_flags: Vec<Vec<bool>>,
+
+    // The region used by ShortestPathAlgorithm::preprocess, and which nodes
+    // were inside it at the last precomputation.
+    region: Option<Region>,
+    is_inside_region: Vec<bool>,
+
+    // Whether arc costs changed since the precomputation, see costs_changed.
+    stale: bool,
 }  
 
 impl ArcFlagsAlgorithm {
-    pub fn is_node_in_region(node: &Node, lat_min: f64, lat_max: f64, lng_min: f64, lng_max: f64) -> bool {
-        node.latitude < lat_max && node.latitude > lat_min && node.longitude < lng_max && node.longitude > lng_min
+    pub fn new(num_threads: usize) -> ArcFlagsAlgorithm {
+        ArcFlagsAlgorithm { num_threads, arc_flags: vec![], region: None, is_inside_region: vec![], stale: false }
     }
 
-    // Precompute arc flags for the given (single, see above) region.
-    // NOTE: the arg flags are stored *not* in this object, but as a bit in each
-    // Arc of the graph (to which this object has a reference).
-    pub fn precompute_arc_flags(&self, nodes: &Vec<Node>, adjacent_arcs: &mut Vec<Vec<Arc>>, lat_min: f64, lat_max: f64, lng_min: f64, lng_max: f64 ) -> Vec<usize> {
-        let mut total_duration = Duration::new(0, 0); 
-        //let mut visited: HashSet<usize> = HashSet::new();
+    // The algorithm for the given region, with the precomputation left to
+    // ShortestPathAlgorithm::preprocess.
+    pub fn with_region(region: Region, num_threads: usize) -> ArcFlagsAlgorithm {
+        ArcFlagsAlgorithm { region: Some(region), ..ArcFlagsAlgorithm::new(num_threads) }
+    }
 
-        let now = Instant::now();
-        let dijkstra = Dijkstra { consider_arc_flags: false};
-        let r_lat_min=(PI/180.0)*lat_min;
-        let r_lat_max=(PI/180.0)*lat_max;
-        let r_lng_min=(PI/180.0)*lng_min;
-        let r_lng_max=(PI/180.0)*lng_max;
-
-        let mut inside_region: Vec<usize> = vec![];
-        for u in 0..nodes.len() {
-            if ArcFlagsAlgorithm::is_node_in_region(&nodes[u], r_lat_min, r_lat_max, r_lng_min, r_lng_max) {
-                inside_region.push(u);
+    pub fn is_target_in_region(&self, t: usize) -> bool {
+        self.is_inside_region.get(t).copied().unwrap_or(false)
+    }
+
+    // The nodes inside the region of the last precomputation.
+    pub fn nodes_in_region(&self) -> Vec<usize> {
+        (0..self.is_inside_region.len()).filter(|&u| self.is_inside_region[u]).collect()
+    }
+
+    // For each node v, the arcs (u, v) pointing to it, given as the tail u and
+    // the position of the arc in adjacent_arcs[u].
+    fn reverse_arcs(adjacent_arcs: &[Vec<Arc>]) -> Vec<Vec<(usize, usize)>> {
+        let mut reverse_arcs = vec![vec![]; adjacent_arcs.len()];
+        for (u, arcs) in adjacent_arcs.iter().enumerate() {
+            for (j, arc) in arcs.iter().enumerate() {
+                reverse_arcs[arc.idx].push((u, j));
             }
         }
+        reverse_arcs
+    }
 
-        println!("Number of nodes in region: {}", inside_region.len());
-
-        for i in 0..inside_region.len() {
-            let u = inside_region[i];
-            //if visited.contains(&u) { continue; }
-
-            //visited.insert(u);
-            let mut boundary_node_processed = false;
-            for j in 0..adjacent_arcs[u].len() {
-                let v = adjacent_arcs[u][j].idx;
-                //if visited.contains(&v) { continue; }
-
-                // visited.insert(v);
-                // source and destination are both inside the region
-                if inside_region.contains(&v) {
-                    adjacent_arcs[u][j].arc_flag=true;
-                    // looping over inside_Region will catch the reverse way
-                    //adjacent_arcs[v][u].arc_flag=true;       
-                    continue;
+    // Compute the tree of shortest paths from all nodes *to* the given node b,
+    // by running Dijkstra on the reversed arcs. Afterwards parent_arc[u] is the
+    // position in adjacent_arcs[u] of the first arc on a shortest path from u
+    // to b (usize::MAX for b itself and for nodes that cannot reach b).
+    // Returns the nodes of the tree and what the search did. g_score and
+    // parent_arc must be all usize::MAX on entry and are reset before returning.
+    fn backward_shortest_path_tree(adjacent_arcs: &[Vec<Arc>], reverse_arcs: &[Vec<(usize, usize)>], b: usize,
+                                   g_score: &mut [usize], parent_arc: &mut [usize]) -> (Vec<(usize, usize)>, QueryStats) {
+        let now = Instant::now();
+        let mut stats = QueryStats::default();
+        let mut tree = vec![];
+        let mut priority_queue = BinaryHeap::new();
+        g_score[b] = 0;
+        priority_queue.push(Reverse((0, b)));
+        stats.queue_pushes += 1;
+        while let Some(Reverse((cost, v))) = priority_queue.pop() {
+            if cost > g_score[v] { continue; }
+            stats.settled_nodes += 1;
+            if parent_arc[v] != usize::MAX { tree.push((v, parent_arc[v])); }
+            for &(u, j) in &reverse_arcs[v] {
+                if adjacent_arcs[u][j].closed { stats.skipped_arcs += 1; continue; }
+                stats.relaxed_arcs += 1;
+                let new_cost = cost + adjacent_arcs[u][j].cost;
+                if new_cost < g_score[u] {
+                    g_score[u] = new_cost;
+                    parent_arc[u] = j;
+                    priority_queue.push(Reverse((new_cost, u)));
+                    stats.queue_pushes += 1;
                 }
+            }
+        }
 
-                // Only compute this once
-                if !boundary_node_processed {
-                    boundary_node_processed = true;
-                    let (_, _, previous_node, _) = dijkstra.compute_shortest_path(nodes, adjacent_arcs, u, None, |_,_| 0);
-                    //println!("i/Visited.len/previous_node.len: {}/{}/{}", i, visited.len(), previous_node.len());
-                    // Need to set the arc_flag on the reverse way
-                    for (head, tail) in previous_node {
-                        for arc in &mut adjacent_arcs[head] {
-                            if arc.idx == tail { 
-                                arc.arc_flag = true; 
-                                break;
-                            }
-                        }
-                        /*
-                        for arc in &mut adjacent_arcs[v] {
-                            if arc.idx == u { 
-                                arc.arc_flag = true; 
-                            }
-                        }
-                        */
-                    }
-                }
+        // Only the entries touched by this search need to be reset. Every node
+        // whose g_score was lowered is eventually settled, so it is in the tree.
+        g_score[b] = usize::MAX;
+        for &(u, _) in &tree {
+            g_score[u] = usize::MAX;
+            parent_arc[u] = usize::MAX;
+        }
+        stats.elapsed = now.elapsed();
+        (tree, stats)
+    }
+
+    // Precompute arc flags for the given (single, see above) region.
+    // NOTE: the arc flags are stored in this object, see arc_flags above.
+    //
+    // An arc (u, v) gets its flag if it lies on a shortest path from u into the
+    // region. All arcs inside the region get their flag. For the arcs outside, it
+    // suffices to look at the boundary nodes of the region (nodes in the region
+    // with an incoming arc from outside): every shortest path into the region
+    // enters it through one of them. So for each boundary node we compute the
+    // tree of shortest paths *to* it with one backward Dijkstra and flag the
+    // arcs of that tree. These searches are independent and distributed over
+    // num_threads threads, each with its own g_score and parent_arc arrays.
+    //
+    // The region can be a rectangle or a polygon, see region.rs. The searches
+    // of the returned stats are the backward searches, one per boundary node.
+    pub fn precompute_arc_flags(&mut self, nodes: &[Node], adjacent_arcs: &[Vec<Arc>], region: &Region) -> PreprocessingStats {
+        let now = Instant::now();
+        self.stale = false;
+        let mut stats = PreprocessingStats::default();
+        let is_inside_region: Vec<bool> = nodes.iter().map(|node| region.contains(node)).collect();
+        let inside_region: Vec<usize> = (0..nodes.len()).filter(|&u| is_inside_region[u]).collect();
+
+        let mut arc_flags: Vec<Vec<bool>> = adjacent_arcs.iter().map(|arcs| vec![false; arcs.len()]).collect();
+
+        // Source and destination are both inside the region.
+        for &u in &inside_region {
+            for (j, arc) in adjacent_arcs[u].iter().enumerate() {
+                if is_inside_region[arc.idx] { arc_flags[u][j] = true; }
             }
         }
-        total_duration = total_duration + now.elapsed();
-        println!("Precompute time: {:?}",  total_duration);
-        inside_region
+
+        let reverse_arcs = ArcFlagsAlgorithm::reverse_arcs(adjacent_arcs);
+        let boundary_nodes: Vec<usize> = inside_region.iter().copied()
+            .filter(|&v| reverse_arcs[v].iter().any(|&(u, _)| !is_inside_region[u]))
+            .collect();
+
+        // The threads only read the graph, the flags are merged in here.
+        parallel::run_jobs(
+            self.num_threads,
+            boundary_nodes.len(),
+            || (vec![usize::MAX; nodes.len()], vec![usize::MAX; nodes.len()]),
+            |(g_score, parent_arc), i| {
+                ArcFlagsAlgorithm::backward_shortest_path_tree(adjacent_arcs, &reverse_arcs, boundary_nodes[i], g_score, parent_arc)
+            },
+            |_, (tree, search_stats)| {
+                for (u, j) in tree {
+                    arc_flags[u][j] = true;
+                }
+                stats.add_search(search_stats);
+            });
+        stats.size_in_bytes = arc_flags.iter().map(|flags| flags.len() * std::mem::size_of::<bool>()).sum();
+        self.arc_flags = arc_flags;
+        self.is_inside_region = is_inside_region;
+        stats.elapsed = now.elapsed();
+        stats
     }
   
     // Compute the shortest paths from the given source to the given target node,
     // using the precomputed arc flags.
     // PRECONDITION: the target node must be *within* the precomputed region.
-    pub fn compute_shortest_path(&self, nodes: &Vec<Node>, adjacent_arcs: &mut Vec<Vec<Arc>>, s: usize, t: usize) -> (Option<usize>, HashSet<usize>) {
-        let dijkstra = Dijkstra { consider_arc_flags: true };
-        let (cost, visited, _, _) = dijkstra.compute_shortest_path(
-            nodes, 
-            adjacent_arcs, 
-            s, 
-            Some(t), 
-            |_,_| 0) ;
-        println!("Cost: {:?}", cost);
-        (cost, visited)
+    pub fn compute_shortest_path<Q: PriorityQueue>(&self, ws: &mut SearchWorkspace<Q>, adjacent_arcs: &[Vec<Arc>], s: usize, t: usize) -> Option<usize> {
+        let dijkstra = Dijkstra { arc_flags: Some(&self.arc_flags) };
+        dijkstra.compute_shortest_path_with_workspace(ws, adjacent_arcs, s, Some(t), |_,_| 0)
+    }
+}
+
+impl ShortestPathAlgorithm for ArcFlagsAlgorithm {
+    fn name(&self) -> &str {
+        "arc-flags"
+    }
+
+    // Precompute the arc flags for the region given to with_region.
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let region = self.region.take().expect("ArcFlagsAlgorithm::preprocess needs a region, see with_region");
+        let stats = self.precompute_arc_flags(&rn.nodes, &rn.adjacent_arcs, &region);
+        self.region = Some(region);
+        stats
+    }
+
+    // The arc flags only help for targets inside the region. For all other
+    // targets this falls back to plain Dijkstra, so the answer is always right.
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        let cost = if self.is_target_in_region(t) && !self.stale {
+            self.compute_shortest_path(ws, &rn.adjacent_arcs, s, t)
+        } else {
+            Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0)
+        };
+        Route::from_workspace(ws, cost, t)
+    }
+
+    // Any change can move a shortest path onto an arc without its flag (a
+    // cheaper arc, or a detour around a more expensive or closed one).
+    fn costs_changed(&mut self, changes: &CostChanges) {
+        if !changes.is_empty() { self.stale = true; }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
     }
-}
\ No newline at end of file
+}
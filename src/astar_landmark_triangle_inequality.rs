@@ -9,91 +9,263 @@
 //
 // vinh: Andrew Goldberg from MS Research first published this algorithm
 // known as "A* Landmark with Triangle Inequality"
-use std::collections::HashSet;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use rand::prelude::*;
 use crate::Arc;
 use crate::Node;
+use crate::RoadNetwork;
 use crate::dijkstra::Dijkstra;
+use crate::dijkstra::SearchWorkspace;
+use crate::priority_queue::PriorityQueue;
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
+use crate::traffic::CostChanges;
+use crate::parallel;
+
+// Distance stored for nodes that cannot be reached from a landmark.
+pub const UNREACHABLE: u32 = u32::MAX;
+
+// Distance stored for unreachable nodes when using 16-bit storage.
+pub const UNREACHABLE_QUANTIZED: u16 = u16::MAX;
+
+// How the landmark distances are stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LandmarkStorage {
+    // One u32 (seconds) per node and landmark.
+    Full,
+
+    // One u16 per node and landmark. Distances are divided by a common scale
+    // factor and rounded down, which halves the memory footprint. The heuristic
+    // accounts for the rounding so it stays admissible.
+    Quantized,
+}
+
+// The landmark distances, in ONE flat array in node-major order: the distances
+// of node u to all landmarks are at [u * num_landmarks .. (u + 1) * num_landmarks].
+// That way, evaluating the heuristic for one node reads one cache line instead
+// of one heap allocation per node.
+#[derive(Clone, Debug)]
+pub enum LandmarkDistances {
+    Full(Vec<u32>),
+
+    // Distance d is stored as d / scale (rounded down).
+    Quantized { distances: Vec<u16>, scale: u32 },
+}
+
+impl LandmarkDistances {
+    // Lower bound on dist(u, v) given by the landmark stored at position l, or
+    // 0 if one of the two nodes is not reachable from that landmark.
+    fn lower_bound(&self, num_landmarks: usize, l: usize, u: usize, v: usize) -> usize {
+        match self {
+            LandmarkDistances::Full(distances) => {
+                let (d_u, d_v) = (distances[u * num_landmarks + l], distances[v * num_landmarks + l]);
+                if d_u == UNREACHABLE || d_v == UNREACHABLE { return 0; }
+                (d_u as i64 - d_v as i64).unsigned_abs() as usize
+            },
+            LandmarkDistances::Quantized { distances, scale } => {
+                let (q_u, q_v) = (distances[u * num_landmarks + l], distances[v * num_landmarks + l]);
+                if q_u == UNREACHABLE_QUANTIZED || q_v == UNREACHABLE_QUANTIZED || q_u == q_v { return 0; }
+                // With d in [q * scale, q * scale + scale - 1], the smallest
+                // possible difference of two distances is this one.
+                let diff = (q_u as i64 - q_v as i64).unsigned_abs() as usize;
+                (diff - 1) * *scale as usize + 1
+            },
+        }
+    }
+
+    // Memory used by the distance array in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        match self {
+            LandmarkDistances::Full(distances) => distances.len() * std::mem::size_of::<u32>(),
+            LandmarkDistances::Quantized { distances, .. } => distances.len() * std::mem::size_of::<u16>(),
+        }
+    }
+}
 
 pub struct LandmarkAlgorithm {
   // PUBLIC members.
   // The set of landmarks. Each entry in the array is a node id.
   landmarks: Vec<usize>,
 
+  // How many landmarks to select. There are fewer (none) only for an empty
+  // network.
+  num_landmarks: usize,
+
   // Precomputed distances (shorted path costs in seconds) to and from these
-  // landmarks. This is one array of size #nodes per landmark.
+  // landmarks, see LandmarkDistances above.
   // NOTE: since our graphs are undirected (or rather, for each arc u,v we also
   // have an arc v,u with the same cost) we have dist(u, l) = dist(l, u) and it
-  // suffices to store one distance array per landmark. For arbitrary directed
-  // graphs we would need one array for the distances *to* the landmark and one
-  // array for the distances *from* the landmark.
-  landmark_distances: Vec<Vec<usize>>,
+  // suffices to store one distance per node and landmark. For arbitrary directed
+  // graphs we would need the distances *to* the landmark and the distances
+  // *from* the landmark.
+  landmark_distances: LandmarkDistances,
+
+  // How to store the distances and how many threads to use for computing them.
+  storage: LandmarkStorage,
+  num_threads: usize,
+
+  // Whether some arc got cheaper since the precomputation, see costs_changed.
+  stale: bool,
 }
 
 impl LandmarkAlgorithm {
-    pub fn new(nodes: &Vec<Node>, adjacent_arcs: &mut Vec<Vec<Arc>>, num_landmarks: usize) -> LandmarkAlgorithm {
-        let mut alt = LandmarkAlgorithm{ landmarks: vec![0; num_landmarks], landmark_distances: vec![vec![0; num_landmarks]; nodes.len()] }; 
-        alt.select_landmarks(nodes.len(), num_landmarks);
-        alt.precompute_landmark_distances(nodes, adjacent_arcs);
+    pub fn new(nodes: &[Node], adjacent_arcs: &[Vec<Arc>], num_landmarks: usize) -> LandmarkAlgorithm {
+        LandmarkAlgorithm::new_with_storage(nodes, adjacent_arcs, num_landmarks, LandmarkStorage::Full, parallel::available_threads())
+    }
+
+    // Like new, but with the given storage for the distances and the given
+    // number of threads for the precomputation.
+    pub fn new_with_storage(nodes: &[Node], adjacent_arcs: &[Vec<Arc>], num_landmarks: usize, storage: LandmarkStorage, num_threads: usize) -> LandmarkAlgorithm {
+        let mut alt = LandmarkAlgorithm::without_preprocessing(num_landmarks, storage, num_threads);
+        alt.select_and_precompute(nodes, adjacent_arcs);
         alt
     }
 
-    // Select the given number of landmarks at random.
-    pub fn select_landmarks(&mut self, nodes_len: usize, num_landmarks: usize) -> () {
-        let mut rng = thread_rng();
-        let distr = rand::distributions::Uniform::new_inclusive(0, nodes_len);
-        for l in 0..num_landmarks {
-            self.landmarks[l] = rng.sample(distr);
+    // The algorithm with the given settings, but without landmarks yet. They
+    // are selected by ShortestPathAlgorithm::preprocess.
+    pub fn without_preprocessing(num_landmarks: usize, storage: LandmarkStorage, num_threads: usize) -> LandmarkAlgorithm {
+        LandmarkAlgorithm{ landmarks: vec![0; num_landmarks], num_landmarks, landmark_distances: LandmarkDistances::Full(vec![]), storage, num_threads, stale: false }
+    }
+
+    fn select_and_precompute(&mut self, nodes: &[Node], adjacent_arcs: &[Vec<Arc>]) -> PreprocessingStats {
+        let now = Instant::now();
+        self.select_landmarks(nodes.len(), self.num_landmarks);
+        let mut stats = self.precompute_landmark_distances(nodes, adjacent_arcs, self.num_threads);
+        if self.storage == LandmarkStorage::Quantized {
+            self.quantize();
+        }
+        stats.size_in_bytes = self.landmark_distances.size_in_bytes();
+        stats.elapsed = now.elapsed();
+        stats
+    }
+
+    // Select the given number of landmarks at random, none if there are no
+    // nodes.
+    pub fn select_landmarks(&mut self, nodes_len: usize, num_landmarks: usize) {
+        if nodes_len == 0 {
+            self.landmarks.clear();
+            return;
         }
+        let mut rng = thread_rng();
+        let distr = rand::distributions::Uniform::new(0, nodes_len);
+        self.landmarks = (0..num_landmarks).map(|_| rng.sample(distr)).collect();
     }
 
     // Precompute the distances to and from the selected landmarks.
     // NOTE: For our undirected / symmetric graphs, the distances *from* the
-    // landmarks are enough, see Array<Array<int>> landmarkDistances below.
-    pub fn precompute_landmark_distances(&mut self, nodes: &Vec<Node>, adjacent_arcs: &mut Vec<Vec<Arc>>) -> () {
-        assert_eq!(nodes.len(), self.landmark_distances.len());
-
-        let mut total_duration = Duration::new(0, 0); 
+    // landmarks are enough, see landmark_distances above.
+    // The searches for the different landmarks are independent, so they are
+    // distributed over num_threads threads, each with its own SearchWorkspace.
+    pub fn precompute_landmark_distances(&mut self, nodes: &[Node], adjacent_arcs: &[Vec<Arc>], num_threads: usize) -> PreprocessingStats {
         let now = Instant::now();
+        self.stale = false;
+        let mut stats = PreprocessingStats::default();
+
+        let num_landmarks = self.landmarks.len();
+        let mut distances = vec![UNREACHABLE; nodes.len() * num_landmarks];
+        let landmarks = &self.landmarks;
+        parallel::run_jobs(
+            num_threads,
+            num_landmarks,
+            || SearchWorkspace::new(nodes.len()),
+            |ws, l| {
+                let dijkstra = Dijkstra { arc_flags: None };
+                dijkstra.compute_shortest_path_with_workspace(ws, adjacent_arcs, landmarks[l], None, |_,_| 0);
+                // Costs that do not fit below the sentinel are treated as unreachable,
+                // that only makes the heuristic weaker.
+                let column = ws.settled_nodes().iter()
+                    .filter_map(|&u| ws.distance(u).filter(|&d| d < UNREACHABLE as usize).map(|d| (u, d as u32)))
+                    .collect::<Vec<_>>();
+                (column, ws.stats())
+            },
+            |l, (column, search_stats)| {
+                for (u, d) in column {
+                    distances[u * num_landmarks + l] = d;
+                }
+                stats.add_search(search_stats);
+            });
+        self.landmark_distances = LandmarkDistances::Full(distances);
+        stats.size_in_bytes = self.landmark_distances.size_in_bytes();
+        stats.elapsed = now.elapsed();
+        stats
+    }
 
-        let dijkstra = Dijkstra { consider_arc_flags: false};
-        for t in 0..self.landmarks.len() {
-            let (_, _, _, g_score) = dijkstra.compute_shortest_path(nodes, adjacent_arcs, self.landmarks[t], None, |_,_| 0);
-            for i in 0..g_score.len() {
-                    self.landmark_distances[i][t] = g_score[i];
-            }
+    // Switch to 16-bit storage. The scale is chosen so that the largest finite
+    // distance fits below the sentinel.
+    pub fn quantize(&mut self) {
+        if let LandmarkDistances::Full(distances) = &self.landmark_distances {
+            let max = distances.iter().filter(|&&d| d != UNREACHABLE).max().copied().unwrap_or(0);
+            let scale = std::cmp::max(1, (max + 1).div_ceil(UNREACHABLE_QUANTIZED as u32));
+            let quantized = distances.iter()
+                .map(|&d| if d == UNREACHABLE { UNREACHABLE_QUANTIZED } else { (d / scale) as u16 })
+                .collect();
+            self.landmark_distances = LandmarkDistances::Quantized { distances: quantized, scale };
         }
-        total_duration = total_duration + now.elapsed();
-        println!("Precompute time: {:?}",  total_duration);
+    }
+
+    pub fn landmark_distances(&self) -> &LandmarkDistances {
+        &self.landmark_distances
     }
 
     fn cost(&self, l: usize, u: usize, v: usize) -> usize {
-        (self.landmark_distances[u][l] as i32 - self.landmark_distances[v][l] as i32).abs() as usize
+        self.landmark_distances.lower_bound(self.landmarks.len(), l, u, v)
     }
 
     // Compute the shortest paths from the given source to the given target node,
     // using A* with the landmark heuristic.
     // NOTE: this algorithm only works in point-to-point mode, so the option
     // targetNodeId == -1 does not make sense here.
-    pub fn compute_shortest_path(&self, nodes: &Vec<Node>, adjacent_arcs: &mut Vec<Vec<Arc>>, s: usize, t: usize) -> (Option<usize>, HashSet<usize>) {
-
-        let dijkstra = Dijkstra { consider_arc_flags: false};
-        let (cost, visited, _, _) = dijkstra.compute_shortest_path(
-            nodes, 
-            adjacent_arcs, 
-            s, 
-            Some(t), 
-            |&u,_| {
-                let mut max = 0;
-                for i in 0..self.landmarks.len() {
-                    let cost = self.cost(i, u, t);
-                    if cost > max { max = cost; }
-                }
-                max
-            }
-            ) ;
-        (cost, visited)
+    // The settled nodes and the path can be read from the workspace afterwards.
+    pub fn compute_shortest_path<Q: PriorityQueue>(&self, ws: &mut SearchWorkspace<Q>, adjacent_arcs: &[Vec<Arc>], s: usize, t: usize) -> Option<usize> {
+
+        let dijkstra = Dijkstra { arc_flags: None };
+        dijkstra.compute_shortest_path_with_workspace(
+            ws,
+            adjacent_arcs,
+            s,
+            Some(t),
+            |&u,_| self.lower_bound(u, t)
+            )
     }
 
+    // The landmark heuristic: a lower bound on the cost from u to t, the
+    // best one over all landmarks.
+    pub fn lower_bound(&self, u: usize, t: usize) -> usize {
+        let mut max = 0;
+        for i in 0..self.landmarks.len() {
+            let cost = self.cost(i, u, t);
+            if cost > max { max = cost; }
+        }
+        max
+    }
+
+}
+
+impl ShortestPathAlgorithm for LandmarkAlgorithm {
+    fn name(&self) -> &str {
+        "alt"
+    }
+
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        self.select_and_precompute(&rn.nodes, &rn.adjacent_arcs)
+    }
+
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        let cost = if self.stale {
+            Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0)
+        } else {
+            self.compute_shortest_path(ws, &rn.adjacent_arcs, s, t)
+        };
+        Route::from_workspace(ws, cost, t)
+    }
+
+    // Distances only grow when arcs get more expensive or closed, so the
+    // precomputed landmark distances are still lower bounds and the heuristic
+    // stays feasible. Only cheaper arcs make it stale.
+    fn costs_changed(&mut self, changes: &CostChanges) {
+        if changes.decreased > 0 { self.stale = true; }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
 }
@@ -1,37 +1,290 @@
 use regex::Regex;
+use std::f64::consts::PI;
+use std::collections::HashMap;
 use std::io::prelude::*;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use efficient_route_planning::osm;
 use efficient_route_planning::RoadNetwork;
-use efficient_route_planning::astar_landmark_triangle_inequality::LandmarkAlgorithm;
+use efficient_route_planning::dijkstra::SearchWorkspace;
+use efficient_route_planning::distance_matrix;
+use efficient_route_planning::isochrone;
+use efficient_route_planning::waypoints;
+use efficient_route_planning::parallel;
+use efficient_route_planning::region::Region;
+use efficient_route_planning::shortest_path_algorithm::{algorithm_by_name, ShortestPathAlgorithm, ALGORITHM_NAMES};
+use efficient_route_planning::traffic::{self, LiveTraffic};
 
+// The number of accepted connections that wait for a free worker.
+const QUEUED_CONNECTIONS: usize = 64;
+
+// The longest request we read (in bytes, up to the end of the headers), and
+// how long one read may wait for the client.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The limits of an isochrone request: the number of budgets, the largest
+// budget (in seconds), and the number of grid cells along the widest area
+// that can be reached within it at osm::MAX_SPEED.
+const MAX_ISOCHRONES: usize = 10;
+const MAX_ISOCHRONE_BUDGET: usize = 3 * 3600;
+const MAX_GRID_CELLS_ACROSS: f64 = 4000.0;
+
+// What all connections share: the network, and the search workspaces of
+// finished queries, so that a new query can reuse one instead of allocating
+// arrays of size #nodes.
+struct Server {
+    rn: RoadNetwork,
+    algorithm: Box<dyn ShortestPathAlgorithm>,
+    workspaces: Mutex<Vec<SearchWorkspace>>,
+}
+
+impl Server {
+    fn take_workspace(&self) -> SearchWorkspace {
+        let workspace = self.workspaces.lock().unwrap().pop();
+        workspace.unwrap_or_else(|| SearchWorkspace::new(self.rn.nodes.len()))
+    }
+
+    fn return_workspace(&self, ws: SearchWorkspace) {
+        self.workspaces.lock().unwrap().push(ws);
+    }
+}
+
+// Usage: maps-demo-server-main [ALGORITHM] [REGION.geojson] [TRAFFIC.feed]
+// ALGORITHM is one of ALGORITHM_NAMES (default: dijkstra). The region is only
+// used by the arc flags. The traffic feed (see traffic.rs) is applied before
+// the preprocessing.
 fn main() -> Result<(), Box<std::io::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let name = args.get(1).map(|s| s.as_str()).unwrap_or("dijkstra");
+    let region = match args.get(2) {
+        Some(filename) => Region::from_geojson_file(filename).unwrap(),
+        None => Region::rectangle(47.95, 48.05, 7.75, 7.90),
+    };
+    let mut algorithm = match algorithm_by_name(name, Some(region)) {
+        Some(algorithm) => algorithm,
+        None => panic!("Unknown algorithm: {}, use one of {:?}", name, ALGORITHM_NAMES),
+    };
 
     let mut rn = osm::read_from_osm_file("tests/baden-wuerttemberg.osm").unwrap();
     //let mut rn = osm::read_from_osm_file("tests/saarland.osm").unwrap();
     println!("Reducing RoadNetwork");
     rn.reduce_to_largest_connected_component();
+    if let Some(filename) = args.get(3) {
+        let updates = traffic::read_feed_file(filename).unwrap();
+        let changes = LiveTraffic::new(&rn).apply_all(&mut rn, &updates).unwrap();
+        println!("Traffic feed {}: {:?}", filename, changes);
+    }
+    println!("Preprocessing for {}", algorithm.name());
+    algorithm.preprocess(&rn);
+
+    // Queries only read the network, so all connections share one copy.
+    let server = Arc::new(Server { rn, algorithm, workspaces: Mutex::new(vec![]) });
 
     let listener = TcpListener::bind("127.0.0.1:8888")?;
 
+    // A fixed number of worker threads answers the connections. The queue in
+    // front of them is bounded, so when all workers are busy and the queue is
+    // full, new connections wait in the listen backlog instead of each getting
+    // a thread of their own. A worker must not panic on a bad connection, or
+    // the pool shrinks.
+    let num_workers = parallel::available_threads();
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(QUEUED_CONNECTIONS);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..num_workers {
+        let (server, receiver) = (Arc::clone(&server), Arc::clone(&receiver));
+        thread::spawn(move || loop {
+            let stream = match receiver.lock().unwrap().recv() {
+                Ok(stream) => stream,
+                Err(_) => break,
+            };
+            handle_connection(&server, stream);
+        });
+    }
+    println!("Listening on 127.0.0.1:8888 with {} workers", num_workers);
+
     for stream in listener.incoming() {
-        let stream = stream.unwrap();
-        handle_connection(&rn, stream);
+        match stream {
+            Ok(stream) => sender.send(stream).unwrap(),
+            Err(e) => println!("Connection failed: {}", e),
+        }
     }
     Ok(())
 }
 
-fn handle_connection(rn: &RoadNetwork, mut stream: TcpStream) {
+// Compute the shortest path between the nodes closest to the two given
+// positions (in degrees), as a flat list lat1, lng1, lat2, lng2, ... in degrees.
+fn compute_path(server: &Server, lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> Option<Vec<f64>> {
+    let rn = &server.rn;
+    let s = rn.get_nearest_node((PI/180.0)*lat1, (PI/180.0)*lng1)?;
+    let t = rn.get_nearest_node((PI/180.0)*lat2, (PI/180.0)*lng2)?;
+    println!("s/t: {:?}/{:?}", rn.nodes[s], rn.nodes[t]);
+
+    let mut ws = server.take_workspace();
+    let route = server.algorithm.compute_route(rn, &mut ws, s, t);
+    server.return_workspace(ws);
+    println!("Cost: {:?}", route.as_ref().map(|route| route.cost));
+
+    Some(route?.nodes.iter()
+        .flat_map(|&u| vec![rn.nodes[u].latitude * 180.0 / PI, rn.nodes[u].longitude * 180.0 / PI])
+        .collect())
+}
+
+// Parse "lat,lng;lat,lng;..." (in degrees) into the nodes closest to these
+// positions.
+fn parse_positions(rn: &RoadNetwork, positions: &str) -> Option<Vec<usize>> {
+    positions.split(';').map(|position| {
+        let (lat, lng) = position.split_once(',')?;
+        let (lat, lng): (f64, f64) = (lat.trim().parse().ok()?, lng.trim().parse().ok()?);
+        rn.get_nearest_node((PI/180.0)*lat, (PI/180.0)*lng)
+    }).collect()
+}
+
+// The parameters of "a=1&b=2" as a map.
+fn query_parameters(query: &str) -> HashMap<&str, &str> {
+    query.split('&').filter_map(|parameter| parameter.split_once('=')).collect()
+}
+
+// Answer "GET /matrix?sources=lat,lng;lat,lng;...&targets=lat,lng;... HTTP/1.1"
+// with the travel times in seconds between the nodes closest to these
+// positions, as JSON: { "durations": [[...], ...] }, with durations[i][j] from
+// source i to target j and null where there is no path.
+fn compute_matrix(server: &Server, query: &str) -> Option<String> {
+    let parameters = query_parameters(query);
+    let sources = parse_positions(&server.rn, parameters.get("sources")?)?;
+    let targets = parse_positions(&server.rn, parameters.get("targets")?)?;
+    println!("Matrix: {} sources, {} targets", sources.len(), targets.len());
+
+    let matrix = distance_matrix::many_to_many(&server.rn, &sources, &targets, parallel::available_threads());
+    Some(serde_json::json!({ "durations": matrix }).to_string())
+}
+
+// Answer "GET /isochrone?position=lat,lng&budgets=300,600,900 HTTP/1.1" with
+// the areas reachable from the node closest to the position within these
+// times (in seconds), as a GeoJSON FeatureCollection, see isochrone.rs. The
+// optional parameter cell_size is the resolution of the polygons in meters.
+// None for invalid parameters or ones beyond the limits above.
+fn compute_isochrones(server: &Server, query: &str) -> Option<String> {
+    let parameters = query_parameters(query);
+    let s = parse_positions(&server.rn, parameters.get("position")?)?[0];
+    let budgets = parameters.get("budgets")?.split(',').map(|budget| budget.parse().ok()).collect::<Option<Vec<usize>>>()?;
+    let cell_size: f64 = match parameters.get("cell_size") {
+        Some(size) => size.parse().ok()?,
+        None => 200.0,
+    };
+    let max_budget = budgets.iter().copied().max().unwrap_or(0);
+    if budgets.len() > MAX_ISOCHRONES || max_budget > MAX_ISOCHRONE_BUDGET { return None; }
+    if !cell_size.is_finite() || cell_size < isochrone::MIN_CELL_SIZE { return None; }
+    let max_distance = max_budget as f64 * osm::MAX_SPEED as f64 / 3.6;
+    if 2.0 * max_distance / cell_size > MAX_GRID_CELLS_ACROSS { return None; }
+
+    let mut ws = server.take_workspace();
+    let isochrones = isochrone::compute_isochrones(&server.rn, &mut ws, s, &budgets, cell_size);
+    server.return_workspace(ws);
+    Some(isochrone::to_geojson(&isochrones).to_string())
+}
+
+// Answer "GET /route?waypoints=lat,lng;lat,lng;... HTTP/1.1" with the route
+// through the nodes closest to these positions, in this order, as JSON:
+// { "cost": seconds, "distance": meters, "path": [[lat, lng], ...],
+//   "legs": [{ "cost": seconds, "distance": meters }, ...] }.
+fn compute_multi_leg_route(server: &Server, query: &str) -> Option<String> {
+    let waypoints = parse_positions(&server.rn, query_parameters(query).get("waypoints")?)?;
+    let mut ws = server.take_workspace();
+    let route = waypoints::compute_route_via_nodes(&server.rn, server.algorithm.as_ref(), &mut ws, &waypoints);
+    server.return_workspace(ws);
+    let route = route?;
+
+    let path: Vec<[f64; 2]> = route.route.nodes.iter()
+        .map(|&u| [server.rn.nodes[u].latitude * 180.0 / PI, server.rn.nodes[u].longitude * 180.0 / PI])
+        .collect();
+    let legs: Vec<serde_json::Value> = route.legs.iter()
+        .map(|leg| serde_json::json!({ "cost": leg.cost, "distance": leg.distance }))
+        .collect();
+    Some(serde_json::json!({ "cost": route.route.cost, "distance": route.distance, "path": path, "legs": legs }).to_string())
+}
+
+// Send the given JSON, as JSONP if the query has a callback parameter (as
+// sent by jQuery for dataType "jsonp"). The callback is put into the script
+// as it is, so only names like "jQuery123_456" or "app.draw" are accepted,
+// anything else is answered with 400.
+fn send_json(stream: &mut TcpStream, query: &str, json: &str) {
+    let callback_name = Regex::new(r"^[A-Za-z_$][A-Za-z0-9_$.]*$").unwrap();
+    let (status, content_type, body) = match query_parameters(query).get("callback") {
+        Some(callback) if callback_name.is_match(callback) => ("HTTP/1.1 200 OK", "application/javascript", format!("{}({})", callback, json)),
+        Some(_) => ("HTTP/1.1 400 Bad Request", "application/json", "{ \"error\": \"invalid callback\" }".to_string()),
+        None => ("HTTP/1.1 200 OK", "application/json", json.to_string()),
+    };
+    let response = format!(
+        "{}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    // If the client has gone away, there is nobody to tell.
+    let _ = stream.write_all(response.as_bytes()).and_then(|_| stream.flush());
+}
+
+// Read the request up to the end of its headers. Matrix requests can be long,
+// so this may take several reads, but at most MAX_REQUEST_SIZE bytes and
+// READ_TIMEOUT per read. Err is the status line to answer with otherwise.
+fn read_request(stream: &mut TcpStream) -> Result<Vec<u8>, &'static str> {
+    const BAD_REQUEST: &str = "HTTP/1.1 400 Bad Request";
+    stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|_| BAD_REQUEST)?;
+    let mut request = vec![];
     let mut buffer = [0; 1024];
+    loop {
+        let n = stream.read(&mut buffer).map_err(|_| BAD_REQUEST)?;
+        // Only the new bytes and the three before them can complete the end.
+        let start = request.len().saturating_sub(3);
+        request.extend_from_slice(&buffer[..n]);
+        if n == 0 || request[start..].windows(4).any(|w| w == b"\r\n\r\n") { return Ok(request); }
+        if request.len() > MAX_REQUEST_SIZE { return Err("HTTP/1.1 413 Payload Too Large"); }
+    }
+}
 
-    stream.read(&mut buffer).unwrap();
+fn handle_connection(server: &Server, mut stream: TcpStream) {
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(status) => {
+            println!("Request failed: {}", status);
+            let _ = stream.write_all(format!("{}\r\nContent-Length: 0\r\n\r\n", status).as_bytes());
+            return;
+        },
+    };
 
     let mut resp = String::new();
-    let req = String::from_utf8_lossy(&buffer[..]);
+    let req = String::from_utf8_lossy(&request);
     if let Some(get) = req.lines().next() {
         println!("Request: {}", get);
 
+        if let Some(query) = get.strip_prefix("GET /matrix?") {
+            let query = query.split(' ').next().unwrap_or("");
+            let json = compute_matrix(server, query).unwrap_or_else(|| "{ \"error\": \"expected sources=lat,lng;...&targets=lat,lng;...\" }".to_string());
+            send_json(&mut stream, query, &json);
+            return;
+        }
+
+        if let Some(query) = get.strip_prefix("GET /route?") {
+            let query = query.split(' ').next().unwrap_or("");
+            let json = compute_multi_leg_route(server, query).unwrap_or_else(|| "{ \"error\": \"expected waypoints=lat,lng;lat,lng;... with a path between them\" }".to_string());
+            send_json(&mut stream, query, &json);
+            return;
+        }
+
+        if let Some(query) = get.strip_prefix("GET /isochrone?") {
+            let query = query.split(' ').next().unwrap_or("");
+            let json = compute_isochrones(server, query).unwrap_or_else(|| "{ \"error\": \"expected position=lat,lng&budgets=t1,t2,... within the limits of the server\" }".to_string());
+            send_json(&mut stream, query, &json);
+            return;
+        }
+
         // Extract four floats from "GET /?a,b,c,d HTTP/1.1"
         let re = Regex::new(r"(?x)
         (?P<lat1>[+-]?([0-9]*[.])?[0-9]+)
@@ -43,18 +296,22 @@ fn handle_connection(rn: &RoadNetwork, mut stream: TcpStream) {
         (?P<lng2>[+-]?([0-9]*[.])?[0-9]+)
         ").unwrap();
 
-        let caps = re.captures(get).unwrap();
-        println!("Caps: {:?}", caps);
-        let s = rn.get_node_from_lat_lng(&caps["lat1"].parse().unwrap(), &caps["lng1"].parse().unwrap());
-        let t = rn.get_node_from_lat_lng(&caps["lat2"].parse().unwrap(), &caps["lng2"].parse().unwrap());
-        if let (Some(s), Some(t)) = (s, t) {
-            println!("s/t: {:?}/{:?}", s, t);
-        }
+        if let Some(caps) = re.captures(get) {
+            println!("Caps: {:?}", caps);
+            let path = compute_path(server, caps["lat1"].parse().unwrap(), caps["lng1"].parse().unwrap(),
+                                    caps["lat2"].parse().unwrap(), caps["lng2"].parse().unwrap());
 
-        // Send JSONP results string back to client.
-        resp = format!("redrawLineServerCallback({{ \
-            path: [{}, {}, {}, {}] \
-        }})", &caps["lat1"], &caps["lng1"], &caps["lat2"], &caps["lng2"]);
+            // Fall back to the straight line if there is no path.
+            let path = match path {
+                Some(path) => path.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", "),
+                None => format!("{}, {}, {}, {}", &caps["lat1"], &caps["lng1"], &caps["lat2"], &caps["lng2"]),
+            };
+
+            // Send JSONP results string back to client.
+            resp = format!("redrawLineServerCallback({{ \
+                path: [{}] \
+            }})", path);
+        }
     }
 
 
@@ -65,5 +322,5 @@ fn handle_connection(rn: &RoadNetwork, mut stream: TcpStream) {
         resp
     );
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();}
\ No newline at end of file
+    let _ = stream.write_all(response.as_bytes()).and_then(|_| stream.flush());
+}
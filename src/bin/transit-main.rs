@@ -0,0 +1,58 @@
+use efficient_route_planning::dijkstra::SearchWorkspace;
+use efficient_route_planning::gtfs::{self, Timetable};
+use efficient_route_planning::osm;
+use efficient_route_planning::raptor::Raptor;
+use efficient_route_planning::transit::{Mode, TransitGraph};
+
+// Stops at most this far apart (in meters) get footpaths.
+const MAX_FOOTPATH_DISTANCE: f64 = 300.0;
+
+fn format_time(time: usize) -> String {
+    format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60)
+}
+
+// Usage: transit-main GTFS_DIR DATE FROM_STOP TO_STOP HH:MM:SS
+// Read the GTFS feed in the given directory (see gtfs.rs) and print the
+// earliest arrival at TO_STOP when leaving FROM_STOP (GTFS stop ids) at the
+// given time on the given date (YYYYMMDD), with the stops on the way. Then
+// the same with transfer times and footpaths, for any number of transfers.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 6 {
+        eprintln!("Usage: transit-main GTFS_DIR DATE FROM_STOP TO_STOP HH:MM:SS");
+        std::process::exit(1);
+    }
+    let mut timetable = Timetable::read_gtfs_dir(&args[1]).unwrap();
+    let date = gtfs::parse_date(&args[2]).unwrap();
+    let s = timetable.stop_index(&args[3]).unwrap_or_else(|| panic!("Unknown stop: {}", args[3]));
+    let t = timetable.stop_index(&args[4]).unwrap_or_else(|| panic!("Unknown stop: {}", args[4]));
+    let departure = gtfs::parse_time(&args[5]).unwrap();
+    println!("{} stops, {} trips", timetable.stops.len(), timetable.trips.len());
+
+    let graph = TransitGraph::new(&timetable, date);
+    println!("{} connections on {}", graph.num_connections(), date);
+    let mut ws = SearchWorkspace::new(timetable.stops.len());
+    match graph.compute_route(&mut ws, s, t, departure) {
+        Some(route) => {
+            println!("Arrival: {}, {} s", format_time(departure + route.cost), route.cost);
+            for u in route.nodes {
+                println!("  {} ({})", timetable.stops[u].name, timetable.stops[u].id);
+            }
+        },
+        None => println!("{} cannot be reached from {} that day", args[4], args[3]),
+    }
+
+    timetable.add_footpaths(MAX_FOOTPATH_DISTANCE, osm::WALKING_SPEED);
+    let raptor = Raptor::new(&timetable, date);
+    for journey in raptor.compute_journeys(&timetable, s, t, departure, usize::MAX) {
+        println!("Arrival with {} transfers: {}", journey.num_transfers(), format_time(journey.arrival));
+        for leg in journey.legs {
+            let mode = match leg.mode {
+                Mode::Walk => "walk".to_string(),
+                Mode::Transit(trip) => format!("route {}", timetable.trips[trip].route_id),
+            };
+            println!("  {} - {} {} from {} to {}", format_time(leg.departure), format_time(leg.arrival), mode,
+                timetable.stops[leg.from].name, timetable.stops[leg.to].name);
+        }
+    }
+}
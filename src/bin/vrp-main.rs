@@ -0,0 +1,21 @@
+use efficient_route_planning::osm;
+use efficient_route_planning::parallel;
+use efficient_route_planning::vrp::{self, Problem};
+
+// Usage: vrp-main PROBLEM.json [MAP.osm]
+// Solve the vehicle routing problem in the given file (see vrp.rs for the
+// format) on the given map, and print the routes and schedules as JSON.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let problem_file = args.get(1).expect("Usage: vrp-main PROBLEM.json [MAP.osm]");
+    let map_file = args.get(2).map(|s| s.as_str()).unwrap_or("tests/baden-wuerttemberg.osm");
+
+    let problem = Problem::from_json_file(problem_file).unwrap();
+    let mut rn = osm::read_from_osm_file(map_file).unwrap();
+    rn.reduce_to_largest_connected_component();
+
+    match vrp::solve(&rn, &problem, parallel::available_threads()) {
+        Some(solution) => println!("{}", serde_json::to_string_pretty(&solution.to_json(&problem)).unwrap()),
+        None => eprintln!("The road network is empty"),
+    }
+}
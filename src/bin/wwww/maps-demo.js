@@ -41,6 +41,10 @@ $(document).ready(function(){
   // and http://tinyurl.com/7mry4xl#Marker -> Subsection "Events".
   google.maps.event.addListener(source, 'dragend', redrawLine);
   google.maps.event.addListener(target, 'dragend', redrawLine);
+
+  // Right-clicking the source marker shows the areas reachable from it within
+  // 5, 10 and 15 minutes.
+  google.maps.event.addListener(source, 'rightclick', showIsochrones);
 });
 
 // Redraw the line between source and target marker. Either do it on the client
@@ -64,10 +68,28 @@ function redrawLineServer() {
   $.ajax(url, { dataType: "jsonp" });
 }
 
-// Function that is called when the server has sent its answer.
+// Function that is called when the server has sent its answer. The path is a
+// flat list lat1, lng1, lat2, lng2, ... of the nodes on the shortest path.
 function redrawLineServerCallback(json) {
   // alert(json.path);
-  var path = [ new google.maps.LatLng(json.path[0], json.path[1]) ,
-               new google.maps.LatLng(json.path[2], json.path[3]) ];
+  var path = [];
+  for (var i = 0; i + 1 < json.path.length; i += 2) {
+    path.push(new google.maps.LatLng(json.path[i], json.path[i + 1]));
+  }
   line.setPath(path);
 }
+
+// Ask the server for the isochrones of the source marker. The answer is a
+// GeoJSON FeatureCollection with one feature per time budget.
+function showIsochrones() {
+  var url = "http://127.0.0.1:8888/isochrone?position="
+    + source.getPosition().lat() + "," + source.getPosition().lng()
+    + "&budgets=900,600,300";
+  $.ajax(url, { dataType: "jsonp", success: showIsochronesCallback });
+}
+
+function showIsochronesCallback(json) {
+  map.data.forEach(function(feature) { map.data.remove(feature); });
+  map.data.addGeoJson(json);
+  map.data.setStyle({ fillColor: "green", fillOpacity: 0.2, strokeWeight: 1 });
+}
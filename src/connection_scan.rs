@@ -0,0 +1,177 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// The Connection Scan Algorithm (CSA), after J. Dibbelt, T. Pajor, B. Strasser,
+// D. Wagner: "Intriguingly Simple and Fast Transit Routing", 2013.
+//
+// A connection is one trip going from one stop to the next one. All
+// connections of a day are kept in one array, sorted by departure. A query
+// needs no priority queue: it scans the connections from the departure time
+// on, and takes a connection if its trip was already taken before, or if we
+// can be at its stop in time. Then the earliest arrival at the next stop can
+// only get better. It stops at the first connection that departs after the
+// earliest arrival at the target.
+//
+// After arriving by a trip we need the minimum transfer time of the stop
+// before taking another trip, after walking we do not (the footpath includes
+// it). Footpaths are taken one at a time from stops where a trip arrives (and
+// from the source), so a walk over two footpaths needs a footpath of its own.
+
+use crate::gtfs::Timetable;
+use crate::transit::{Journey, Leg, Mode};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Connection {
+    pub from: usize,
+    pub to: usize,
+    pub departure: usize,
+    pub arrival: usize,
+    // The index of the trip in Timetable::trips, and of the trip on the date
+    // in Timetable::trips_on (a trip may run on the date and the day before).
+    pub trip: usize,
+    pub trip_on_date: usize,
+}
+
+// How the earliest arrival at a stop was found: with the trip entered at one
+// connection and left after another one, or by walking from a stop.
+#[derive(Copy, Clone, Debug)]
+enum Reached {
+    Source,
+    Ride { enter: usize, exit: usize },
+    Walk { from: usize },
+}
+
+pub struct ConnectionScan {
+    // Sorted by departure, then arrival.
+    connections: Vec<Connection>,
+
+    // The number of trips on the date.
+    num_trips: usize,
+}
+
+impl ConnectionScan {
+    // The connections of the trips of the given date (YYYYMMDD), see
+    // Timetable::trips_on.
+    pub fn new(timetable: &Timetable, date: u32) -> ConnectionScan {
+        let trips = timetable.trips_on(date);
+        let mut connections = vec![];
+        for (trip_on_date, &trip) in trips.iter().enumerate() {
+            for pair in timetable.stop_times_on(trip).windows(2) {
+                connections.push(Connection { from: pair[0].stop, to: pair[1].stop, departure: pair[0].departure, arrival: pair[1].arrival,
+                    trip: trip.trip, trip_on_date });
+            }
+        }
+        connections.sort_unstable_by_key(|c| (c.departure, c.arrival));
+        ConnectionScan { connections, num_trips: trips.len() }
+    }
+
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    // The earliest arrival at stop t when being at stop s at the given time,
+    // or None if t cannot be reached that day.
+    pub fn earliest_arrival(&self, timetable: &Timetable, s: usize, t: usize, departure: usize) -> Option<usize> {
+        self.compute_journey(timetable, s, t, departure).map(|journey| journey.arrival)
+    }
+
+    // The journey with the earliest arrival at t, see above.
+    pub fn compute_journey(&self, timetable: &Timetable, s: usize, t: usize, departure: usize) -> Option<Journey> {
+        let (scan, _) = self.scan(timetable, &[(s, departure)], &[(t, 0)])?;
+        Some(Journey { departure, arrival: scan.arrival[t], legs: scan.legs(self, t) })
+    }
+
+    // Scan from several sources, (stop, time when we are there), to several
+    // targets, (stop, time from there to the destination), until no
+    // connection can lead to an earlier arrival at the destination. Returns
+    // the scan and the best target, or None if no target can be reached.
+    pub(crate) fn scan(&self, timetable: &Timetable, sources: &[(usize, usize)], targets: &[(usize, usize)]) -> Option<(Scan, usize)> {
+        let n = timetable.stops.len();
+        let mut scan = Scan { arrival: vec![usize::MAX; n], ready: vec![usize::MAX; n], reached: vec![Reached::Source; n],
+            to_destination: vec![usize::MAX; n], best: (usize::MAX, usize::MAX) };
+        for &(t, duration) in targets {
+            scan.to_destination[t] = scan.to_destination[t].min(duration);
+        }
+        // The connection where we entered each trip, if we did.
+        let mut entered: Vec<Option<usize>> = vec![None; self.num_trips];
+
+        for &(s, time) in sources {
+            if time < scan.arrival[s] { scan.improve(s, time, time, Reached::Source); }
+        }
+        for &(s, _) in sources {
+            let time = scan.arrival[s];
+            for footpath in &timetable.footpaths[s] {
+                if time + footpath.duration < scan.arrival[footpath.to] {
+                    scan.improve(footpath.to, time + footpath.duration, time + footpath.duration, Reached::Walk { from: s });
+                }
+            }
+        }
+
+        let departure = sources.iter().map(|&(_, time)| time).min().unwrap_or(usize::MAX);
+        let first = self.connections.partition_point(|c| c.departure < departure);
+        for (i, c) in self.connections.iter().enumerate().skip(first) {
+            if c.departure >= scan.best.0 { break; }
+            if entered[c.trip_on_date].is_none() {
+                if scan.ready[c.from] > c.departure { continue; }
+                entered[c.trip_on_date] = Some(i);
+            }
+            if c.arrival >= scan.arrival[c.to] { continue; }
+            let reached = Reached::Ride { enter: entered[c.trip_on_date].unwrap(), exit: i };
+            scan.improve(c.to, c.arrival, c.arrival + timetable.min_transfer_times[c.to], reached);
+            for footpath in &timetable.footpaths[c.to] {
+                if c.arrival + footpath.duration < scan.arrival[footpath.to] {
+                    scan.improve(footpath.to, c.arrival + footpath.duration, c.arrival + footpath.duration, Reached::Walk { from: c.to });
+                }
+            }
+        }
+        if scan.best.0 == usize::MAX { return None; }
+        let target = scan.best.1;
+        Some((scan, target))
+    }
+}
+
+// The earliest arrivals at the stops during and after a scan, and how they
+// were found.
+pub(crate) struct Scan {
+    pub(crate) arrival: Vec<usize>,
+    // When we can take a trip at each stop (after the minimum transfer time
+    // if we came by trip).
+    ready: Vec<usize>,
+    reached: Vec<Reached>,
+
+    // The time from each target to the destination, and the earliest arrival
+    // at the destination so far, with the target it is over.
+    to_destination: Vec<usize>,
+    best: (usize, usize),
+}
+
+impl Scan {
+    fn improve(&mut self, v: usize, arrival: usize, ready: usize, reached: Reached) {
+        self.arrival[v] = arrival;
+        self.ready[v] = ready;
+        self.reached[v] = reached;
+        if self.to_destination[v] < usize::MAX && arrival + self.to_destination[v] < self.best.0 {
+            self.best = (arrival + self.to_destination[v], v);
+        }
+    }
+
+    // The legs from a source to v, following the arrivals back.
+    pub(crate) fn legs(&self, csa: &ConnectionScan, v: usize) -> Vec<Leg> {
+        let mut legs = vec![];
+        let mut v = v;
+        loop {
+            let leg = match self.reached[v] {
+                Reached::Source => break,
+                Reached::Ride { enter, exit } => {
+                    let (enter, exit) = (&csa.connections[enter], &csa.connections[exit]);
+                    Leg { mode: Mode::Transit(enter.trip), from: enter.from, to: v, departure: enter.departure, arrival: exit.arrival }
+                },
+                Reached::Walk { from } => Leg { mode: Mode::Walk, from, to: v, departure: self.arrival[from], arrival: self.arrival[v] },
+            };
+            legs.push(leg);
+            v = leg.from;
+        }
+        legs.reverse();
+        legs
+    }
+}
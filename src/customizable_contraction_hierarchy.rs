@@ -0,0 +1,350 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Customizable contraction hierarchies (CCH), after J. Dibbelt, B. Strasser,
+// D. Wagner: "Customizable Contraction Hierarchies", 2014.
+//
+// The preprocessing has two phases:
+// - compute_topology only looks at which nodes are connected, not at the
+//   costs. It orders the nodes by nested dissection: split the nodes
+//   geometrically into two halves, take the nodes on one side of the cut as
+//   separator, and order the separator after (above) both halves, which are
+//   ordered the same way recursively. Then it contracts the nodes in this
+//   order: the neighbors of a node that come later in the order are all
+//   connected to each other by shortcuts. The result is the "upward" graph:
+//   each edge is stored at its lower end.
+// - customize computes the costs of these edges for the current arc costs.
+//   Every edge x-y gets the cost of its arc, if any, and is then improved over
+//   all triangles v-x-y with v below x and y ("lower triangles"), for v from
+//   the bottom up. That is one pass over the edges, without any search, so it
+//   is cheap enough to run again after every batch of traffic updates (see
+//   traffic.rs). Closed arcs are simply left out.
+//
+// A query from s to t looks only at the nodes above s and t in the
+// elimination tree: the parent of a node is its lowest upward neighbor, and all
+// upward neighbors of a node are among its ancestors. So both searches just
+// walk up the tree, without a priority queue, and the shortest path goes via
+// the common ancestor with the smallest sum of both costs. Shortcuts are
+// unpacked by the middle node of the triangle that gave them their cost.
+//
+// Both directions of an edge get their own cost, so the arc costs need not be
+// symmetric. Nodes are numbered by their rank in the order internally.
+
+use std::time::Instant;
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
+use crate::traffic::CostChanges;
+
+// Parts of at most this many nodes are not split any further.
+const LEAF_SIZE: usize = 8;
+
+// The cost of an edge without a path, and the marker for "no node / no edge".
+const INFINITY: usize = usize::MAX;
+const NONE: usize = usize::MAX;
+
+pub struct CustomizableContractionHierarchy {
+    // order[r] is the node with rank r, rank[u] the rank of node u.
+    order: Vec<usize>,
+    rank: Vec<usize>,
+
+    // The upward edges of rank r go to up_head[up_first[r]..up_first[r + 1]],
+    // sorted by rank. parent[r] is the first of them (NONE for a root).
+    up_first: Vec<usize>,
+    up_head: Vec<usize>,
+    parent: Vec<usize>,
+
+    // arc_edges[u][j] is the edge of rn.adjacent_arcs[u][j], and whether the
+    // arc goes up (from the lower to the higher rank). NONE for loops.
+    arc_edges: Vec<Vec<(usize, bool)>>,
+
+    // The metric from the last customization: the cost of each edge upwards
+    // (lower to higher rank) and downwards, and the middle node (rank) of the
+    // lower triangle that gave it, NONE if it is the cost of an arc.
+    up_cost: Vec<usize>,
+    down_cost: Vec<usize>,
+    up_middle: Vec<usize>,
+    down_middle: Vec<usize>,
+
+    // Whether arc costs changed since the last customization.
+    stale: bool,
+}
+
+impl Default for CustomizableContractionHierarchy {
+    fn default() -> Self {
+        CustomizableContractionHierarchy::new()
+    }
+}
+
+impl CustomizableContractionHierarchy {
+    // Without topology and metric yet, see ShortestPathAlgorithm::preprocess.
+    pub fn new() -> CustomizableContractionHierarchy {
+        CustomizableContractionHierarchy {
+            order: vec![], rank: vec![], up_first: vec![0], up_head: vec![], parent: vec![], arc_edges: vec![],
+            up_cost: vec![], down_cost: vec![], up_middle: vec![], down_middle: vec![], stale: false,
+        }
+    }
+
+    // The number of edges (arcs and shortcuts) of the upward graph.
+    pub fn num_edges(&self) -> usize {
+        self.up_head.len()
+    }
+
+    // The rank of each node in the nested dissection order.
+    pub fn rank(&self, u: usize) -> usize {
+        self.rank[u]
+    }
+
+    // The metric-independent phase, see above. The network may change its
+    // arc costs afterwards, but not its nodes or arcs. Must be followed by
+    // customize.
+    pub fn compute_topology(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        let n = rn.nodes.len();
+        let mut neighbors: Vec<Vec<usize>> = vec![vec![]; n];
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for arc in arcs.iter().filter(|arc| arc.idx != u) {
+                neighbors[u].push(arc.idx);
+                neighbors[arc.idx].push(u);
+            }
+        }
+        for list in neighbors.iter_mut() { list.sort_unstable(); list.dedup(); }
+
+        // Nested dissection on (x, y) in radian, with x scaled to the latitude.
+        let coordinates: Vec<(f64, f64)> = rn.nodes.iter().map(|node| (node.longitude * node.latitude.cos(), node.latitude)).collect();
+        let mut order = Vec::with_capacity(n);
+        let mut side = vec![0u8; n];
+        dissect((0..n).collect(), &neighbors, &coordinates, &mut side, &mut order);
+        let mut rank = vec![0; n];
+        for (r, &u) in order.iter().enumerate() { rank[u] = r; }
+
+        // Contract in rank order. Connecting the upward neighbors of r to its
+        // lowest one (the parent) is enough: the parent is contracted later and
+        // then connects them further up.
+        let mut up: Vec<Vec<usize>> = (0..n).map(|r| neighbors[order[r]].iter().map(|&v| rank[v]).filter(|&s| s > r).collect()).collect();
+        for r in 0..n {
+            let mut ups = std::mem::take(&mut up[r]);
+            ups.sort_unstable();
+            ups.dedup();
+            if let Some((&parent, rest)) = ups.split_first() {
+                up[parent].extend_from_slice(rest);
+            }
+            up[r] = ups;
+        }
+        self.up_first = Vec::with_capacity(n + 1);
+        self.up_first.push(0);
+        for ups in &up { self.up_first.push(self.up_first.last().unwrap() + ups.len()); }
+        self.parent = up.iter().map(|ups| ups.first().copied().unwrap_or(NONE)).collect();
+        self.up_head = up.concat();
+        self.order = order;
+        self.rank = rank;
+
+        self.arc_edges = rn.adjacent_arcs.iter().enumerate().map(|(u, arcs)| {
+            arcs.iter().map(|arc| {
+                let (ru, rv) = (self.rank[u], self.rank[arc.idx]);
+                if ru == rv { (NONE, false) } else { (self.edge(ru.min(rv), ru.max(rv)), ru < rv) }
+            }).collect()
+        }).collect();
+
+        PreprocessingStats { elapsed: now.elapsed(), size_in_bytes: self.size_in_bytes(), ..PreprocessingStats::default() }
+    }
+
+    // The metric-dependent phase for the current arc costs, see above.
+    pub fn customize(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        let m = self.up_head.len();
+        self.up_cost = vec![INFINITY; m];
+        self.down_cost = vec![INFINITY; m];
+        self.up_middle = vec![NONE; m];
+        self.down_middle = vec![NONE; m];
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for (j, arc) in arcs.iter().enumerate().filter(|(_, arc)| !arc.closed) {
+                let (e, upward) = self.arc_edges[u][j];
+                if e == NONE { continue; }
+                let cost = if upward { &mut self.up_cost[e] } else { &mut self.down_cost[e] };
+                *cost = std::cmp::min(*cost, arc.cost);
+            }
+        }
+
+        // The lower triangles r-x-y, for each r from the bottom up. The edges
+        // x-y are found by walking the (sorted) upward edges of x alongside.
+        for r in 0..self.order.len() {
+            let edges = self.up_first[r]..self.up_first[r + 1];
+            for i in edges.clone() {
+                let x = self.up_head[i];
+                let mut e = self.up_first[x];
+                for j in i + 1..edges.end {
+                    let y = self.up_head[j];
+                    while self.up_head[e] != y { e += 1; }
+                    if self.down_cost[i] != INFINITY && self.up_cost[j] != INFINITY && self.down_cost[i] + self.up_cost[j] < self.up_cost[e] {
+                        self.up_cost[e] = self.down_cost[i] + self.up_cost[j];
+                        self.up_middle[e] = r;
+                    }
+                    if self.down_cost[j] != INFINITY && self.up_cost[i] != INFINITY && self.down_cost[j] + self.up_cost[i] < self.down_cost[e] {
+                        self.down_cost[e] = self.down_cost[j] + self.up_cost[i];
+                        self.down_middle[e] = r;
+                    }
+                }
+            }
+        }
+        self.stale = false;
+        PreprocessingStats { elapsed: now.elapsed(), size_in_bytes: self.size_in_bytes(), ..PreprocessingStats::default() }
+    }
+
+    // The memory used by the topology and the metric.
+    pub fn size_in_bytes(&self) -> usize {
+        let words = self.order.len() + self.rank.len() + self.up_first.len() + self.up_head.len() + self.parent.len()
+            + self.up_cost.len() + self.down_cost.len() + self.up_middle.len() + self.down_middle.len();
+        let arc_edges: usize = self.arc_edges.iter().map(|edges| edges.len()).sum();
+        words * std::mem::size_of::<usize>() + arc_edges * std::mem::size_of::<(usize, bool)>()
+    }
+
+    // The index of the edge between the ranks lower < higher.
+    fn edge(&self, lower: usize, higher: usize) -> usize {
+        let first = self.up_first[lower];
+        first + self.up_head[first..self.up_first[lower + 1]].binary_search(&higher).unwrap()
+    }
+
+    // The shortest route from s to t, see above. The settled nodes in the
+    // workspace are those visited by the two searches.
+    pub fn compute_shortest_route(&self, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        let now = Instant::now();
+        ws.start_query();
+        let (forward_chain, forward) = self.upward_search(ws, self.rank[s], &self.up_cost);
+        let (backward_chain, backward) = self.upward_search(ws, self.rank[t], &self.down_cost);
+
+        // The common ancestors are the same part at the end of both chains.
+        let (m, n) = (forward_chain.len(), backward_chain.len());
+        let meeting = (1..=std::cmp::min(m, n))
+            .take_while(|&k| forward_chain[m - k] == backward_chain[n - k])
+            .filter(|&k| forward[m - k].0 != INFINITY && backward[n - k].0 != INFINITY)
+            .map(|k| (forward[m - k].0 + backward[n - k].0, m - k, n - k))
+            .min();
+        ws.stats_mut().elapsed = now.elapsed();
+        let (cost, i, j) = meeting?;
+
+        // The ranks on the path, with shortcuts still packed.
+        let mut ranks = vec![];
+        let mut k = i;
+        while k != NONE { ranks.push(forward_chain[k]); k = forward[k].1; }
+        ranks.reverse();
+        let mut k = backward[j].1;
+        while k != NONE { ranks.push(backward_chain[k]); k = backward[k].1; }
+
+        let mut nodes = vec![s];
+        for pair in ranks.windows(2) {
+            self.unpack(pair[0], pair[1], &mut nodes);
+        }
+        Some(Route { cost, nodes })
+    }
+
+    // The ancestors of rank r (r first, by increasing rank), and the costs
+    // from r to them (or from them to r, with the downward costs) with the
+    // position of the previous one on the way.
+    fn upward_search(&self, ws: &mut SearchWorkspace, r: usize, costs: &[usize]) -> (Vec<usize>, Vec<(usize, usize)>) {
+        let mut chain = vec![r];
+        while self.parent[*chain.last().unwrap()] != NONE {
+            chain.push(self.parent[*chain.last().unwrap()]);
+        }
+        let mut reached = vec![(INFINITY, NONE); chain.len()];
+        reached[0].0 = 0;
+        for i in 0..chain.len() {
+            let (v, d) = (chain[i], reached[i].0);
+            if d == INFINITY { continue; }
+            ws.settle(self.order[v]);
+            // The upward neighbors are ancestors, so they come later in the
+            // chain, in the same order.
+            let mut k = i;
+            let edges = self.up_first[v]..self.up_first[v + 1];
+            for (&head, &cost) in self.up_head[edges.clone()].iter().zip(&costs[edges]) {
+                while chain[k] != head { k += 1; }
+                ws.stats_mut().relaxed_arcs += 1;
+                if cost != INFINITY && d + cost < reached[k].0 { reached[k] = (d + cost, i); }
+            }
+        }
+        (chain, reached)
+    }
+
+    // Append the nodes of the edge from rank a to rank b, without a itself.
+    fn unpack(&self, a: usize, b: usize, nodes: &mut Vec<usize>) {
+        let middle = if a < b { self.up_middle[self.edge(a, b)] } else { self.down_middle[self.edge(b, a)] };
+        if middle == NONE {
+            nodes.push(self.order[b]);
+        } else {
+            self.unpack(a, middle, nodes);
+            self.unpack(middle, b, nodes);
+        }
+    }
+}
+
+// Append the given nodes to the order, separators last, see above.
+fn dissect(nodes: Vec<usize>, neighbors: &[Vec<usize>], coordinates: &[(f64, f64)], side: &mut [u8], order: &mut Vec<usize>) {
+    if nodes.len() <= LEAF_SIZE {
+        order.extend(nodes);
+        return;
+    }
+
+    // Try a few cut directions and keep the smallest separator, together with
+    // the side it was taken from.
+    let mut best: Option<(Vec<usize>, Vec<usize>, Vec<usize>)> = None;
+    for &(dx, dy) in &[(1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, -1.0)] {
+        let mut sorted = nodes.clone();
+        sorted.sort_by(|&u, &v| {
+            let key = |w: usize| dx * coordinates[w].0 + dy * coordinates[w].1;
+            key(u).total_cmp(&key(v))
+        });
+        let right = sorted.split_off(sorted.len() / 2);
+        let left = sorted;
+        for &u in &left { side[u] = 1; }
+        for &u in &right { side[u] = 2; }
+        let boundary = |part: &[usize], other: u8| -> Vec<usize> {
+            part.iter().copied().filter(|&u| neighbors[u].iter().any(|&v| side[v] == other)).collect()
+        };
+        let (left_boundary, right_boundary) = (boundary(&left, 2), boundary(&right, 1));
+        for &u in &nodes { side[u] = 0; }
+        let candidate = if left_boundary.len() <= right_boundary.len() { (left_boundary, left, right) } else { (right_boundary, right, left) };
+        if best.as_ref().is_none_or(|(separator, _, _)| candidate.0.len() < separator.len()) {
+            best = Some(candidate);
+        }
+    }
+
+    let (separator, mut part, other) = best.unwrap();
+    for &u in &separator { side[u] = 1; }
+    part.retain(|&u| side[u] == 0);
+    for &u in &separator { side[u] = 0; }
+    dissect(part, neighbors, coordinates, side, order);
+    dissect(other, neighbors, coordinates, side, order);
+    order.extend(separator);
+}
+
+impl ShortestPathAlgorithm for CustomizableContractionHierarchy {
+    fn name(&self) -> &str {
+        "cch"
+    }
+
+    // Both phases. After a change of the arc costs only, customize is enough.
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let mut stats = self.compute_topology(rn);
+        let customization = self.customize(rn);
+        stats.elapsed += customization.elapsed;
+        stats.size_in_bytes = customization.size_in_bytes;
+        stats
+    }
+
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        if self.stale {
+            let cost = Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+            return Route::from_workspace(ws, cost, t);
+        }
+        self.compute_shortest_route(ws, s, t)
+    }
+
+    // Any change needs a new customization (but not a new topology).
+    fn costs_changed(&mut self, changes: &CostChanges) {
+        if !changes.is_empty() { self.stale = true; }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
+}
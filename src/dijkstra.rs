@@ -3,125 +3,386 @@
 
 // My implementation of Lecture 2 class given by Prof. Dr. Hannah Bast <bast@informatik.uni-freiburg.de>
 // Class wiki: https://ad-wiki.informatik.uni-freiburg.de/teaching/EfficientRoutePlanningSS2012
-use std::collections::BinaryHeap;
-use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
 
 use crate::Arc;
 use crate::Node;
+use crate::RoadNetwork;
+use crate::priority_queue::{LazyBinaryHeap, PriorityQueue};
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-struct State {
-    idx: usize,
-    cost: usize,
-    f_score: usize,
+// The arc flags to consider in a search, one flag per arc in the same layout
+// as the adjacency lists (see arc_flags.rs). Arcs without their flag are skipped,
+// and so are closed arcs (see traffic.rs).
+pub type ArcFlags = [Vec<bool>];
+
+// What a query did, see SearchWorkspace::stats. Stats of several queries can
+// be added up with +=.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    // The number of nodes taken out of the priority queue (a reopened node is
+    // counted each time).
+    pub settled_nodes: usize,
+
+    // The number of arcs looked at, and of arcs skipped because of their arc flag,
+    // because they are closed or because they were pruned (see reach.rs).
+    pub relaxed_arcs: usize,
+    pub skipped_arcs: usize,
+
+    pub queue_pushes: usize,
+    pub elapsed: Duration,
 }
 
-// The priority queue depends on `Ord`.
-// Explicitly implement the trait so the queue becomes a min-heap
-// instead of a max-heap.
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // Notice that the we flip the ordering on costs.
-        // In case of a tie we compare positions - this step is necessary
-        // to make implementations of `PartialEq` and `Ord` consistent.
-        other.f_score.cmp(&self.f_score)
-            .then_with(|| self.idx.cmp(&other.idx))
+impl AddAssign for QueryStats {
+    fn add_assign(&mut self, other: QueryStats) {
+        self.settled_nodes += other.settled_nodes;
+        self.relaxed_arcs += other.relaxed_arcs;
+        self.skipped_arcs += other.skipped_arcs;
+        self.queue_pushes += other.queue_pushes;
+        self.elapsed += other.elapsed;
     }
 }
 
-// `PartialOrd` needs to be implemented as well.
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+// The per-query state of a search: tentative costs, predecessors, which nodes
+// are settled, and the priority queue. A workspace is allocated once (O(n))
+// and reused for many queries. Instead of clearing the arrays before each
+// query, every entry carries the number of the query that last wrote it, and
+// entries from older queries count as unset. So a query only pays for the
+// nodes it actually touches.
+// The priority queue is a type parameter, see priority_queue.rs.
+pub struct SearchWorkspace<Q: PriorityQueue = LazyBinaryHeap> {
+    // g_score[n] is the cost from start to n, valid if reached_in[n] == round.
+    g_score: Vec<usize>,
+
+    // The node before n on the shortest path found so far, or usize::MAX for
+    // the source. Valid if reached_in[n] == round.
+    previous_node: Vec<usize>,
+
+    // The query in which n was last reached / settled.
+    reached_in: Vec<u32>,
+    settled_in: Vec<u32>,
+
+    // The number of the current query.
+    round: u32,
+
+    // The nodes reached / settled in the current query, in that order. A node
+    // that is reopened (see reach) appears in settled_nodes more than once.
+    reached_nodes: Vec<usize>,
+    settled_nodes: Vec<usize>,
+
+    priority_queue: Q,
+
+    // The counters of the current query.
+    stats: QueryStats,
+}
+
+impl SearchWorkspace {
+    // A workspace with the default priority queue.
+    pub fn new(num_nodes: usize) -> SearchWorkspace {
+        SearchWorkspace::with_queue(num_nodes)
     }
 }
 
-pub struct Dijkstra {
-    pub consider_arc_flags: bool,
+impl<Q: PriorityQueue> SearchWorkspace<Q> {
+    // A workspace with the priority queue Q, e.g.
+    // SearchWorkspace::<RadixHeap>::with_queue(rn.nodes.len()).
+    pub fn with_queue(num_nodes: usize) -> SearchWorkspace<Q> {
+        SearchWorkspace {
+            g_score: vec![usize::MAX; num_nodes],
+            previous_node: vec![usize::MAX; num_nodes],
+            reached_in: vec![0; num_nodes],
+            settled_in: vec![0; num_nodes],
+            round: 0,
+            reached_nodes: vec![],
+            settled_nodes: vec![],
+            priority_queue: Q::new(num_nodes),
+            stats: QueryStats::default(),
+        }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.g_score.len()
+    }
+
+    // Forget the previous query. Only when the query counter wraps around do
+    // the arrays have to be cleared for real.
+    pub(crate) fn start_query(&mut self) {
+        if self.round == u32::MAX {
+            self.reached_in.iter_mut().for_each(|r| *r = 0);
+            self.settled_in.iter_mut().for_each(|r| *r = 0);
+            self.round = 0;
+        }
+        self.round += 1;
+        self.reached_nodes.clear();
+        self.settled_nodes.clear();
+        self.priority_queue.clear();
+        self.stats = QueryStats::default();
+    }
+
+    fn push(&mut self, u: usize, key: usize) {
+        self.priority_queue.push(u, key);
+        self.stats.queue_pushes += 1;
+    }
+
+    // NOTE: a settled node is only reached again if the heuristic is not
+    // consistent (see compute_shortest_path_with_workspace), it is then reopened.
+    fn reach(&mut self, u: usize, cost: usize, previous_node: usize) {
+        if self.reached_in[u] != self.round {
+            self.reached_in[u] = self.round;
+            self.reached_nodes.push(u);
+        }
+        if self.settled_in[u] == self.round {
+            self.settled_in[u] = 0;
+        }
+        self.g_score[u] = cost;
+        self.previous_node[u] = previous_node;
+    }
+
+    pub(crate) fn settle(&mut self, u: usize) {
+        self.settled_in[u] = self.round;
+        self.settled_nodes.push(u);
+        self.stats.settled_nodes += 1;
+    }
+
+    // The cost of the best path to u found by the last query, if u was reached.
+    // This is the shortest path cost if u is settled.
+    pub fn distance(&self, u: usize) -> Option<usize> {
+        if self.reached_in[u] == self.round { Some(self.g_score[u]) } else { None }
+    }
+
+    // The node before u on the path found by the last query (None for the
+    // source and for nodes that were not reached).
+    pub fn previous_node(&self, u: usize) -> Option<usize> {
+        if self.reached_in[u] == self.round && self.previous_node[u] != usize::MAX { Some(self.previous_node[u]) } else { None }
+    }
+
+    pub fn is_settled(&self, u: usize) -> bool {
+        self.settled_in[u] == self.round
+    }
+
+    pub fn reached_nodes(&self) -> &[usize] {
+        &self.reached_nodes
+    }
+
+    pub fn settled_nodes(&self) -> &[usize] {
+        &self.settled_nodes
+    }
+
+    // What the last query did, and how long it took.
+    pub fn stats(&self) -> QueryStats {
+        self.stats
+    }
+
+    // For algorithms that do their own search and only use the workspace for
+    // its counters, see start_query and settle.
+    pub(crate) fn stats_mut(&mut self) -> &mut QueryStats {
+        &mut self.stats
+    }
+
+    // The nodes on the path from the source of the last query to t, or None if
+    // t was not reached.
+    pub fn path(&self, t: usize) -> Option<Vec<usize>> {
+        self.distance(t)?;
+        let mut path = vec![t];
+        while let Some(u) = self.previous_node(*path.last().unwrap()) {
+            path.push(u);
+        }
+        path.reverse();
+        Some(path)
+    }
 }
 
-impl Dijkstra {
+pub struct Dijkstra<'a> {
+    pub arc_flags: Option<&'a ArcFlags>,
+}
+
+impl Dijkstra<'_> {
     // Compute the shortest paths from the given source to the given target node.
     // Returns the cost of the shortest path.
     // NOTE: If called with target node -1, Dijkstra is run until all nodes
     // reachable from the source are settled.
-    //pub fn compute_shortest_path(rn: &RoadNetwork, h: Option<&Vec<usize>>, source_node_id: usize, target_node_id: Option<usize>) -> (Option<usize>, HashSet<usize>, Option<HashMap<usize, usize>>) {
-    pub fn compute_shortest_path<F>(&self, nodes: &Vec<Node>, arcs: &mut Vec<Vec<Arc>>, s: usize, t: Option<usize>, h: F) -> (Option<usize>, HashSet<usize>, HashMap<usize, usize>, Vec<usize>) where 
+    // NOTE: this allocates a new SearchWorkspace, use
+    // compute_shortest_path_with_workspace for repeated queries.
+    pub fn compute_shortest_path<F>(&self, nodes: &[Node], arcs: &[Vec<Arc>], s: usize, t: Option<usize>, h: F) -> (Option<usize>, HashSet<usize>, HashMap<usize, usize>, Vec<usize>) where 
     F: Fn(&usize, &usize) -> usize
     {
+        let mut ws = SearchWorkspace::new(nodes.len());
+        let cost = self.compute_shortest_path_with_workspace(&mut ws, arcs, s, t, h);
 
-        assert!(s < nodes.len()-1);
+        let visited: HashSet<usize> = ws.settled_nodes().iter().copied().collect();
+        let previous_node: HashMap<usize, usize> = ws.reached_nodes().iter()
+            .filter_map(|&u| ws.previous_node(u).map(|p| (u, p)))
+            .collect();
+        let g_score = (0..nodes.len()).map(|u| ws.distance(u).unwrap_or(usize::MAX)).collect();
+        (cost, visited, previous_node, g_score)
+    }
+
+    // Same as compute_shortest_path, but the search state is kept in the given
+    // workspace (see SearchWorkspace), where the caller can read the distances,
+    // the settled nodes and the path after the query.
+    // NOTE: the heuristic h must be admissible. If it is also consistent (as
+    // the straight-line and the exact landmark heuristic), every node is
+    // settled once. Otherwise (e.g. 16-bit landmark distances) a settled node
+    // whose cost still improves is reopened, so the result stays exact.
+    pub fn compute_shortest_path_with_workspace<Q, F>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, t: Option<usize>, h: F) -> Option<usize> where
+    Q: PriorityQueue,
+    F: Fn(&usize, &usize) -> usize
+    {
         if let Some(t) = t {
-            assert!(t < nodes.len()-1);
+            assert!(t < ws.num_nodes());
         }
+        self.search(ws, arcs, s, t, h, |u, j, _| arcs[u][j].cost, |u, _| Some(u) == t)
+    }
 
+    // Same as compute_shortest_path_with_workspace without a heuristic, but
+    // the cost of arc j of node u, when u is reached with cost g, is
+    // arc_cost(u, j, g). This is for time-dependent costs, where g is the time
+    // since departure (see time_dependent.rs).
+    // NOTE: the result is only exact if leaving later never means arriving
+    // earlier, i.e. g + arc_cost(u, j, g) does not decrease with g (FIFO).
+    pub fn compute_shortest_path_with_arc_costs<Q, A>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, t: usize, arc_cost: A) -> Option<usize> where
+    Q: PriorityQueue,
+    A: Fn(usize, usize, usize) -> usize
+    {
+        assert!(t < ws.num_nodes());
+        self.search(ws, arcs, s, Some(t), |_,_| 0, arc_cost, |u, _| u == t)
+    }
 
-        let mut visited: HashSet<usize> = HashSet::new();
+    // Same as compute_shortest_path_with_workspace towards t, but an arc into
+    // v is not relaxed if prune(v, g) returns true for the cost g that v would
+    // get over it. This is for reach-based pruning (see reach.rs).
+    pub fn compute_shortest_path_with_pruning<Q, F, P>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, t: usize, h: F, prune: P) -> Option<usize> where
+    Q: PriorityQueue,
+    F: Fn(&usize, &usize) -> usize,
+    P: Fn(usize, usize) -> bool
+    {
+        assert!(t < ws.num_nodes());
+        self.search(ws, arcs, s, Some(t), h, |u, j, g| {
+            let arc = &arcs[u][j];
+            if prune(arc.idx, g + arc.cost) { usize::MAX } else { arc.cost }
+        }, |u, _| u == t)
+    }
 
-        // g_score[n] is the cost from start to n
-        let mut g_score = vec![usize::MAX; nodes.len() as usize];
+    // Compute the costs from s to all the given targets, in the same order (None
+    // for targets that are not reachable). This is one Dijkstra run that stops
+    // as soon as all targets are settled, see distance_matrix.rs.
+    pub fn compute_one_to_many_with_workspace<Q: PriorityQueue>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, targets: &[usize]) -> Vec<Option<usize>> {
+        let mut remaining: HashSet<usize> = targets.iter().copied().collect();
+        if !remaining.is_empty() {
+            self.search(ws, arcs, s, None, |_,_| 0, |u, j, _| arcs[u][j].cost, |u, _| { remaining.remove(&u); remaining.is_empty() });
+        }
+        targets.iter().map(|&t| if ws.is_settled(t) { ws.distance(t) } else { None }).collect()
+    }
 
-        // f_score[n] is the cost from start to n + a cost estimate of n to target
-        //let mut f_score = vec![usize::MAX; nodes.len() as usize];
+    // Settle all nodes with cost at most budget from s (one-to-all Dijkstra
+    // that stops at the budget, see isochrone.rs). Afterwards these are the
+    // settled nodes of the workspace, plus the first node beyond the budget,
+    // if there is one.
+    pub fn compute_within_budget_with_workspace<Q: PriorityQueue>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, budget: usize) {
+        self.search(ws, arcs, s, None, |_,_| 0, |u, j, _| arcs[u][j].cost, |_, cost| cost > budget);
+    }
 
-        let mut previous_node= HashMap::new();
+    // Same as compute_within_budget_with_workspace, with the arc costs given by
+    // arc_cost as in compute_shortest_path_with_arc_costs.
+    pub fn compute_within_budget_with_arc_costs<Q, A>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, budget: usize, arc_cost: A) where
+    Q: PriorityQueue,
+    A: Fn(usize, usize, usize) -> usize
+    {
+        self.search(ws, arcs, s, None, |_,_| 0, arc_cost, |_, cost| cost > budget);
+    }
 
-        let mut priority_queue = BinaryHeap::new();
+    // The search loop of the functions above: A* towards t with the heuristic
+    // h (plain Dijkstra if t is None) and the arc costs given by arc_cost (see
+    // compute_shortest_path_with_arc_costs, an arc with cost usize::MAX is
+    // skipped), until is_done returns true for a settled node and its cost.
+    // Returns that cost, or None if the queue ran empty.
+    // NOTE: the queue keeps old entries of a node when it is pushed again with
+    // a smaller key. The node is settled by the first of its entries that is
+    // popped, with its current g_score, and later entries find it settled and
+    // are skipped. Settled nodes are not final: if an arc still improves the
+    // cost of a settled node, reach reopens it and it is pushed again. With a
+    // consistent heuristic (none, straight line, exact landmark distances)
+    // that never happens and every node is settled once. With an admissible
+    // but inconsistent one, such as the 16-bit landmark distances with a
+    // scale > 1 (see astar_landmark_triangle_inequality.rs), it keeps the
+    // result exact, see test_landmarks_quantized_with_scale.
+    #[allow(clippy::too_many_arguments)]
+    fn search<Q, F, A, D>(&self, ws: &mut SearchWorkspace<Q>, arcs: &[Vec<Arc>], s: usize, t: Option<usize>, h: F, arc_cost: A, mut is_done: D) -> Option<usize> where
+    Q: PriorityQueue,
+    F: Fn(&usize, &usize) -> usize,
+    A: Fn(usize, usize, usize) -> usize,
+    D: FnMut(usize, usize) -> bool
+    {
+        assert!(s < ws.num_nodes());
+
+        ws.start_query();
+        let now = Instant::now();
 
         // Set initial g_score to 0 for the source node
-        g_score[s] = 0;
-        let mut current_node = State {idx: s, cost: 0, f_score: 0};
+        ws.reach(s, 0, usize::MAX);
+        let mut f_score = 0;
         if let Some(t) = t {
-            //current_node.f_score = nodes[s].cost(&nodes[t], crate::osm::MAX_SPEED);
-            current_node.f_score = h(&s, &t);
+            //f_score = nodes[s].cost(&nodes[t], crate::osm::MAX_SPEED);
+            f_score = h(&s, &t);
         }
-        priority_queue.push(current_node);
-
+        ws.push(s, f_score);
 
-        let mut number_of_arcs_skipped = 0;
-
-        while let Some(State {idx, cost, f_score: _}) = priority_queue.pop() {
+        while let Some((idx, _)) = ws.priority_queue.pop() {
 
             // println!("Processing: {}, idx: {}, cost: {}", head_node_id, idx, cost);
-            visited.insert(idx);
-
-            if cost > g_score[idx] { continue; }
+            // Old entry of a node that was pushed again with a smaller key.
+            if ws.is_settled(idx) { continue; }
+            ws.settle(idx);
+            let cost = ws.g_score[idx];
 
-            if Some(idx) == t {
-                if self.consider_arc_flags == true  {
-                    println!("Number of arcs skipped: {}", number_of_arcs_skipped);
-                    
-                }
-                return (Some(g_score[idx]), visited, previous_node, g_score);
+            if is_done(idx, cost) {
+                ws.stats.elapsed = now.elapsed();
+                return Some(cost);
             }
 
-            for arc in arcs[idx].iter_mut() {
-                if visited.contains(&arc.idx) { continue; }
-                if self.consider_arc_flags == true && arc.arc_flag == false { number_of_arcs_skipped += 1; continue; }
-                // if self.consider_arc_flags == false && arc.arc_flag == true { continue; }
+            for (j, arc) in arcs[idx].iter().enumerate() {
+                if arc.closed { ws.stats.skipped_arcs += 1; continue; }
+                if let Some(arc_flags) = self.arc_flags {
+                    if !arc_flags[idx][j] { ws.stats.skipped_arcs += 1; continue; }
+                }
+                let arc_cost = arc_cost(idx, j, cost);
+                if arc_cost == usize::MAX { ws.stats.skipped_arcs += 1; continue; }
+                ws.stats.relaxed_arcs += 1;
 
-                if arc.cost + cost < g_score[arc.idx] {
-                    g_score[arc.idx] = arc.cost + cost;
+                let new_cost = arc_cost + cost;
+                if ws.distance(arc.idx).is_none_or(|g| new_cost < g) {
+                    ws.reach(arc.idx, new_cost, idx);
                     let mut h_value = 0;
                     if let Some(t) = t  {
                         h_value = h(&arc.idx, &t);
                     }
-                    priority_queue.push(State{idx: arc.idx, cost: arc.cost+cost, f_score: g_score[arc.idx] + h_value});
-                    previous_node.insert(arc.idx as usize, idx);
-                    // arc.arc_flag=true;
+                    ws.push(arc.idx, new_cost + h_value);
                 }
             }
             //println!("priority_queue: {:?}", priority_queue);
         }
 
-        if self.consider_arc_flags == true  {
-            println!("Number of arcs skipped: {}", number_of_arcs_skipped);
-
-        }
+        ws.stats.elapsed = now.elapsed();
         //println!("Target not reached: {}/{:?}", source_node_id, target_node_id);
-        (None, visited, previous_node, g_score)
+        None
+    }
+}
+
+impl ShortestPathAlgorithm for Dijkstra<'_> {
+    fn name(&self) -> &str {
+        "dijkstra"
+    }
+
+    // Plain Dijkstra has no preprocessing.
+    fn preprocess(&mut self, _rn: &RoadNetwork) -> PreprocessingStats {
+        PreprocessingStats::default()
+    }
+
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        let cost = self.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+        Route::from_workspace(ws, cost, t)
     }
-  
 }
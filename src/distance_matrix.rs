@@ -0,0 +1,41 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Travel time matrices between many sources and many targets (e.g. depots and
+// customers). Instead of one point-to-point query per pair, we run one Dijkstra
+// per source, which stops as soon as all targets are settled. The runs for the
+// different sources are independent and distributed over several threads.
+//
+// NOTE: the bucket-based algorithm (backward searches from the targets that
+// leave entries in buckets, then forward searches from the sources that scan
+// them) only pays off with small search spaces, as with contraction
+// hierarchies. With plain Dijkstra the backward searches would each cover most
+// of the graph.
+
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::parallel;
+
+// matrix[i][j] is the cost from sources[i] to targets[j], or None if there is
+// no path.
+pub type DistanceMatrix = Vec<Vec<Option<usize>>>;
+
+// The costs from s to each of the targets, see
+// Dijkstra::compute_one_to_many_with_workspace.
+pub fn one_to_many(rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, targets: &[usize]) -> Vec<Option<usize>> {
+    let dijkstra = Dijkstra { arc_flags: None };
+    dijkstra.compute_one_to_many_with_workspace(ws, &rn.adjacent_arcs, s, targets)
+}
+
+// The cost matrix from all sources to all targets, computed on num_threads
+// threads with one SearchWorkspace each.
+pub fn many_to_many(rn: &RoadNetwork, sources: &[usize], targets: &[usize], num_threads: usize) -> DistanceMatrix {
+    let mut matrix = vec![vec![]; sources.len()];
+    parallel::run_jobs(
+        num_threads,
+        sources.len(),
+        || SearchWorkspace::new(rn.nodes.len()),
+        |ws, i| one_to_many(rn, ws, sources[i], targets),
+        |i, row| matrix[i] = row);
+    matrix
+}
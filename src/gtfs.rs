@@ -0,0 +1,394 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Reading public transit timetables in the GTFS format
+// (https://gtfs.org/reference/static), as published e.g. for Baden-Württemberg.
+// A feed is a directory with CSV files, we read:
+//
+//   stops.txt           stop_id, stop_name, stop_lat, stop_lon
+//   trips.txt           trip_id, route_id, service_id
+//   stop_times.txt      trip_id, arrival_time, departure_time, stop_id, stop_sequence
+//   calendar.txt        service_id, monday, ..., sunday, start_date, end_date
+//   calendar_dates.txt  service_id, date, exception_type
+//   transfers.txt       from_stop_id, to_stop_id, transfer_type, min_transfer_time (optional)
+//
+// One of calendar.txt and calendar_dates.txt may be missing, some feeds list
+// every date a service runs in calendar_dates.txt only. Other files and
+// columns are ignored. Times are HH:MM:SS and may go beyond
+// 24:00:00 for trips that run past midnight, we keep them as seconds since
+// midnight of the day the trip starts (its service date). Dates are YYYYMMDD,
+// we keep them as numbers in that format. Stop times without times (stops
+// that are not timepoints) are left out. Fields in quotes may contain commas,
+// but no line breaks.
+//
+// transfers.txt is optional too. Only transfers with a minimum time
+// (transfer_type 2) are read, and only they need the min_transfer_time column.
+// From a stop to itself they give the time needed to change trips there.
+// Between two stops they are footpaths, more footpaths can be added with
+// add_footpaths.
+//
+// Routing on the timetable is in transit.rs, connection_scan.rs and raptor.rs.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use crate::{Node, RoadNetwork};
+use crate::time_dependent::DAY;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stop {
+    pub id: String,
+    pub name: String,
+
+    // In radian, as in Node.
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// A trip stops at a stop, arriving and departing at the given times.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StopTime {
+    // The index of the stop in Timetable::stops.
+    pub stop: usize,
+    pub arrival: usize,
+    pub departure: usize,
+}
+
+// A walk from one stop to another (the index in Timetable::stops), taking the
+// given number of seconds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Footpath {
+    pub to: usize,
+    pub duration: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trip {
+    pub id: String,
+    pub route_id: String,
+    pub service_id: String,
+
+    // Ordered by stop_sequence.
+    pub stop_times: Vec<StopTime>,
+}
+
+// A trip as it runs on some date, see Timetable::trips_on: the stop times of
+// Timetable::trips[trip] from position first on, with shift seconds taken off
+// their times.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TripOnDate {
+    pub trip: usize,
+    pub first: usize,
+    pub shift: usize,
+}
+
+// The days a service runs: the weekdays between two dates, plus and minus
+// single dates from calendar_dates.txt.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Service {
+    // Monday first.
+    weekdays: [bool; 7],
+    start_date: u32,
+    end_date: u32,
+    added: HashSet<u32>,
+    removed: HashSet<u32>,
+}
+
+impl Service {
+    fn runs_on(&self, date: u32) -> bool {
+        if self.removed.contains(&date) { return false; }
+        self.added.contains(&date) || (self.start_date <= date && date <= self.end_date && self.weekdays[weekday(date)])
+    }
+}
+
+// The contents of the files of a feed, see above. calendar, calendar_dates
+// and transfers are empty if the feed has no such file.
+#[derive(Clone, Debug, Default)]
+pub struct GtfsFeed {
+    pub stops: String,
+    pub trips: String,
+    pub stop_times: String,
+    pub calendar: String,
+    pub calendar_dates: String,
+    pub transfers: String,
+}
+
+impl GtfsFeed {
+    pub fn read_dir(dir: &str) -> Result<GtfsFeed, Box<dyn Error>> {
+        let read = |name: &str| fs::read_to_string(Path::new(dir).join(name)).map_err(|e| format!("{}: {}", name, e));
+        let read_optional = |name: &str| if Path::new(dir).join(name).exists() { read(name) } else { Ok(String::new()) };
+        Ok(GtfsFeed {
+            stops: read("stops.txt")?,
+            trips: read("trips.txt")?,
+            stop_times: read("stop_times.txt")?,
+            // Needed unless calendar_dates.txt gives the dates.
+            calendar: if Path::new(dir).join("calendar_dates.txt").exists() { read_optional("calendar.txt")? } else { read("calendar.txt")? },
+            calendar_dates: read_optional("calendar_dates.txt")?,
+            transfers: read_optional("transfers.txt")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Timetable {
+    pub stops: Vec<Stop>,
+    pub trips: Vec<Trip>,
+
+    // For each stop, the time needed to change from one trip to another one
+    // there, and the footpaths to other stops.
+    pub min_transfer_times: Vec<usize>,
+    pub footpaths: Vec<Vec<Footpath>>,
+
+    services: HashMap<String, Service>,
+    stop_index: HashMap<String, usize>,
+}
+
+impl Timetable {
+    pub fn read_gtfs_dir(dir: &str) -> Result<Timetable, Box<dyn Error>> {
+        Timetable::from_feed(&GtfsFeed::read_dir(dir)?)
+    }
+
+    pub fn from_feed(feed: &GtfsFeed) -> Result<Timetable, Box<dyn Error>> {
+        let mut timetable = Timetable::default();
+
+        for_each_record("stops.txt", &feed.stops, &["stop_id", "stop_name", "stop_lat", "stop_lon"], |fields| {
+            // Entrances and the like have no coordinates, no trip stops there.
+            if fields[2].is_empty() { return Ok(()); }
+            let (lat, lon): (f64, f64) = (fields[2].parse()?, fields[3].parse()?);
+            timetable.stop_index.insert(fields[0].clone(), timetable.stops.len());
+            timetable.stops.push(Stop { id: fields[0].clone(), name: fields[1].clone(),
+                latitude: lat.to_radians(), longitude: lon.to_radians() });
+            Ok(())
+        })?;
+
+        let mut trip_index = HashMap::new();
+        for_each_record("trips.txt", &feed.trips, &["trip_id", "route_id", "service_id"], |fields| {
+            trip_index.insert(fields[0].clone(), timetable.trips.len());
+            timetable.trips.push(Trip { id: fields[0].clone(), route_id: fields[1].clone(), service_id: fields[2].clone(), stop_times: vec![] });
+            Ok(())
+        })?;
+
+        let mut sequences: Vec<Vec<usize>> = vec![vec![]; timetable.trips.len()];
+        for_each_record("stop_times.txt", &feed.stop_times, &["trip_id", "arrival_time", "departure_time", "stop_id", "stop_sequence"], |fields| {
+            if fields[1].is_empty() && fields[2].is_empty() { return Ok(()); }
+            let trip = *trip_index.get(&fields[0]).ok_or(format!("unknown trip {}", fields[0]))?;
+            let stop = timetable.stop_index(&fields[3]).ok_or(format!("unknown stop {}", fields[3]))?;
+            // One of the two times may be missing, then both are the same.
+            let arrival = parse_time(if fields[1].is_empty() { &fields[2] } else { &fields[1] })?;
+            let departure = parse_time(if fields[2].is_empty() { &fields[1] } else { &fields[2] })?;
+            timetable.trips[trip].stop_times.push(StopTime { stop, arrival, departure });
+            sequences[trip].push(fields[4].parse()?);
+            Ok(())
+        })?;
+        for (trip, sequence) in timetable.trips.iter_mut().zip(sequences) {
+            let mut stop_times: Vec<(usize, StopTime)> = sequence.into_iter().zip(trip.stop_times.drain(..)).collect();
+            stop_times.sort_unstable_by_key(|&(sequence, _)| sequence);
+            trip.stop_times = stop_times.into_iter().map(|(_, stop_time)| stop_time).collect();
+        }
+
+        let days = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+        let mut columns = vec!["service_id", "start_date", "end_date"];
+        columns.extend_from_slice(&days);
+        if !feed.calendar.is_empty() {
+            for_each_record("calendar.txt", &feed.calendar, &columns, |fields| {
+                let service = timetable.services.entry(fields[0].clone()).or_default();
+                service.start_date = parse_date(&fields[1])?;
+                service.end_date = parse_date(&fields[2])?;
+                for (day, field) in fields[3..].iter().enumerate() {
+                    service.weekdays[day] = field == "1";
+                }
+                Ok(())
+            })?;
+        }
+        if !feed.calendar_dates.is_empty() {
+            for_each_record("calendar_dates.txt", &feed.calendar_dates, &["service_id", "date", "exception_type"], |fields| {
+                let service = timetable.services.entry(fields[0].clone()).or_default();
+                let date = parse_date(&fields[1])?;
+                match fields[2].as_str() {
+                    "1" => service.added.insert(date),
+                    "2" => service.removed.insert(date),
+                    other => return Err(format!("unknown exception_type {}", other).into()),
+                };
+                Ok(())
+            })?;
+        }
+
+        timetable.min_transfer_times = vec![0; timetable.stops.len()];
+        timetable.footpaths = vec![vec![]; timetable.stops.len()];
+        if !feed.transfers.is_empty() {
+            for_each_record("transfers.txt", &feed.transfers, &["from_stop_id", "to_stop_id", "transfer_type", "min_transfer_time?"], |fields| {
+                // Only transfers of type 2 have a minimum transfer time.
+                if fields[2] != "2" { return Ok(()); }
+                let from = timetable.stop_index(&fields[0]).ok_or(format!("unknown stop {}", fields[0]))?;
+                let to = timetable.stop_index(&fields[1]).ok_or(format!("unknown stop {}", fields[1]))?;
+                if fields[3].is_empty() { return Err("transfer_type 2 without min_transfer_time".into()); }
+                let duration = fields[3].parse()?;
+                if from == to { timetable.min_transfer_times[from] = duration; } else { timetable.add_footpath(from, to, duration); }
+                Ok(())
+            })?;
+        }
+
+        Ok(timetable)
+    }
+
+    // The index of the stop with the given GTFS stop_id.
+    pub fn stop_index(&self, id: &str) -> Option<usize> {
+        self.stop_index.get(id).copied()
+    }
+
+    // The stops as nodes of a network without arcs (OSM ids are the stop
+    // indices), e.g. for Route::length of a transit route.
+    pub fn stop_network(&self) -> RoadNetwork {
+        let mut rn = RoadNetwork::new();
+        for (i, stop) in self.stops.iter().enumerate() {
+            rn.add_node(Node { osm_id: i, latitude: stop.latitude, longitude: stop.longitude });
+        }
+        rn
+    }
+
+    // Add a footpath from u to v, or make the one there shorter.
+    pub fn add_footpath(&mut self, u: usize, v: usize, duration: usize) {
+        match self.footpaths[u].iter_mut().find(|footpath| footpath.to == v) {
+            Some(footpath) => footpath.duration = footpath.duration.min(duration),
+            None => self.footpaths[u].push(Footpath { to: v, duration }),
+        }
+    }
+
+    // Add footpaths in both directions between all stops at most max_distance
+    // meters apart (straight line), walking at the given speed in km/h.
+    pub fn add_footpaths(&mut self, max_distance: f64, walking_speed: usize) {
+        let nodes = self.stop_network().nodes;
+        let mut by_latitude: Vec<usize> = (0..nodes.len()).collect();
+        by_latitude.sort_unstable_by(|&u, &v| nodes[u].latitude.total_cmp(&nodes[v].latitude));
+        // Only stops within max_distance in latitude can be close enough.
+        let max_latitude_difference = max_distance / (6371.0 * 1000.0);
+        for (i, &u) in by_latitude.iter().enumerate() {
+            for &v in by_latitude[i + 1..].iter().take_while(|&&v| nodes[v].latitude - nodes[u].latitude <= max_latitude_difference) {
+                if nodes[u].distance(&nodes[v]) <= max_distance {
+                    let duration = nodes[u].cost(&nodes[v], walking_speed);
+                    self.add_footpath(u, v, duration);
+                    self.add_footpath(v, u, duration);
+                }
+            }
+        }
+    }
+
+    // Whether the given trip runs on the given date (YYYYMMDD).
+    pub fn runs_on(&self, trip: usize, date: u32) -> bool {
+        self.services.get(&self.trips[trip].service_id).is_some_and(|service| service.runs_on(date))
+    }
+
+    // The trips of the given date (YYYYMMDD), with their times in seconds
+    // since midnight of that date: the trips that run on it, and the part
+    // after midnight of the trips that ran the day before (from the first stop
+    // they depart from at or after 24:00:00 of their service date on, shifted
+    // by a day). Trips with less than two stop times that day are left out.
+    pub fn trips_on(&self, date: u32) -> Vec<TripOnDate> {
+        let yesterday = previous_date(date);
+        let mut trips = vec![];
+        for (trip, t) in self.trips.iter().enumerate() {
+            if self.runs_on(trip, date) {
+                trips.push(TripOnDate { trip, first: 0, shift: 0 });
+            }
+            if self.runs_on(trip, yesterday) {
+                let first = t.stop_times.iter().position(|stop_time| stop_time.departure >= DAY).unwrap_or(t.stop_times.len());
+                trips.push(TripOnDate { trip, first, shift: DAY });
+            }
+        }
+        trips.retain(|t| self.trips[t.trip].stop_times.len() >= t.first + 2);
+        trips
+    }
+
+    // The stop time at position i of a trip on a date. The arrival at the
+    // first stop after midnight may have been the day before, it is 0 then.
+    pub fn stop_time_on(&self, trip: TripOnDate, i: usize) -> StopTime {
+        let stop_time = self.trips[trip.trip].stop_times[trip.first + i];
+        StopTime { stop: stop_time.stop, arrival: stop_time.arrival.saturating_sub(trip.shift), departure: stop_time.departure - trip.shift }
+    }
+
+    pub fn stop_times_on(&self, trip: TripOnDate) -> Vec<StopTime> {
+        (0..self.trips[trip.trip].stop_times.len() - trip.first).map(|i| self.stop_time_on(trip, i)).collect()
+    }
+}
+
+// Seconds since midnight for HH:MM:SS (or H:MM:SS).
+pub fn parse_time(time: &str) -> Result<usize, Box<dyn Error>> {
+    let parts: Vec<&str> = time.trim().split(':').collect();
+    match parts.as_slice() {
+        [h, m, s] => Ok(h.parse::<usize>()? * 3600 + m.parse::<usize>()? * 60 + s.parse::<usize>()?),
+        _ => Err(format!("cannot parse time \"{}\"", time).into()),
+    }
+}
+
+// The date YYYYMMDD as a number, e.g. 20211024.
+pub fn parse_date(date: &str) -> Result<u32, Box<dyn Error>> {
+    let date = date.trim();
+    if date.len() != 8 { return Err(format!("cannot parse date \"{}\"", date).into()); }
+    Ok(date.parse()?)
+}
+
+// The day of the week of a date YYYYMMDD, 0 for Monday.
+pub fn weekday(date: u32) -> usize {
+    // 1970-01-01 was a Thursday.
+    (days_since_epoch(date) + 3).rem_euclid(7) as usize
+}
+
+// The date YYYYMMDD before the given one, e.g. 20211031 for 20211101.
+pub fn previous_date(date: u32) -> u32 {
+    if date % 100 > 1 { return date - 1; }
+    let (y, m) = (date / 10000, (date / 100) % 100);
+    let first_of_previous_month = if m == 1 { (y - 1) * 10000 + 1201 } else { date - 100 };
+    first_of_previous_month + (days_since_epoch(date) - days_since_epoch(first_of_previous_month)) as u32 - 1
+}
+
+// Days since 1970-01-01 of a date YYYYMMDD, after H. Hinnant's days_from_civil.
+fn days_since_epoch(date: u32) -> i64 {
+    let (y, m, d) = ((date / 10000) as i64, ((date / 100) % 100) as i64, (date % 100) as i64);
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Call f with the given columns of each record of a CSV file with a header
+// line, in that order. A column ending in "?" is optional, its fields are
+// empty if the file does not have it. Errors get the file name and line number.
+fn for_each_record<F>(file: &str, text: &str, columns: &[&str], mut f: F) -> Result<(), Box<dyn Error>> where
+F: FnMut(&[String]) -> Result<(), Box<dyn Error>>
+{
+    let mut lines = text.trim_start_matches('\u{feff}').lines();
+    let header = split_fields(lines.next().unwrap_or(""));
+    let positions = columns.iter()
+        .map(|column| match column.strip_suffix('?') {
+            Some(column) => Ok(header.iter().position(|name| name.trim() == column)),
+            None => header.iter().position(|name| name.trim() == *column).map(Some).ok_or(format!("{}: no column {}", file, column)),
+        })
+        .collect::<Result<Vec<Option<usize>>, String>>()?;
+    for (i, line) in lines.enumerate() {
+        if line.trim().is_empty() { continue; }
+        let fields = split_fields(line);
+        let record: Vec<String> = positions.iter().map(|&p| p.and_then(|p| fields.get(p)).map_or("", |field| field.trim()).to_string()).collect();
+        f(&record).map_err(|e| format!("{} line {}: {}", file, i + 2, e))?;
+    }
+    Ok(())
+}
+
+// The fields of a CSV line. Fields in double quotes may contain commas, and
+// "" stands for a quote.
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => { chars.next(); fields.last_mut().unwrap().push('"'); },
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
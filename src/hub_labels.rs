@@ -0,0 +1,292 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Hub labels: every node u stores a forward label, (hub, cost from u to the
+// hub) pairs, and a backward label, (hub, cost from the hub to u) pairs. The
+// labels are built such that every pair s, t has a hub on a shortest path
+// from s to t in the forward label of s and the backward label of t, so the
+// cost from s to t is the minimum over the common hubs, found by merging the
+// two labels (sorted by hub). No search at all.
+//
+// The labels are built by pruned labeling (T. Akiba, Y. Iwata, Y. Yoshida:
+// "Fast Exact Shortest-Path Distance Queries on Large Networks by Pruned
+// Landmark Labeling", 2013): the nodes are taken as hubs one after the other,
+// most important first, and each hub h runs a Dijkstra in both directions that
+// adds h to the labels of the nodes it settles, except where the labels built
+// so far already give the cost. There it does not go on either. As order we
+// use the one of the customizable contraction hierarchy (nested dissection,
+// see customizable_contraction_hierarchy.rs), from the top: nodes of large
+// separators are on many shortest paths, so the searches of later hubs are
+// pruned early and the labels stay small.
+//
+// Hubs are numbered by their position in that order, so the labels come out
+// sorted. They are stored in flat arrays with 32-bit hubs and costs.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::convert::TryFrom;
+use std::time::Instant;
+use crate::RoadNetwork;
+use crate::customizable_contraction_hierarchy::CustomizableContractionHierarchy;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::distance_matrix::DistanceMatrix;
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
+use crate::traffic::CostChanges;
+
+// The labels of all nodes in one direction: the label of u is
+// hubs[first[u]..first[u + 1]] with the costs at the same positions.
+#[derive(Default)]
+struct Labels {
+    first: Vec<usize>,
+    hubs: Vec<u32>,
+    costs: Vec<u32>,
+}
+
+impl Labels {
+    fn from_lists(lists: Vec<Vec<(u32, u32)>>) -> Labels {
+        let mut first = Vec::with_capacity(lists.len() + 1);
+        first.push(0);
+        for list in &lists { first.push(first.last().unwrap() + list.len()); }
+        let (hubs, costs) = lists.into_iter().flatten().unzip();
+        Labels { first, hubs, costs }
+    }
+
+    fn label(&self, u: usize) -> (&[u32], &[u32]) {
+        let range = self.first[u]..self.first[u + 1];
+        (&self.hubs[range.clone()], &self.costs[range])
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.first.len() * std::mem::size_of::<usize>() + (self.hubs.len() + self.costs.len()) * std::mem::size_of::<u32>()
+    }
+}
+
+// How big the labels are, see HubLabels::report.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LabelReport {
+    pub num_nodes: usize,
+
+    // Over both directions: the number of (hub, cost) pairs, per node on
+    // average and at most.
+    pub num_entries: usize,
+    pub average_label_size: f64,
+    pub max_label_size: usize,
+
+    pub size_in_bytes: usize,
+}
+
+#[derive(Default)]
+pub struct HubLabels {
+    forward: Labels,
+    backward: Labels,
+
+    // Whether arc costs changed since the labels were built.
+    stale: bool,
+}
+
+impl HubLabels {
+    // Without labels yet, see ShortestPathAlgorithm::preprocess.
+    pub fn new() -> HubLabels {
+        HubLabels::default()
+    }
+
+    // Build the labels with the hubs in the given order (all nodes, most
+    // important first), see above.
+    pub fn build_with_order(&mut self, rn: &RoadNetwork, order: &[usize]) -> PreprocessingStats {
+        let now = Instant::now();
+        let mut stats = PreprocessingStats::default();
+        let n = rn.nodes.len();
+        let mut reverse_arcs: Vec<Vec<(usize, usize)>> = vec![vec![]; n];
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for arc in arcs.iter().filter(|arc| !arc.closed) {
+                reverse_arcs[arc.idx].push((u, arc.cost));
+            }
+        }
+        let forward_arcs: Vec<Vec<(usize, usize)>> = rn.adjacent_arcs.iter()
+            .map(|arcs| arcs.iter().filter(|arc| !arc.closed).map(|arc| (arc.idx, arc.cost)).collect())
+            .collect();
+
+        let mut forward: Vec<Vec<(u32, u32)>> = vec![vec![]; n];
+        let mut backward: Vec<Vec<(u32, u32)>> = vec![vec![]; n];
+        // The label of the current hub, by hub, and the scratch space of the
+        // searches.
+        let mut hub_costs = vec![u32::MAX; n];
+        let mut search = PrunedSearch { cost: vec![usize::MAX; n], touched: vec![], queue: BinaryHeap::new() };
+        for (i, &h) in order.iter().enumerate() {
+            let hub = u32::try_from(i).expect("too many nodes for 32-bit hubs");
+            // Backward from h: the costs from u to h, for the forward labels.
+            // A common hub of forward[u] and backward[h] may give them already.
+            for &(j, cost) in &backward[h] { hub_costs[j as usize] = cost; }
+            let settled = search.run(h, &reverse_arcs, |u, cost| add_unless_covered(&mut forward[u], &hub_costs, hub, cost));
+            stats.search_stats.settled_nodes += settled;
+            for &(j, _) in &backward[h] { hub_costs[j as usize] = u32::MAX; }
+
+            // Forward from h: the costs from h to u, for the backward labels.
+            for &(j, cost) in &forward[h] { hub_costs[j as usize] = cost; }
+            let settled = search.run(h, &forward_arcs, |u, cost| add_unless_covered(&mut backward[u], &hub_costs, hub, cost));
+            stats.search_stats.settled_nodes += settled;
+            for &(j, _) in &forward[h] { hub_costs[j as usize] = u32::MAX; }
+            stats.searches += 2;
+        }
+
+        self.forward = Labels::from_lists(forward);
+        self.backward = Labels::from_lists(backward);
+        self.stale = false;
+        stats.elapsed = now.elapsed();
+        stats.size_in_bytes = self.size_in_bytes();
+        stats
+    }
+
+    // The cost from s to t, or None if t is not reachable from s.
+    pub fn distance(&self, s: usize, t: usize) -> Option<usize> {
+        let ((hubs1, costs1), (hubs2, costs2)) = (self.forward.label(s), self.backward.label(t));
+        let (mut i, mut j) = (0, 0);
+        let mut best: Option<usize> = None;
+        while i < hubs1.len() && j < hubs2.len() {
+            match hubs1[i].cmp(&hubs2[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    let cost = costs1[i] as usize + costs2[j] as usize;
+                    if best.is_none_or(|best| cost < best) { best = Some(cost); }
+                    i += 1;
+                    j += 1;
+                },
+            }
+        }
+        best
+    }
+
+    // The costs from all sources to all targets, as distance_matrix.rs.
+    pub fn distance_matrix(&self, sources: &[usize], targets: &[usize]) -> DistanceMatrix {
+        sources.iter().map(|&s| targets.iter().map(|&t| self.distance(s, t)).collect()).collect()
+    }
+
+    // The shortest route from s to t. The labels only give costs, so the path
+    // is found arc by arc: from u, take an arc u -> v with cost(u, v) +
+    // distance(v, t) = distance(u, t). With arcs of cost 0 this can run into a
+    // node that is already on the path, so it backtracks then.
+    pub fn compute_shortest_route(&self, rn: &RoadNetwork, s: usize, t: usize) -> Option<Route> {
+        let cost = self.distance(s, t)?;
+        // The path so far, with the cost from each node to t and the position
+        // of the next arc to try there.
+        let mut path = vec![(s, cost, 0)];
+        let mut on_path = HashSet::new();
+        on_path.insert(s);
+        while path.last()?.0 != t {
+            let (u, remaining, j) = path.last_mut().unwrap();
+            let next = rn.adjacent_arcs[*u][*j..].iter().position(|arc| {
+                !arc.closed && arc.cost <= *remaining && !on_path.contains(&arc.idx) && self.distance(arc.idx, t) == Some(*remaining - arc.cost)
+            });
+            match next {
+                Some(k) => {
+                    let arc = &rn.adjacent_arcs[*u][*j + k];
+                    *j += k + 1;
+                    let next = (arc.idx, *remaining - arc.cost, 0);
+                    on_path.insert(arc.idx);
+                    path.push(next);
+                },
+                None => { path.pop(); },
+            }
+        }
+        Some(Route { cost, nodes: path.into_iter().map(|(u, _, _)| u).collect() })
+    }
+
+    pub fn report(&self) -> LabelReport {
+        let num_nodes = self.forward.first.len().saturating_sub(1);
+        let num_entries = self.forward.hubs.len() + self.backward.hubs.len();
+        let max_label_size = (0..num_nodes)
+            .map(|u| std::cmp::max(self.forward.label(u).0.len(), self.backward.label(u).0.len()))
+            .max()
+            .unwrap_or(0);
+        let average_label_size = if num_nodes == 0 { 0.0 } else { num_entries as f64 / (2 * num_nodes) as f64 };
+        LabelReport { num_nodes, num_entries, average_label_size, max_label_size, size_in_bytes: self.size_in_bytes() }
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.forward.size_in_bytes() + self.backward.size_in_bytes()
+    }
+}
+
+// Add (hub, cost) to the label, unless the label and the label of the hub (by
+// hub, see build_with_order) already give a cost of at most the given one.
+// Returns whether it was added.
+fn add_unless_covered(label: &mut Vec<(u32, u32)>, hub_costs: &[u32], hub: u32, cost: usize) -> bool {
+    if label.iter().any(|&(j, c)| hub_costs[j as usize] != u32::MAX && c as usize + hub_costs[j as usize] as usize <= cost) {
+        return false;
+    }
+    label.push((hub, u32::try_from(cost).expect("costs too large for 32-bit labels")));
+    true
+}
+
+// A Dijkstra that hands each settled node to visit, and only goes on from it
+// if visit returns true.
+struct PrunedSearch {
+    cost: Vec<usize>,
+    touched: Vec<usize>,
+    queue: BinaryHeap<Reverse<(usize, usize)>>,
+}
+
+impl PrunedSearch {
+    // Returns the number of settled nodes.
+    fn run<V: FnMut(usize, usize) -> bool>(&mut self, s: usize, arcs: &[Vec<(usize, usize)>], mut visit: V) -> usize {
+        let mut settled = 0;
+        self.cost[s] = 0;
+        self.touched.push(s);
+        self.queue.push(Reverse((0, s)));
+        while let Some(Reverse((cost, u))) = self.queue.pop() {
+            if cost != self.cost[u] { continue; }
+            settled += 1;
+            if !visit(u, cost) { continue; }
+            for &(v, arc_cost) in &arcs[u] {
+                if cost + arc_cost < self.cost[v] {
+                    if self.cost[v] == usize::MAX { self.touched.push(v); }
+                    self.cost[v] = cost + arc_cost;
+                    self.queue.push(Reverse((cost + arc_cost, v)));
+                }
+            }
+        }
+        for &u in &self.touched { self.cost[u] = usize::MAX; }
+        self.touched.clear();
+        settled
+    }
+}
+
+impl ShortestPathAlgorithm for HubLabels {
+    fn name(&self) -> &str {
+        "hub-labels"
+    }
+
+    // Build the labels in the nested dissection order, see above.
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        let mut cch = CustomizableContractionHierarchy::new();
+        cch.compute_topology(rn);
+        let mut order: Vec<usize> = (0..rn.nodes.len()).collect();
+        order.sort_unstable_by_key(|&u| Reverse(cch.rank(u)));
+        let mut stats = self.build_with_order(rn, &order);
+        stats.elapsed = now.elapsed();
+        stats
+    }
+
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        if self.stale {
+            let cost = Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+            return Route::from_workspace(ws, cost, t);
+        }
+        ws.start_query();
+        let now = Instant::now();
+        let route = self.compute_shortest_route(rn, s, t);
+        ws.stats_mut().elapsed = now.elapsed();
+        route
+    }
+
+    // The labels hold costs, so any change makes them stale.
+    fn costs_changed(&mut self, changes: &CostChanges) {
+        if !changes.is_empty() { self.stale = true; }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
+}
@@ -0,0 +1,241 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Isochrones: the area reachable from a source within a time budget (e.g. 5,
+// 10 and 15 minutes), for showing service areas on a map.
+//
+// One Dijkstra from the source settles all nodes up to the largest budget.
+// For each budget, the reachable part of the network is the arcs between
+// reachable nodes, plus the first part of each arc that leaves this set, up to
+// the point where the budget runs out (the cut point). To turn this into a
+// polygon, we mark all cells of a regular grid that such an arc passes
+// through, fill the holes, and trace the outline of the marked cells. This is
+// cruder than a concave hull, but it is simple and it never produces
+// self-intersecting polygons.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use serde_json::{json, Value};
+use crate::Node;
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+
+// Earth radius in meters, as in Node::distance.
+const EARTH_RADIUS: f64 = 6371.0 * 1000.0;
+
+// The smallest cell size (in meters) of the grid for the polygons.
+pub const MIN_CELL_SIZE: f64 = 10.0;
+
+// A (latitude, longitude) in radian.
+type Position = (f64, f64);
+
+pub struct Isochrone {
+    // The time budget in seconds.
+    pub budget: usize,
+
+    // The nodes with cost at most budget from the source.
+    pub nodes: Vec<usize>,
+
+    // The points where the budget runs out on the arcs leaving the reachable
+    // nodes, as (latitude, longitude) in radian.
+    pub cut_points: Vec<(f64, f64)>,
+
+    // The outlines of the reachable area, one closed ring of (latitude,
+    // longitude) in radian per connected part, counter-clockwise.
+    pub polygons: Vec<Vec<(f64, f64)>>,
+}
+
+// Compute the isochrones of the given source for the given budgets (in
+// seconds). cell_size is the size of the grid cells for the polygons, in
+// meters. It should be somewhat larger than the distance between neighbouring
+// roads, otherwise the polygons get frayed, and at least MIN_CELL_SIZE. The
+// grid covers everything reachable, so a small cell size with a large budget
+// needs a lot of memory.
+pub fn compute_isochrones(rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, budgets: &[usize], cell_size: f64) -> Vec<Isochrone> {
+    let max_budget = budgets.iter().copied().max().unwrap_or(0);
+    let dijkstra = Dijkstra { arc_flags: None };
+    dijkstra.compute_within_budget_with_workspace(ws, &rn.adjacent_arcs, s, max_budget);
+
+    let grid = Grid::new(&rn.nodes[s], cell_size);
+    budgets.iter().map(|&budget| {
+        let nodes: Vec<usize> = ws.settled_nodes().iter().copied()
+            .filter(|&u| ws.distance(u).is_some_and(|d| d <= budget))
+            .collect();
+
+        // The reachable pieces of arcs, as pairs of points.
+        let mut segments = vec![];
+        let mut cut_points = vec![];
+        for &u in &nodes {
+            let d = ws.distance(u).unwrap();
+            let from = (rn.nodes[u].latitude, rn.nodes[u].longitude);
+            for arc in rn.adjacent_arcs[u].iter().filter(|arc| !arc.closed) {
+                let to = (rn.nodes[arc.idx].latitude, rn.nodes[arc.idx].longitude);
+                if d + arc.cost <= budget {
+                    segments.push((from, to));
+                } else {
+                    let f = (budget - d) as f64 / arc.cost as f64;
+                    let cut_point = (from.0 + f * (to.0 - from.0), from.1 + f * (to.1 - from.1));
+                    segments.push((from, cut_point));
+                    // The head may be reachable another way, then the arc
+                    // does not leave the reachable nodes.
+                    if ws.distance(arc.idx).is_none_or(|d| d > budget) { cut_points.push(cut_point); }
+                }
+            }
+        }
+
+        let polygons = grid.polygons(&segments);
+        Isochrone { budget, nodes, cut_points, polygons }
+    }).collect()
+}
+
+// The isochrones as a GeoJSON FeatureCollection, with one MultiPolygon
+// feature per budget and the budget in seconds as property "time".
+pub fn to_geojson(isochrones: &[Isochrone]) -> Value {
+    let features: Vec<Value> = isochrones.iter().map(|isochrone| {
+        let coordinates: Vec<Value> = isochrone.polygons.iter().map(|ring| {
+            let ring: Vec<Value> = ring.iter().map(|&(lat, lng)| json!([lng * 180.0 / PI, lat * 180.0 / PI])).collect();
+            json!([ring])
+        }).collect();
+        json!({
+            "type": "Feature",
+            "properties": { "time": isochrone.budget, "nodes": isochrone.nodes.len() },
+            "geometry": { "type": "MultiPolygon", "coordinates": coordinates },
+        })
+    }).collect();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+// A grid of square cells around an origin. Cell (row, col) covers the
+// latitudes [origin + row * d_lat, origin + (row + 1) * d_lat), and the same
+// for longitudes with col and d_lng.
+struct Grid {
+    origin: (f64, f64),
+    d_lat: f64,
+    d_lng: f64,
+    cell_size: f64,
+}
+
+impl Grid {
+    fn new(origin: &Node, cell_size: f64) -> Grid {
+        assert!(cell_size >= MIN_CELL_SIZE, "the cell size must be at least {} m, not {}", MIN_CELL_SIZE, cell_size);
+        let d_lat = cell_size / EARTH_RADIUS;
+        let d_lng = d_lat / origin.latitude.cos();
+        Grid { origin: (origin.latitude, origin.longitude), d_lat, d_lng, cell_size }
+    }
+
+    fn cell(&self, (lat, lng): (f64, f64)) -> (i64, i64) {
+        (((lat - self.origin.0) / self.d_lat).floor() as i64, ((lng - self.origin.1) / self.d_lng).floor() as i64)
+    }
+
+    // The position of the grid corner (row, col).
+    fn corner(&self, (row, col): (i64, i64)) -> (f64, f64) {
+        (self.origin.0 + row as f64 * self.d_lat, self.origin.1 + col as f64 * self.d_lng)
+    }
+
+    // The outlines of the cells touched by the given segments, see above.
+    fn polygons(&self, segments: &[(Position, Position)]) -> Vec<Vec<Position>> {
+        // Sample each segment at half the cell size, so no cell is missed.
+        let mut cells = vec![];
+        for &(from, to) in segments {
+            let length = Node { osm_id: 0, latitude: from.0, longitude: from.1 }
+                .distance(&Node { osm_id: 0, latitude: to.0, longitude: to.1 });
+            let steps = (2.0 * length / self.cell_size).ceil() as usize;
+            for i in 0..=steps {
+                let f = if steps == 0 { 0.0 } else { i as f64 / steps as f64 };
+                cells.push(self.cell((from.0 + f * (to.0 - from.0), from.1 + f * (to.1 - from.1))));
+            }
+        }
+        if cells.is_empty() { return vec![]; }
+
+        // The marked cells in a bounding box with one empty cell around.
+        let row_min = cells.iter().map(|c| c.0).min().unwrap() - 1;
+        let col_min = cells.iter().map(|c| c.1).min().unwrap() - 1;
+        let rows = (cells.iter().map(|c| c.0).max().unwrap() - row_min + 2) as usize;
+        let cols = (cells.iter().map(|c| c.1).max().unwrap() - col_min + 2) as usize;
+        let mut is_marked = vec![vec![false; cols]; rows];
+        for (row, col) in cells {
+            is_marked[(row - row_min) as usize][(col - col_min) as usize] = true;
+        }
+        fill_holes(&mut is_marked);
+
+        trace_outlines(&is_marked).into_iter()
+            .map(|ring| ring.into_iter().map(|(row, col)| self.corner((row + row_min, col + col_min))).collect())
+            .collect()
+    }
+}
+
+// Mark all cells that cannot be reached from the border of the box without
+// crossing a marked cell.
+fn fill_holes(is_marked: &mut [Vec<bool>]) {
+    let (rows, cols) = (is_marked.len(), is_marked[0].len());
+    let mut is_outside = vec![vec![false; cols]; rows];
+    let mut stack: Vec<(usize, usize)> = vec![(0, 0)];
+    is_outside[0][0] = true;
+    while let Some((row, col)) = stack.pop() {
+        let neighbours = [(row.wrapping_sub(1), col), (row + 1, col), (row, col.wrapping_sub(1)), (row, col + 1)];
+        for (r, c) in neighbours {
+            if r < rows && c < cols && !is_marked[r][c] && !is_outside[r][c] {
+                is_outside[r][c] = true;
+                stack.push((r, c));
+            }
+        }
+    }
+    for row in 0..rows {
+        for col in 0..cols {
+            if !is_outside[row][col] { is_marked[row][col] = true; }
+        }
+    }
+}
+
+// The outlines of the marked cells as rings of grid corners (row, col),
+// counter-clockwise, closed, and without corners in the middle of a straight
+// side. Cells that only touch at a corner belong to different rings.
+// PRECONDITION: there are no holes and the border cells are not marked.
+fn trace_outlines(is_marked: &[Vec<bool>]) -> Vec<Vec<(i64, i64)>> {
+    let marked = |row: i64, col: i64| is_marked[row as usize][col as usize];
+
+    // The sides between a marked and an unmarked cell, directed so that the
+    // marked cell is on the left.
+    let mut sides: HashMap<(i64, i64), Vec<(i64, i64)>> = HashMap::new();
+    for row in 1..is_marked.len() as i64 - 1 {
+        for col in 1..is_marked[0].len() as i64 - 1 {
+            if !marked(row, col) { continue; }
+            let mut add = |from: (i64, i64), to: (i64, i64)| sides.entry(from).or_default().push(to);
+            if !marked(row - 1, col) { add((row, col), (row, col + 1)); }
+            if !marked(row, col + 1) { add((row, col + 1), (row + 1, col + 1)); }
+            if !marked(row + 1, col) { add((row + 1, col + 1), (row + 1, col)); }
+            if !marked(row, col - 1) { add((row + 1, col), (row, col)); }
+        }
+    }
+
+    let mut used = HashSet::new();
+    let mut starts: Vec<((i64, i64), (i64, i64))> = sides.iter()
+        .flat_map(|(&from, tos)| tos.iter().map(move |&to| (from, to)))
+        .collect();
+    starts.sort_unstable();
+
+    let mut rings = vec![];
+    for start in starts {
+        if used.contains(&start) { continue; }
+        let mut ring = vec![];
+        let (mut from, mut to) = start;
+        loop {
+            used.insert((from, to));
+            // Where two marked cells touch at a corner, turn left, so that the
+            // ring stays around the cell it came from.
+            let direction = (to.0 - from.0, to.1 - from.1);
+            let left = (to.0 + direction.1, to.1 - direction.0);
+            let next = if sides[&to].contains(&left) { left } else { sides[&to][0] };
+            if direction != (next.0 - to.0, next.1 - to.1) {
+                ring.push(to);
+            }
+            if used.contains(&(to, next)) { break; }
+            from = to;
+            to = next;
+        }
+        ring.push(ring[0]);
+        rings.push(ring);
+    }
+    rings
+}
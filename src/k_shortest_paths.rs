@@ -0,0 +1,79 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// The k shortest simple (loopless) paths from s to t, by Yen's algorithm
+// (J. Y. Yen: "Finding the K Shortest Loopless Paths in a Network", 1971).
+//
+// Having found the i-th path, every node on it except t is tried as a "spur
+// node": the candidate path follows the i-th path up to the spur node (the
+// root) and then takes the shortest path to t that
+// - does not use the next arc of any path found so far with the same root,
+//   so it is a new path, and
+// - does not pass through a node of the root, so it is loopless.
+// The (i+1)-th path is the cheapest candidate found so far.
+//
+// The removed arcs and nodes are expressed as arc flags (see dijkstra.rs): a
+// removed arc has its flag cleared, and a removed node all its outgoing arcs.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashSet;
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::shortest_path_algorithm::Route;
+
+// Up to k shortest loopless routes from s to t, by increasing cost (ties
+// broken by the node sequence). Fewer if there are not that many.
+pub fn k_shortest_paths(rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize, k: usize) -> Vec<Route> {
+    let mut routes: Vec<Route> = vec![];
+    if k == 0 { return routes; }
+    let cost = Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+    match Route::from_workspace(ws, cost, t) {
+        Some(route) => routes.push(route),
+        None => return routes,
+    }
+
+    let mut arc_flags: Vec<Vec<bool>> = rn.adjacent_arcs.iter().map(|arcs| vec![true; arcs.len()]).collect();
+    let mut candidates = BinaryHeap::new();
+    let mut seen: HashSet<Vec<usize>> = HashSet::new();
+    seen.insert(routes[0].nodes.clone());
+
+    while routes.len() < k {
+        let previous = &routes[routes.len() - 1].nodes;
+        let mut root_cost = 0;
+        for i in 0..previous.len() - 1 {
+            let (root, spur_node) = (&previous[..=i], previous[i]);
+
+            // The arcs and nodes to remove, as (node, position in adjacent_arcs).
+            let mut removed = vec![];
+            for route in &routes {
+                if route.nodes.len() > i + 1 && route.nodes[..=i] == *root {
+                    let next = route.nodes[i + 1];
+                    removed.extend((0..rn.adjacent_arcs[spur_node].len()).filter(|&j| rn.adjacent_arcs[spur_node][j].idx == next).map(|j| (spur_node, j)));
+                }
+            }
+            for &u in &root[..i] {
+                removed.extend((0..rn.adjacent_arcs[u].len()).map(|j| (u, j)));
+            }
+            for &(u, j) in &removed { arc_flags[u][j] = false; }
+
+            let dijkstra = Dijkstra { arc_flags: Some(&arc_flags) };
+            if let Some(spur_cost) = dijkstra.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, spur_node, Some(t), |_,_| 0) {
+                let mut nodes = root[..i].to_vec();
+                nodes.extend(ws.path(t).unwrap());
+                if seen.insert(nodes.clone()) {
+                    candidates.push(Reverse((root_cost + spur_cost, nodes)));
+                }
+            }
+
+            for &(u, j) in &removed { arc_flags[u][j] = true; }
+            root_cost += rn.arc_cost(spur_node, previous[i + 1]).unwrap();
+        }
+
+        match candidates.pop() {
+            Some(Reverse((cost, nodes))) => routes.push(Route { cost, nodes }),
+            None => break,
+        }
+    }
+    routes
+}
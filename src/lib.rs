@@ -5,12 +5,33 @@
 // Class wiki: https://ad-wiki.informatik.uni-freiburg.de/teaching/EfficientRoutePlanningSS2012
 
 use std::collections::HashMap;
-use std::collections::HashSet;
 
 pub mod osm;
 pub mod dijkstra;
 pub mod astar_landmark_triangle_inequality;
 pub mod arc_flags;
+pub mod parallel;
+pub mod priority_queue;
+pub mod region;
+pub mod shortest_path_algorithm;
+pub mod distance_matrix;
+pub mod isochrone;
+pub mod alternative_routes;
+pub mod k_shortest_paths;
+pub mod waypoints;
+pub mod trip;
+pub mod vrp;
+pub mod time_dependent;
+pub mod traffic;
+pub mod customizable_contraction_hierarchy;
+pub mod multi_level_overlay;
+pub mod hub_labels;
+pub mod reach;
+pub mod gtfs;
+pub mod transit;
+pub mod connection_scan;
+pub mod raptor;
+pub mod multimodal;
 
 // A node with its OSM id and its latitude / longitude. This is useful for
 // building the graph from an OSM file (we first read the nodes there, and later
@@ -27,14 +48,19 @@ pub struct Node {
 }
 
 impl Node {
-    pub fn cost(&self, v: &Node, speed: usize) -> usize {
-        //println!("Node 1: {:?}", self);
-        //println!("Node 2: {:?}", v);
-        // Quick distance from this node to Arc's node 
+    // Quick distance in meters from this node to the given node
+    // (equirectangular approximation, good enough for nearby nodes).
+    pub fn distance(&self, v: &Node) -> f64 {
         const R: f64 = 6371.0 * 1000.0;
         let x = (v.longitude - self.longitude) * (0.5*(v.latitude + self.latitude)).cos();
         let y = v.latitude - self.latitude;
-        ((R * (x*x + y*y).sqrt()) / (speed*5/18) as f64).round() as usize
+        R * (x*x + y*y).sqrt()
+    }
+
+    // Travel time in seconds from this node to the given node at the given
+    // speed in km/h.
+    pub fn cost(&self, v: &Node, speed: usize) -> usize {
+        (self.distance(v) / (speed as f64 * 5.0 / 18.0)).round() as usize
     }
 }
 
@@ -53,12 +79,17 @@ pub struct Arc {
     pub cost: usize,
     pub speed: usize,
 
-    pub arc_flag: bool,
+    // The id of the OSM way the arc belongs to, 0 if it is not from a way.
+    pub way_id: usize,
+
+    // A closed arc (e.g. a road closure, see traffic.rs) is skipped by all
+    // searches.
+    pub closed: bool,
 }
 
 impl Arc {
     pub fn new(head_node_id: usize, idx: usize, cost: usize, speed: usize) -> Arc {
-        Arc { head_node_id, idx, cost, speed, arc_flag: false }
+        Arc { head_node_id, idx, cost, speed, way_id: 0, closed: false }
     }
 }
 // A road network modelled as an undirected graph. We will use "arc" and "edge",
@@ -91,22 +122,32 @@ pub struct RoadNetwork {
     pub node_id_to_index: HashMap<usize, usize>,
 }
 
+impl Default for RoadNetwork {
+    fn default() -> Self {
+        RoadNetwork::new()
+    }
+}
+
 impl RoadNetwork {
     // PUBLIC members.
-    pub fn get_node(&self, osm_id: &usize) -> Option<&mut Node> {
-        if let Some(idx) = self.node_id_to_index.get(osm_id) {
-            Some(&self.adjacent_arcs[*idx as usize]);
-        }
-        None
+    pub fn get_node(&self, osm_id: &usize) -> Option<&Node> {
+        self.node_id_to_index.get(osm_id).map(|&idx| &self.nodes[idx])
     }
 
     pub fn get_node_from_lat_lng(&self, lat: &f64, lng: &f64) -> Option<&Node> {
-        for i in 0..self.nodes.len() {
-            if self.nodes[i].latitude == *lat && self.nodes[i].longitude == *lng {
-                return Some(&self.nodes[i]);
-            }
-        }
-        None
+        self.nodes.iter().find(|node| node.latitude == *lat && node.longitude == *lng)
+    }
+
+    // The index of the node closest to the given latitude / longitude (in
+    // radian), or None if the network is empty. This is a linear scan.
+    pub fn get_nearest_node(&self, lat: f64, lng: f64) -> Option<usize> {
+        let point = Node { osm_id: 0, latitude: lat, longitude: lng };
+        (0..self.nodes.len()).min_by(|&u, &v| point.distance(&self.nodes[u]).total_cmp(&point.distance(&self.nodes[v])))
+    }
+
+    // The cost of the cheapest open arc from u to v (indices), if there is one.
+    pub fn arc_cost(&self, u: usize, v: usize) -> Option<usize> {
+        self.adjacent_arcs[u].iter().filter(|arc| arc.idx == v && !arc.closed).map(|arc| arc.cost).min()
     }
 
     // Create an empty network (with zero nodes and zero arcs).
@@ -115,7 +156,7 @@ impl RoadNetwork {
     }
 
     pub fn add_node(&mut self, node: Node) {
-        self.node_id_to_index.entry(node.osm_id).or_insert(self.nodes.len() as usize);
+        self.node_id_to_index.entry(node.osm_id).or_insert(self.nodes.len());
         self.nodes.push(node);
         self.adjacent_arcs.push(vec!());
     }
@@ -123,13 +164,19 @@ impl RoadNetwork {
     // Add an (undirected) edge between the given nodes with the given cost.
     // vtrinh: duplicate allowed for now
     pub fn add_edge_calc_cost_from_speed(&mut self, u: usize, v: usize, speed: usize) {
+        self.add_way_edge(u, v, speed, 0);
+    }
+
+    // Same as add_edge_calc_cost_from_speed, for an edge of the OSM way with
+    // the given id.
+    pub fn add_way_edge(&mut self, u: usize, v: usize, speed: usize, way_id: usize) {
         if let Some(idx_u) = self.node_id_to_index.get(&u) {
             if let Some(idx_v) = self.node_id_to_index.get(&v) {
-                let node1 = &self.nodes[*idx_u as usize];
-                let node2 = &self.nodes[*idx_v as usize];
+                let node1 = &self.nodes[*idx_u];
+                let node2 = &self.nodes[*idx_v];
                 let cost = node1.cost(node2, speed);
-                &self.adjacent_arcs[*idx_u as usize].push(Arc::new(v, *idx_v as usize, cost, speed));
-                &self.adjacent_arcs[*idx_v as usize].push(Arc::new(u, *idx_u as usize, cost, speed));
+                self.adjacent_arcs[*idx_u].push(Arc { way_id, ..Arc::new(v, *idx_v, cost, speed) });
+                self.adjacent_arcs[*idx_v].push(Arc { way_id, ..Arc::new(u, *idx_u, cost, speed) });
             }
             else {
                 println!("Warning node not found: {}", v);
@@ -143,8 +190,8 @@ impl RoadNetwork {
     pub fn add_edge(&mut self, u: usize, v: usize, cost: usize) {
         if let Some(idx_u) = self.node_id_to_index.get(&u) {
             if let Some(idx_v) = self.node_id_to_index.get(&v) {
-                &self.adjacent_arcs[*idx_u as usize].push(Arc::new(v, *idx_v as usize, cost, 0));
-                &self.adjacent_arcs[*idx_v as usize].push(Arc::new(u, *idx_u as usize, cost, 0));
+                self.adjacent_arcs[*idx_u].push(Arc::new(v, *idx_v, cost, 0));
+                self.adjacent_arcs[*idx_v].push(Arc::new(u, *idx_u, cost, 0));
             }
         }
     }
@@ -152,57 +199,51 @@ impl RoadNetwork {
     pub fn add_one_way_edge(&mut self, tail: usize, head: usize, cost: usize, speed: usize) {
         match (self.node_id_to_index.get(&tail), self.node_id_to_index.get(&head)) {
             (Some(idx_u), Some(idx_v)) => {
-                &self.adjacent_arcs[*idx_u as usize].push(Arc::new(head, *idx_v as usize, cost, speed));
+                self.adjacent_arcs[*idx_u].push(Arc::new(head, *idx_v, cost, speed));
             },
             _ => { /*println!("Warning nodes not found: tail: {}/{:?}, head: {}/{:?}", tail,self.node_id_to_index.get(&tail), head,  self.node_id_to_index.get(&head)); */}
         }
     }
 
     pub fn reduce_to_largest_connected_component(&mut self) {
-        let mut visited: HashSet<usize> = HashSet::new();
-        let mut largest_connected_nodes: HashMap<usize, usize> = HashMap::new();
-        let mut largest_number_of_connected_nodes = 0;
+        let mut visited = vec![false; self.nodes.len()];
+        let mut largest_connected_nodes: Vec<usize> = vec![];
         //println!("Nodes.len(): {}", self.nodes.len());
-        let dijkstra = dijkstra::Dijkstra{ consider_arc_flags: false };
+        let dijkstra = dijkstra::Dijkstra{ arc_flags: None };
+        let mut ws = dijkstra::SearchWorkspace::new(self.nodes.len());
 
         for i in 0..self.nodes.len() {
-            if visited.contains(&i) { continue };
-            visited.insert(i);
-
-            if self.adjacent_arcs[i].len() == 0 { continue; }
-
-            match dijkstra.compute_shortest_path(&self.nodes, &mut self.adjacent_arcs, i, None, |_,_| 0) {
-                (_, v, previous_nodes, _) => { 
-                    if  previous_nodes.len() > largest_number_of_connected_nodes { 
-                        largest_number_of_connected_nodes = previous_nodes.len(); 
-                        largest_connected_nodes = previous_nodes.clone();
-                    }
-                    visited.extend(v);
-                }
+            if visited[i] { continue };
+            visited[i] = true;
+
+            if self.adjacent_arcs[i].is_empty() { continue; }
+
+            dijkstra.compute_shortest_path_with_workspace(&mut ws, &self.adjacent_arcs, i, None, |_,_| 0);
+            if ws.settled_nodes().len() > largest_connected_nodes.len() { 
+                largest_connected_nodes = ws.settled_nodes().to_vec();
             }
-            //break;
+            for &u in ws.settled_nodes() { visited[u] = true; }
         }
 
-        //if let Some(largest_connected_nodes) = largest_connected_nodes {
-            //println!("Largest connected nodes: {:?}", largest_connected_nodes);
-            let mut rn =  RoadNetwork::new();
-            for &idx in largest_connected_nodes.keys() {
-                rn.add_node(self.nodes[idx as usize].clone());
-            }
-            //rn.add_node(self.nodes[idx as usize].clone());
-            //println!("Node id to index: {:?}", rn.node_id_to_index);
-            for &idx in largest_connected_nodes.keys() {
-                for arc in &self.adjacent_arcs[idx as usize] {
-                    if largest_connected_nodes.contains_key(&arc.idx) {
-                        rn.add_one_way_edge(self.nodes[idx].osm_id, arc.head_node_id, arc.cost, arc.speed);
-                    }
+        let mut is_connected = vec![false; self.nodes.len()];
+        for &idx in &largest_connected_nodes { is_connected[idx] = true; }
+
+        let mut rn =  RoadNetwork::new();
+        for &idx in &largest_connected_nodes {
+            rn.add_node(self.nodes[idx]);
+        }
+        //println!("Node id to index: {:?}", rn.node_id_to_index);
+        for (new_idx, &idx) in largest_connected_nodes.iter().enumerate() {
+            for arc in &self.adjacent_arcs[idx] {
+                if is_connected[arc.idx] {
+                    let head = rn.node_id_to_index[&arc.head_node_id];
+                    rn.adjacent_arcs[new_idx].push(Arc { idx: head, ..*arc });
                 }
-            };
-            println!("Largest number of connected nodes: {:?}", rn.nodes.len());
-            self.nodes = rn.nodes;
-            self.adjacent_arcs = rn.adjacent_arcs;
-            self.node_id_to_index = rn.node_id_to_index;
-        //}
+            }
+        };
+        self.nodes = rn.nodes;
+        self.adjacent_arcs = rn.adjacent_arcs;
+        self.node_id_to_index = rn.node_id_to_index;
     }
 
 }
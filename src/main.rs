@@ -5,14 +5,62 @@
 // Class wiki: https://ad-wiki.informatik.uni-freiburg.de/teaching/EfficientRoutePlanningSS2012
 
 use rand::prelude::*;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 use efficient_route_planning::osm;
 //use efficient_route_planning::dijkstra;
-//use efficient_route_planning::astar_landmark_triangle_inequality::LandmarkAlgorithm;
-use efficient_route_planning::arc_flags::ArcFlagsAlgorithm;
+use efficient_route_planning::dijkstra::{Dijkstra, QueryStats, SearchWorkspace};
+use efficient_route_planning::priority_queue::{BucketQueue, IndexedBinaryHeap, LazyBinaryHeap, PriorityQueue, RadixHeap};
+use efficient_route_planning::region::Region;
+use efficient_route_planning::shortest_path_algorithm::{algorithm_by_name, ShortestPathAlgorithm, ALGORITHM_NAMES};
+use efficient_route_planning::RoadNetwork;
 
+// Run the same 100 random Dijkstra queries with the given priority queue.
+fn benchmark_priority_queue<Q: PriorityQueue>(rn: &RoadNetwork, name: &str, queries: &[(usize, usize)]) {
+    let dijkstra = Dijkstra { arc_flags: None };
+    let mut ws = SearchWorkspace::<Q>::with_queue(rn.nodes.len());
+    let mut total_cost = 0;
+    let now = Instant::now();
+    for &(start, stop) in queries {
+        total_cost += dijkstra.compute_shortest_path_with_workspace(&mut ws, &rn.adjacent_arcs, start, Some(stop), |_,_| 0).unwrap_or(0);
+    }
+    println!("{}: Average Cost, time per query: {}, {:?}", name, total_cost / queries.len(), now.elapsed() / queries.len() as u32);
+}
+
+// Preprocess with the given algorithm and run the given queries with it.
+fn benchmark_algorithm(rn: &RoadNetwork, algo: &mut dyn ShortestPathAlgorithm, queries: &[(usize, usize)]) {
+    // The memory is reported the same way for every algorithm: in total and
+    // per node, i.e. per label for hub labels.
+    let stats = algo.preprocess(rn);
+    println!("{}: Precompute time, searches, settled nodes, bytes, bytes per node: {:?}, {}, {}, {}, {:.1}", algo.name(),
+             stats.elapsed, stats.searches, stats.search_stats.settled_nodes, stats.size_in_bytes,
+             stats.size_in_bytes as f64 / rn.nodes.len() as f64);
+
+    let mut total_cost = 0;
+    let mut total_stats = QueryStats::default();
+    // One workspace for all queries, so they do not pay for allocating it.
+    let mut ws = SearchWorkspace::new(rn.nodes.len());
+    for &(start, stop) in queries {
+        if let Some(route) = algo.compute_route(rn, &mut ws, start, stop) {
+            total_cost += route.cost;
+        }
+        // The stats of this query are in the workspace until the next query
+        // (see ShortestPathAlgorithm::compute_route).
+        total_stats += ws.stats();
+    }
+    let n = queries.len();
+    println!("{}: Average Cost, settled nodes, relaxed arcs, skipped arcs, queue pushes, time per query: {}, {}, {}, {}, {}, {:?}",
+             algo.name(), total_cost/n, total_stats.settled_nodes/n, total_stats.relaxed_arcs/n, total_stats.skipped_arcs/n,
+             total_stats.queue_pushes/n, total_stats.elapsed/n as u32);
+}
+
+// Usage: efficient_route_planning [ALGORITHM|all] [REGION.geojson]
+// ALGORITHM is one of ALGORITHM_NAMES (default: all of them, one after the
+// other on the same queries). The region is used by the arc flags and to pick
+// the query targets.
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let algorithm = args.get(1).map(|s| s.as_str()).unwrap_or("all");
 
     let mut rn = osm::read_from_osm_file("tests/baden-wuerttemberg.osm").unwrap();
     //let mut rn = osm::read_from_osm_file("tests/saarland.osm").unwrap();
@@ -25,39 +73,42 @@ fn main() {
 
     println!("Node 0: {:?}", rn.nodes[0]);
     println!("Node 0 Arc 0: {:?}, Node: {:?}", rn.adjacent_arcs[0][0], rn.nodes[rn.adjacent_arcs[0][0].idx]);
-    let mut total_cost = 0;
-    let mut total_visited = 0;
-    let mut total_duration = Duration::new(0, 0); 
     let mut rng = thread_rng();
     let distr = rand::distributions::Uniform::new_inclusive(0, rn.nodes.len()-1);
-    //let alt = LandmarkAlgorithm::new(&rn.nodes, &mut rn.adjacent_arcs, 42);
-    let algo = ArcFlagsAlgorithm { };
+
+    let queries: Vec<(usize, usize)> = (0..100).map(|_| (rng.sample(distr), rng.sample(distr))).collect();
+    benchmark_priority_queue::<LazyBinaryHeap>(&rn, "Binary heap (lazy deletion)", &queries);
+    benchmark_priority_queue::<IndexedBinaryHeap>(&rn, "Binary heap (decrease-key)", &queries);
+    benchmark_priority_queue::<RadixHeap>(&rn, "Radix heap", &queries);
+    benchmark_priority_queue::<BucketQueue>(&rn, "Bucket queue", &queries);
 
     // Saarland: [49.20..49.25] × [6.95..7.05]
     // BaWu use [47.95..48.05] × [7.75..7.90] (Freiburg + surroundings)
-    //let targets = algo.precompute_arc_flags(&rn.nodes, &mut rn.adjacent_arcs, 49.20, 49.25, 6.95, 7.05); //saarland
-    let targets = algo.precompute_arc_flags(&rn.nodes, &mut rn.adjacent_arcs, 47.95, 48.05, 7.75, 7.90); //bawu
+    // A polygon region can be given as a GeoJSON file on the command line, or
+    // read from the OSM file with osm::read_boundary_from_osm_file.
+    //let region = Region::rectangle(49.20, 49.25, 6.95, 7.05); //saarland
+    let region = match args.get(2) {
+        Some(filename) => Region::from_geojson_file(filename).unwrap(),
+        None => Region::rectangle(47.95, 48.05, 7.75, 7.90), //bawu
+    };
 
-    let mut counter = 0;
-    loop {
-        let (start, stop) = (rng.sample(distr), rng.sample(distr));
-        if targets.contains(&stop) == false { continue; }
-
-        counter += 1;
+    // All algorithms answer the same queries, with targets inside the region
+    // so that the arc flags are used.
+    let targets: Vec<usize> = (0..rn.nodes.len()).filter(|&u| region.contains(&rn.nodes[u])).collect();
+    if targets.is_empty() {
+        println!("Error: the region {} contains no nodes of the network, skipping the algorithm benchmarks",
+                 args.get(2).map(|s| s.as_str()).unwrap_or("[47.95..48.05] x [7.75..7.90]"));
+        return;
+    }
+    let queries: Vec<(usize, usize)> = (0..100).map(|_| (rng.sample(distr), *targets.choose(&mut rng).unwrap())).collect();
 
-        let now = Instant::now();
-        //println!("Computing heuristic");
-        //println!("H: {:?}", h);
-        //if let (Some(cost), visited, Some(previous_nodes)) = dijkstra::compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, start, Some(stop), |_,_| 0) {
-        if let (Some(cost), visited) = algo.compute_shortest_path(&rn.nodes, &mut rn.adjacent_arcs, start, stop) {
-            total_duration = total_duration + now.elapsed();
-            total_cost = total_cost + cost;
-            total_visited = total_visited + visited.len();
+    let names: Vec<&str> = if algorithm == "all" { ALGORITHM_NAMES.to_vec() } else { vec![algorithm] };
+    for name in names {
+        match algorithm_by_name(name, Some(region.clone())) {
+            Some(mut algo) => benchmark_algorithm(&rn, algo.as_mut(), &queries),
+            None => println!("Unknown algorithm: {}, use one of {:?}", name, ALGORITHM_NAMES),
         }
-        if counter==100 {break};
     }
-    println!("Average Cost, visited.len, time per query: {:?}, {}, {:?}", total_cost/100, total_visited/100, total_duration/100);
-
 }
 
 //
@@ -101,12 +152,12 @@ mod test {
         // Times Square
         let v= efficient_route_planning::Node { osm_id: 0, latitude: std::f64::consts::PI/180.0 * 40.75453807308639, longitude: std::f64::consts::PI/180.0 * -73.9866689484263};
 
-        // Distance should be around 673 m
+        // Distance should be around 673 m, that is 2423 s at 1 km/h
         let c = u.cost(&v, 1);
-        println!("Distance between Empire State Building and Times Square: {}", c);
+        println!("Travel time between Empire State Building and Times Square: {}", c);
 
-        // Fails if distance is greater than 20 meters from 673
-        assert!((c as i32 - 673).abs() < 20);
+        // Fails if distance is greater than 20 meters (72 s) from 673
+        assert!((c as i32 - 2423).abs() < 72);
     }
 
     #[test]
@@ -128,17 +179,16 @@ mod test {
         rn.add_edge(666, 777, 5);
         println!("RoadNetwork: {:?}", rn);
 
-        let dijkstra = efficient_route_planning::dijkstra::Dijkstra { consider_arc_flags: false};
-        match dijkstra.compute_shortest_path(&rn.nodes, &mut rn.adjacent_arcs, 111, Some(444), |_,_| 0) {
-            (Some(_cost), _, previous_nodes, _) => {
-//                println!("Shortest path 111 to 444: {:?}", cost);
-                let mut old_idx = rn.node_id_to_index.get(&444).unwrap().clone();
-                while let Some(&current_idx) = previous_nodes.get(&old_idx) { 
-                    println!("Node: {:?}", rn.nodes[current_idx as usize]); 
-                    old_idx = current_idx;
-                }
-            },
-            _ => (),
+        let dijkstra = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None };
+        let (s, t) = (rn.node_id_to_index[&111], rn.node_id_to_index[&444]);
+        if let (Some(cost), _, previous_nodes, _) = dijkstra.compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, s, Some(t), |_,_| 0) {
+            // 111 -> 333 -> 222 -> 555 -> 444
+            assert_eq!(cost, 10);
+            let mut old_idx = t;
+            while let Some(&current_idx) = previous_nodes.get(&old_idx) { 
+                println!("Node: {:?}", rn.nodes[current_idx]); 
+                old_idx = current_idx;
+            }
         }
 
 /*
@@ -152,6 +202,1273 @@ mod test {
 */
         rn.reduce_to_largest_connected_component();
         println!("RoadNetwork: {:?}", rn);
+        assert_eq!(rn.nodes.len(), 5);
+    }
+
+    // The graph from the slide (see ASCII graph above) plus the two extra
+    // nodes 666 and 777 that are not connected to it.
+    fn lecture_graph() -> efficient_route_planning::RoadNetwork {
+        let mut rn = efficient_route_planning::RoadNetwork::new();
+        for osm_id in [111, 222, 333, 444, 555, 666, 777] {
+            rn.add_node(efficient_route_planning::Node {osm_id, latitude: 11.11, longitude: 11.11});
+        }
+        rn.add_edge(111, 222, 3);
+        rn.add_edge(111, 333, 1);
+        rn.add_edge(222, 333, 1);
+        rn.add_edge(222, 555, 3);
+        rn.add_edge(444, 555, 5);
+        rn.add_edge(666, 777, 5);
+        rn
+    }
+
+    #[test]
+    fn test_landmarks_quantized_is_admissible() {
+        use efficient_route_planning::astar_landmark_triangle_inequality::{LandmarkAlgorithm, LandmarkStorage};
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+
+        let rn = lecture_graph();
+        let full = LandmarkAlgorithm::new_with_storage(&rn.nodes, &rn.adjacent_arcs, 3, LandmarkStorage::Full, 2);
+        let quantized = LandmarkAlgorithm::new_with_storage(&rn.nodes, &rn.adjacent_arcs, 3, LandmarkStorage::Quantized, 2);
+        assert_eq!(full.landmark_distances().size_in_bytes(), 2 * quantized.landmark_distances().size_in_bytes());
+
+        // 111 -> 333 -> 222 -> 555 -> 444
+        let mut ws = efficient_route_planning::dijkstra::SearchWorkspace::new(rn.nodes.len());
+        assert_eq!(full.compute_shortest_path(&mut ws, &rn.adjacent_arcs, 0, 3), Some(10));
+        assert_eq!(quantized.compute_shortest_path(&mut ws, &rn.adjacent_arcs, 0, 3), Some(10));
+        assert_eq!(ws.path(3), Some(vec![0, 2, 1, 4, 3]));
+        assert_eq!(quantized.compute_shortest_path(&mut ws, &rn.adjacent_arcs, 0, 5), None);
+
+        // No landmarks on an empty network, and the same number as asked for
+        // on the next one.
+        let mut alt = LandmarkAlgorithm::without_preprocessing(3, LandmarkStorage::Quantized, 2);
+        assert_eq!(alt.preprocess(&efficient_route_planning::RoadNetwork::new()).size_in_bytes, 0);
+        alt.preprocess(&rn);
+        assert_eq!(alt.landmark_distances().size_in_bytes(), quantized.landmark_distances().size_in_bytes());
+        assert_eq!(alt.compute_shortest_path(&mut ws, &rn.adjacent_arcs, 0, 3), Some(10));
+    }
+
+    #[test]
+    fn test_landmarks_quantized_with_scale() {
+        use efficient_route_planning::astar_landmark_triangle_inequality::{LandmarkAlgorithm, LandmarkDistances, LandmarkStorage};
+
+        // Two long roads make the distances too large for 16 bits, so they are
+        // stored with a scale > 1. The other roads are shorter than that, so
+        // the quantized heuristic is admissible but not consistent on them:
+        // with some landmarks, A* must reopen a settled node to stay exact.
+        let mut rn = efficient_route_planning::RoadNetwork::new();
+        for osm_id in 0..7 {
+            rn.add_node(efficient_route_planning::Node {osm_id, latitude: 0.0, longitude: 0.0});
+        }
+        for (u, v, cost) in [(1, 0, 1), (2, 0, 120000), (3, 0, 7), (4, 1, 100000), (5, 3, 7), (6, 0, 2), (1, 5, 5), (5, 2, 100000), (5, 6, 1)] {
+            rn.add_edge(u, v, cost);
+        }
+
+        let dijkstra = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None };
+        let mut ws = efficient_route_planning::dijkstra::SearchWorkspace::new(rn.nodes.len());
+        let mut alt_ws = efficient_route_planning::dijkstra::SearchWorkspace::new(rn.nodes.len());
+        // The landmark is random, try it often enough to hit the bad ones.
+        for _ in 0..20 {
+            let quantized = LandmarkAlgorithm::new_with_storage(&rn.nodes, &rn.adjacent_arcs, 1, LandmarkStorage::Quantized, 1);
+            match quantized.landmark_distances() {
+                LandmarkDistances::Quantized { scale, .. } => assert!(*scale > 1),
+                LandmarkDistances::Full(_) => panic!("expected 16-bit landmark distances"),
+            }
+            for s in 0..rn.nodes.len() {
+                dijkstra.compute_shortest_path_with_workspace(&mut ws, &rn.adjacent_arcs, s, None, |_,_| 0);
+                for t in 0..rn.nodes.len() {
+                    let cost = ws.distance(t).unwrap();
+                    assert!(quantized.lower_bound(s, t) <= cost, "{} -> {}", s, t);
+                    assert_eq!(quantized.compute_shortest_path(&mut alt_ws, &rn.adjacent_arcs, s, t), Some(cost), "{} -> {}", s, t);
+                }
+            }
+        }
+    }
+
+    // A grid of size x size nodes around Freiburg, about 100 m apart. Edges get
+    // different speeds so that shortest paths are not all of the same shape.
+    fn grid_graph(size: usize) -> efficient_route_planning::RoadNetwork {
+        let mut rn = efficient_route_planning::RoadNetwork::new();
+        for i in 0..size {
+            for j in 0..size {
+                let (lat, lng) = (48.0 + 0.001 * i as f64, 7.8 + 0.0015 * j as f64);
+                rn.add_node(efficient_route_planning::Node {osm_id: i * size + j,
+                    latitude: std::f64::consts::PI/180.0 * lat, longitude: std::f64::consts::PI/180.0 * lng});
+            }
+        }
+        let speeds = [30, 50, 10, 70, 5];
+        for i in 0..size {
+            for j in 0..size {
+                let u = i * size + j;
+                if j + 1 < size { rn.add_edge_calc_cost_from_speed(u, u + 1, speeds[(i + 2 * j) % speeds.len()]); }
+                if i + 1 < size { rn.add_edge_calc_cost_from_speed(u, u + size, speeds[(3 * i + j) % speeds.len()]); }
+            }
+        }
+        rn
+    }
+
+    // Compare the algorithm with Dijkstra on a sample of node pairs: the same
+    // costs, and routes from s to t whose arcs add up to the cost.
+    fn assert_matches_dijkstra(rn: &efficient_route_planning::RoadNetwork,
+                               algorithm: &dyn efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm,
+                               ws: &mut efficient_route_planning::dijkstra::SearchWorkspace) {
+        use efficient_route_planning::dijkstra::Dijkstra;
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+
+        for s in (0..rn.nodes.len()).step_by(7) {
+            for t in (0..rn.nodes.len()).step_by(5) {
+                let expected = Dijkstra { arc_flags: None }.compute_route(rn, ws, s, t).map(|route| route.cost);
+                let route = algorithm.compute_route(rn, ws, s, t);
+                assert_eq!(route.as_ref().map(|route| route.cost), expected, "{}: {} -> {}", algorithm.name(), s, t);
+                if let Some(route) = route {
+                    assert_eq!((route.nodes[0], *route.nodes.last().unwrap()), (s, t));
+                    let cost: usize = route.nodes.windows(2).map(|pair| rn.arc_cost(pair[0], pair[1]).unwrap()).sum();
+                    assert_eq!(cost, route.cost, "{}: {} -> {}", algorithm.name(), s, t);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_arc_flags_match_dijkstra() {
+        use efficient_route_planning::arc_flags::ArcFlagsAlgorithm;
+
+        let rn = grid_graph(10);
+        let mut algo = ArcFlagsAlgorithm::new(4);
+        let region = efficient_route_planning::region::Region::rectangle(48.0025, 48.0065, 7.8035, 7.8095);
+        algo.precompute_arc_flags(&rn.nodes, &rn.adjacent_arcs, &region);
+        let targets = algo.nodes_in_region();
+        assert!(!targets.is_empty() && targets.len() < rn.nodes.len());
+
+        let dijkstra = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None };
+        let mut ws = efficient_route_planning::dijkstra::SearchWorkspace::new(rn.nodes.len());
+        for s in 0..rn.nodes.len() {
+            for &t in &targets {
+                let (expected, _, _, _) = dijkstra.compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+                assert_eq!(algo.compute_shortest_path(&mut ws, &rn.adjacent_arcs, s, t), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_polygon_region() {
+        use efficient_route_planning::region::Region;
+
+        // An L-shaped polygon with a square hole in the corner.
+        let region = Region::polygon(vec![
+            vec![(48.0, 7.8), (48.0, 7.83), (48.01, 7.83), (48.01, 7.81), (48.03, 7.81), (48.03, 7.8)],
+            vec![(48.002, 7.802), (48.002, 7.805), (48.005, 7.805), (48.005, 7.802)],
+        ]);
+        let node = |lat: f64, lng: f64| efficient_route_planning::Node {osm_id: 0,
+            latitude: std::f64::consts::PI/180.0 * lat, longitude: std::f64::consts::PI/180.0 * lng};
+        assert!(region.contains(&node(48.005, 7.82)));
+        assert!(region.contains(&node(48.02, 7.805)));
+        assert!(!region.contains(&node(48.02, 7.82)));
+        assert!(!region.contains(&node(48.0035, 7.8035)));
+        assert!(!region.contains(&node(47.99, 7.805)));
+
+        // The same region in GeoJSON, with [lng, lat] positions.
+        let filename = std::env::temp_dir().join("test_polygon_region.geojson");
+        std::fs::write(&filename, r#"{"type": "Feature", "properties": {}, "geometry": {"type": "Polygon", "coordinates": [
+            [[7.8, 48.0], [7.83, 48.0], [7.83, 48.01], [7.81, 48.01], [7.81, 48.03], [7.8, 48.03], [7.8, 48.0]],
+            [[7.802, 48.002], [7.805, 48.002], [7.805, 48.005], [7.802, 48.005], [7.802, 48.002]]]}}"#).unwrap();
+        let region = Region::from_geojson_file(filename.to_str().unwrap()).unwrap();
+        assert!(region.contains(&node(48.02, 7.805)));
+        assert!(!region.contains(&node(48.0035, 7.8035)));
+    }
+
+    #[test]
+    fn test_boundary_from_osm_file() {
+        use efficient_route_planning::region::Region;
+
+        // The ring 1 - 2 - 3 - 4 - 1 is split into ways that are out of order
+        // and partly reversed. Way 14 is a piece that cannot be closed, and
+        // the first relation has another name.
+        let filename = std::env::temp_dir().join("test_boundary_from_osm_file.osm");
+        std::fs::write(&filename, r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="48.0" lon="7.8"/>
+ <node id="2" lat="48.0" lon="7.81"/>
+ <node id="3" lat="48.01" lon="7.81"/>
+ <node id="4" lat="48.01" lon="7.8"/>
+ <node id="5" lat="48.05" lon="7.85"/>
+ <node id="6" lat="48.06" lon="7.86"/>
+ <way id="10"><nd ref="3"/><nd ref="4"/></way>
+ <way id="11"><nd ref="2"/><nd ref="1"/></way>
+ <way id="12"><nd ref="2"/><nd ref="3"/></way>
+ <way id="13"><nd ref="1"/><nd ref="4"/></way>
+ <way id="14"><nd ref="5"/><nd ref="6"/></way>
+ <relation id="20">
+  <member type="way" ref="13" role="outer"/>
+  <tag k="boundary" v="administrative"/>
+  <tag k="name" v="Somewhere else"/>
+ </relation>
+ <relation id="21">
+  <member type="way" ref="10" role="outer"/>
+  <member type="way" ref="11" role="outer"/>
+  <member type="way" ref="14" role="outer"/>
+  <member type="way" ref="12" role="outer"/>
+  <member type="way" ref="13" role="outer"/>
+  <tag k="boundary" v="administrative"/>
+  <tag k="name" v="Test"/>
+ </relation>
+</osm>
+"#).unwrap();
+        let filename = filename.to_str().unwrap();
+        let region = efficient_route_planning::osm::read_boundary_from_osm_file(filename, "Test").unwrap();
+        match &region {
+            Region::Polygon { rings } => {
+                assert_eq!(rings.len(), 1);
+                assert_eq!(rings[0].first(), rings[0].last());
+                assert_eq!(rings[0].len(), 5);
+            },
+            Region::Rectangle { .. } => panic!("expected a polygon"),
+        }
+        let node = |lat: f64, lng: f64| efficient_route_planning::Node {osm_id: 0,
+            latitude: std::f64::consts::PI/180.0 * lat, longitude: std::f64::consts::PI/180.0 * lng};
+        assert!(region.contains(&node(48.005, 7.805)));
+        assert!(!region.contains(&node(48.055, 7.855)));
+        assert!(efficient_route_planning::osm::read_boundary_from_osm_file(filename, "Nowhere").is_err());
+    }
+
+    #[test]
+    fn test_concurrent_queries_on_shared_network() {
+        use std::sync::Arc;
+        use efficient_route_planning::arc_flags::ArcFlagsAlgorithm;
+
+        let rn = Arc::new(grid_graph(8));
+        let mut algo = ArcFlagsAlgorithm::new(2);
+        let region = efficient_route_planning::region::Region::rectangle(48.002, 48.005, 7.803, 7.807);
+        algo.precompute_arc_flags(&rn.nodes, &rn.adjacent_arcs, &region);
+        let targets = algo.nodes_in_region();
+        let algo = Arc::new(algo);
+
+        let handles: Vec<_> = (0..4).map(|i| {
+            let (rn, algo, targets) = (Arc::clone(&rn), Arc::clone(&algo), targets.clone());
+            std::thread::spawn(move || {
+                let dijkstra = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None };
+                let mut ws = efficient_route_planning::dijkstra::SearchWorkspace::new(rn.nodes.len());
+                for &t in &targets {
+                    let (expected, _, _, _) = dijkstra.compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, i, Some(t), |_,_| 0);
+                    assert_eq!(algo.compute_shortest_path(&mut ws, &rn.adjacent_arcs, i, t), expected);
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_priority_queues_agree() {
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::priority_queue::{BucketQueue, IndexedBinaryHeap, RadixHeap};
+
+        let rn = grid_graph(12);
+        let dijkstra = Dijkstra { arc_flags: None };
+        let mut lazy = SearchWorkspace::new(rn.nodes.len());
+        let mut indexed = SearchWorkspace::<IndexedBinaryHeap>::with_queue(rn.nodes.len());
+        let mut radix = SearchWorkspace::<RadixHeap>::with_queue(rn.nodes.len());
+        let mut buckets = SearchWorkspace::<BucketQueue>::with_queue(rn.nodes.len());
+        for s in (0..rn.nodes.len()).step_by(7) {
+            dijkstra.compute_shortest_path_with_workspace(&mut lazy, &rn.adjacent_arcs, s, None, |_,_| 0);
+            dijkstra.compute_shortest_path_with_workspace(&mut indexed, &rn.adjacent_arcs, s, None, |_,_| 0);
+            dijkstra.compute_shortest_path_with_workspace(&mut radix, &rn.adjacent_arcs, s, None, |_,_| 0);
+            dijkstra.compute_shortest_path_with_workspace(&mut buckets, &rn.adjacent_arcs, s, None, |_,_| 0);
+            for u in 0..rn.nodes.len() {
+                assert_eq!(indexed.distance(u), lazy.distance(u));
+                assert_eq!(radix.distance(u), lazy.distance(u));
+                assert_eq!(buckets.distance(u), lazy.distance(u));
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_algorithms_agree() {
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::shortest_path_algorithm::{algorithm_by_name, ALGORITHM_NAMES};
+
+        let rn = grid_graph(10);
+        let region = efficient_route_planning::region::Region::rectangle(48.0025, 48.0065, 7.8035, 7.8095);
+        let mut algorithms: Vec<_> = ALGORITHM_NAMES.iter().map(|name| algorithm_by_name(name, Some(region.clone())).unwrap()).collect();
+        for algo in algorithms.iter_mut() {
+            algo.preprocess(&rn);
+        }
+
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        for s in (0..rn.nodes.len()).step_by(3) {
+            for t in (0..rn.nodes.len()).step_by(5) {
+                let expected = algorithms[0].compute_route(&rn, &mut ws, s, t).unwrap();
+                assert_eq!(expected.nodes.first(), Some(&s));
+                assert_eq!(expected.nodes.last(), Some(&t));
+                for algo in &algorithms[1..] {
+                    assert_eq!(algo.compute_route(&rn, &mut ws, s, t).unwrap().cost, expected.cost, "{}", algo.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_and_preprocessing_stats() {
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::shortest_path_algorithm::algorithm_by_name;
+
+        let rn = grid_graph(10);
+        let region = efficient_route_planning::region::Region::rectangle(48.0025, 48.0065, 7.8035, 7.8095);
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+
+        let mut alt = algorithm_by_name("alt", None).unwrap();
+        let stats = alt.preprocess(&rn);
+        assert_eq!(stats.searches, efficient_route_planning::shortest_path_algorithm::DEFAULT_NUM_LANDMARKS);
+        assert_eq!(stats.search_stats.settled_nodes, stats.searches * rn.nodes.len());
+        assert!(stats.size_in_bytes > 0);
+
+        let mut arc_flags = algorithm_by_name("arc-flags", Some(region)).unwrap();
+        assert!(arc_flags.preprocess(&rn).searches > 0);
+        let t = rn.get_nearest_node(48.0045_f64.to_radians(), 7.8065_f64.to_radians()).unwrap();
+        arc_flags.compute_route(&rn, &mut ws, 0, t).unwrap();
+        let stats = ws.stats();
+        assert_eq!(stats.settled_nodes, ws.settled_nodes().len());
+        assert!(stats.relaxed_arcs > 0 && stats.skipped_arcs > 0);
+        assert!(stats.queue_pushes >= stats.settled_nodes);
+
+        let dijkstra = algorithm_by_name("dijkstra", None).unwrap();
+        dijkstra.compute_route(&rn, &mut ws, 0, t).unwrap();
+        assert_eq!(ws.stats().skipped_arcs, 0);
+        assert!(ws.stats().settled_nodes >= stats.settled_nodes);
+
+        // Every algorithm leaves the stats of its last query, and only of that
+        // one, in the workspace.
+        let region = efficient_route_planning::region::Region::rectangle(48.0025, 48.0065, 7.8035, 7.8095);
+        let all = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None };
+        for name in efficient_route_planning::shortest_path_algorithm::ALGORITHM_NAMES {
+            let mut algo = algorithm_by_name(name, Some(region.clone())).unwrap();
+            algo.preprocess(&rn);
+            all.compute_shortest_path_with_workspace(&mut ws, &rn.adjacent_arcs, 0, None, |_,_| 0);
+            assert_eq!(ws.stats().settled_nodes, rn.nodes.len());
+            algo.compute_route(&rn, &mut ws, t, t + 1).unwrap();
+            assert!(ws.stats().settled_nodes < rn.nodes.len(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_distance_matrix() {
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::distance_matrix;
+
+        let rn = grid_graph(10);
+        let sources = vec![0, 17, 42, 99];
+        let targets = vec![5, 42, 42, 63, 0];
+        let matrix = distance_matrix::many_to_many(&rn, &sources, &targets, 3);
+
+        let dijkstra = Dijkstra { arc_flags: None };
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        assert_eq!(matrix.len(), sources.len());
+        for (i, &s) in sources.iter().enumerate() {
+            assert_eq!(matrix[i].len(), targets.len());
+            for (j, &t) in targets.iter().enumerate() {
+                let cost = dijkstra.compute_shortest_path_with_workspace(&mut ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+                assert_eq!(matrix[i][j], cost);
+            }
+        }
+        assert_eq!(matrix[2][1], Some(0));
+        assert_eq!(distance_matrix::one_to_many(&rn, &mut ws, 0, &[]), vec![]);
+    }
+
+    #[test]
+    fn test_isochrones() {
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::isochrone;
+        use efficient_route_planning::region::Region;
+
+        let rn = grid_graph(10);
+        let s = 45;
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        let isochrones = isochrone::compute_isochrones(&rn, &mut ws, s, &[60, 120, 1000000], 150.0);
+        let (_, _, _, g_score) = Dijkstra { arc_flags: None }.compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, s, None, |_,_| 0);
+
+        for isochrone in &isochrones {
+            let mut nodes = isochrone.nodes.clone();
+            nodes.sort_unstable();
+            let expected: Vec<usize> = (0..rn.nodes.len()).filter(|&u| g_score[u] <= isochrone.budget).collect();
+            assert_eq!(nodes, expected);
+            assert!(!isochrone.polygons.is_empty());
+
+            // Every reachable node lies in one of the polygons.
+            let rings: Vec<Vec<(f64, f64)>> = isochrone.polygons.iter()
+                .map(|ring| ring.iter().map(|&(lat, lng)| (lat.to_degrees(), lng.to_degrees())).collect())
+                .collect();
+            for ring in &rings {
+                assert_eq!(ring.first(), ring.last());
+            }
+            let region = Region::polygon(rings);
+            assert!(isochrone.nodes.iter().all(|&u| region.contains(&rn.nodes[u])));
+        }
+        assert!(isochrones[0].nodes.len() < isochrones[1].nodes.len());
+        assert!(!isochrones[0].cut_points.is_empty());
+        assert_eq!(isochrones[2].nodes.len(), rn.nodes.len());
+        assert!(isochrones[2].cut_points.is_empty());
+        assert_eq!(isochrones[2].polygons.len(), 1);
+
+        // Node 2 is reached over node 1, the direct arc from 0 does not leave
+        // the reachable nodes.
+        let mut triangle = efficient_route_planning::RoadNetwork::new();
+        for (osm_id, lng) in [(0, 7.8), (1, 7.801), (2, 7.802)] {
+            triangle.add_node(efficient_route_planning::Node {osm_id, latitude: 48.0_f64.to_radians(), longitude: f64::to_radians(lng)});
+        }
+        triangle.add_edge(0, 1, 10);
+        triangle.add_edge(1, 2, 10);
+        triangle.add_edge(0, 2, 100);
+        let mut triangle_ws = SearchWorkspace::new(triangle.nodes.len());
+        let triangle_isochrones = isochrone::compute_isochrones(&triangle, &mut triangle_ws, 0, &[15, 50], 10.0);
+        assert_eq!(triangle_isochrones[0].cut_points.len(), 2);
+        assert_eq!(triangle_isochrones[1].nodes.len(), 3);
+        assert!(triangle_isochrones[1].cut_points.is_empty());
+
+        let geojson = isochrone::to_geojson(&isochrones);
+        assert_eq!(geojson["features"].as_array().unwrap().len(), 3);
+        assert_eq!(geojson["features"][1]["properties"]["time"], 120);
+    }
+
+    #[test]
+    fn test_alternative_routes() {
+        use std::collections::HashSet;
+        use efficient_route_planning::alternative_routes::{AlternativeRouteParameters, AlternativeRoutes};
+        use efficient_route_planning::traffic::{LiveTraffic, TrafficUpdate};
+
+        let mut rn = grid_graph(10);
+        let parameters = AlternativeRouteParameters { max_routes: 4, ..AlternativeRouteParameters::default() };
+        let alternatives = AlternativeRoutes::new(&rn, parameters);
+
+        let mut num_alternatives = 0;
+        for (s, t) in [(0, 99), (9, 90), (12, 87), (40, 49)] {
+            let routes = alternatives.compute_routes(&rn, s, t);
+            let (optimal_cost, _, _, _) = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None }
+                .compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+            assert_eq!(routes[0].cost, optimal_cost.unwrap());
+            assert!(routes.len() <= 4);
+            num_alternatives += routes.len() - 1;
+
+            for (i, route) in routes.iter().enumerate() {
+                assert_eq!((route.nodes[0], *route.nodes.last().unwrap()), (s, t));
+                assert_eq!(route.nodes.iter().collect::<HashSet<_>>().len(), route.nodes.len());
+                assert!(route.cost as f64 <= 1.25 * routes[0].cost as f64);
+                if i > 0 {
+                    assert!(route.cost >= routes[i - 1].cost);
+                    assert_ne!(route.nodes, routes[0].nodes);
+                }
+            }
+        }
+        assert!(num_alternatives > 0);
+
+        // After live traffic changes, the backward search uses the new costs
+        // and closures too.
+        let mut live_traffic = LiveTraffic::new(&rn);
+        let before = alternatives.compute_routes(&rn, 0, 99);
+        for pair in before[0].nodes.windows(2).skip(3).take(4) {
+            live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: pair[0], to: pair[1] }).unwrap();
+        }
+        live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 98, to: 99, cost: 1 }).unwrap();
+        for (s, t) in [(0, 99), (9, 90), (12, 87)] {
+            let routes = alternatives.compute_routes(&rn, s, t);
+            let (optimal_cost, _, _, _) = efficient_route_planning::dijkstra::Dijkstra { arc_flags: None }
+                .compute_shortest_path(&rn.nodes, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+            assert_eq!(routes[0].cost, optimal_cost.unwrap());
+            for route in &routes {
+                let cost: usize = route.nodes.windows(2).map(|pair| rn.arc_cost(pair[0], pair[1]).unwrap()).sum();
+                assert_eq!(cost, route.cost);
+            }
+        }
+    }
+
+    #[test]
+    fn test_k_shortest_paths() {
+        use std::collections::HashSet;
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::k_shortest_paths::k_shortest_paths;
+
+        // All simple paths from u to t, by depth-first search.
+        fn all_simple_paths(rn: &efficient_route_planning::RoadNetwork, path: &mut Vec<usize>, cost: usize, t: usize, costs: &mut Vec<usize>) {
+            let u = *path.last().unwrap();
+            if u == t { costs.push(cost); return; }
+            for arc in &rn.adjacent_arcs[u] {
+                if !path.contains(&arc.idx) {
+                    path.push(arc.idx);
+                    all_simple_paths(rn, path, cost + arc.cost, t, costs);
+                    path.pop();
+                }
+            }
+        }
+
+        let rn = grid_graph(4);
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        for (s, t) in [(0, 15), (5, 6), (3, 12)] {
+            let mut costs = vec![];
+            all_simple_paths(&rn, &mut vec![s], 0, t, &mut costs);
+            costs.sort_unstable();
+
+            let routes = k_shortest_paths(&rn, &mut ws, s, t, 20);
+            assert_eq!(routes.len(), 20);
+            assert_eq!(routes.iter().map(|route| route.cost).collect::<Vec<_>>(), costs[..20].to_vec());
+            let distinct: HashSet<&Vec<usize>> = routes.iter().map(|route| &route.nodes).collect();
+            assert_eq!(distinct.len(), routes.len());
+            for route in &routes {
+                assert_eq!((route.nodes[0], *route.nodes.last().unwrap()), (s, t));
+                assert_eq!(route.nodes.iter().collect::<HashSet<_>>().len(), route.nodes.len());
+                let cost: usize = route.nodes.windows(2).map(|pair| rn.arc_cost(pair[0], pair[1]).unwrap()).sum();
+                assert_eq!(cost, route.cost);
+            }
+        }
+
+        // There are only two simple paths between neighbours on a 2x2 grid.
+        let rn = grid_graph(2);
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        assert_eq!(k_shortest_paths(&rn, &mut ws, 0, 1, 5).len(), 2);
+        assert!(k_shortest_paths(&rn, &mut ws, 0, 1, 0).is_empty());
+    }
+
+    #[test]
+    fn test_route_via_waypoints() {
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::shortest_path_algorithm::algorithm_by_name;
+        use efficient_route_planning::waypoints;
+
+        let rn = grid_graph(10);
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        let mut alt = algorithm_by_name("alt", None).unwrap();
+        alt.preprocess(&rn);
+        let dijkstra = algorithm_by_name("dijkstra", None).unwrap();
+
+        let stops = [0, 99, 9, 90];
+        let route = waypoints::compute_route_via_nodes(&rn, alt.as_ref(), &mut ws, &stops).unwrap();
+        assert_eq!(route.legs.len(), 3);
+        for (leg, pair) in route.legs.iter().zip(stops.windows(2)) {
+            assert_eq!((leg.from, leg.to), (pair[0], pair[1]));
+            assert_eq!(leg.cost, dijkstra.compute_route(&rn, &mut ws, pair[0], pair[1]).unwrap().cost);
+            assert!(leg.distance > 0.0);
+        }
+        assert_eq!(route.route.cost, route.legs.iter().map(|leg| leg.cost).sum::<usize>());
+        assert_eq!((route.route.nodes[0], *route.route.nodes.last().unwrap()), (0, 90));
+        assert!((route.distance - route.route.length(&rn)).abs() < 1e-6);
+        let cost: usize = route.route.nodes.windows(2).map(|pair| rn.arc_cost(pair[0], pair[1]).unwrap()).sum();
+        assert_eq!(cost, route.route.cost);
+
+        // Coordinates are snapped to the closest nodes.
+        let positions: Vec<(f64, f64)> = stops.iter().map(|&u| (rn.nodes[u].latitude + 1e-7, rn.nodes[u].longitude - 1e-7)).collect();
+        assert_eq!(waypoints::compute_route_via_waypoints(&rn, alt.as_ref(), &mut ws, &positions), Some(route));
+        assert_eq!(waypoints::compute_route_via_nodes(&rn, alt.as_ref(), &mut ws, &[5]), None);
+    }
+
+    #[test]
+    fn test_trip_optimization() {
+        use efficient_route_planning::distance_matrix;
+        use efficient_route_planning::trip::{self, TripOptions};
+
+        // The cheapest order by trying all permutations of stops[k..].
+        fn brute_force(costs: &[Vec<Option<usize>>], order: &mut Vec<usize>, k: usize, options: TripOptions) -> usize {
+            if k == order.len() {
+                if options.end.is_some_and(|end| end != order[order.len() - 1]) { return usize::MAX; }
+                let mut cost: usize = order.windows(2).map(|pair| costs[pair[0]][pair[1]].unwrap()).sum();
+                if options.round_trip { cost += costs[order[order.len() - 1]][order[0]].unwrap(); }
+                return cost;
+            }
+            let mut best = usize::MAX;
+            for i in k..order.len() {
+                order.swap(k, i);
+                best = std::cmp::min(best, brute_force(costs, order, k + 1, options));
+                order.swap(k, i);
+            }
+            best
+        }
+
+        let rn = grid_graph(10);
+        let stops = vec![3, 97, 55, 20, 68, 41, 9, 80];
+        let costs = distance_matrix::many_to_many(&rn, &stops, &stops, 2);
+        let all_options = [
+            TripOptions { round_trip: true, start: None, end: None },
+            TripOptions { round_trip: true, start: Some(4), end: None },
+            TripOptions { round_trip: false, start: None, end: None },
+            TripOptions { round_trip: false, start: Some(1), end: None },
+            TripOptions { round_trip: false, start: Some(0), end: Some(7) },
+        ];
+        for options in all_options {
+            let trip = trip::optimize_trip(&rn, &stops, options, 2).unwrap();
+            let mut order = trip.order.clone();
+            order.sort_unstable();
+            assert_eq!(order, (0..stops.len()).collect::<Vec<_>>());
+            if let Some(start) = options.start { assert_eq!(trip.order[0], start); }
+            if let Some(end) = options.end { assert_eq!(*trip.order.last().unwrap(), end); }
+
+            let route = &trip.route.route;
+            assert_eq!(route.nodes[0], stops[trip.order[0]]);
+            let last_stop = if options.round_trip { trip.order[0] } else { *trip.order.last().unwrap() };
+            assert_eq!(*route.nodes.last().unwrap(), stops[last_stop]);
+            assert_eq!(trip.route.legs.len(), if options.round_trip { stops.len() } else { stops.len() - 1 });
+
+            // The heuristic should get close to the optimum. The first stop is
+            // fixed for the brute force, for a round trip that is no restriction.
+            let mut order: Vec<usize> = (0..stops.len()).collect();
+            let best = match options.start {
+                Some(start) => { order.swap(0, start); brute_force(&costs, &mut order, 1, options) },
+                None if options.round_trip => brute_force(&costs, &mut order, 1, options),
+                None => brute_force(&costs, &mut order, 0, options),
+            };
+            assert!(route.cost >= best && route.cost as f64 <= 1.05 * best as f64, "{:?}: {} vs. {}", options, route.cost, best);
+        }
+
+        assert_eq!(trip::solve(&vec![], TripOptions::default()), Some(vec![]));
+        assert_eq!(trip::solve(&vec![vec![Some(0), None], vec![None, Some(0)]], TripOptions::default()), None);
+
+        // A start or end that is not a stop.
+        let two_stops = vec![vec![Some(0), Some(1)], vec![Some(1), Some(0)]];
+        assert_eq!(trip::solve(&two_stops, TripOptions { round_trip: true, start: Some(2), end: None }), None);
+        assert_eq!(trip::solve(&two_stops, TripOptions { round_trip: false, start: None, end: Some(5) }), None);
+        assert_eq!(trip::solve(&vec![], TripOptions { round_trip: true, start: Some(0), end: None }), None);
+        assert!(trip::optimize_trip(&rn, &stops, TripOptions { round_trip: true, start: Some(stops.len()), end: None }, 2).is_none());
     }
 
+    #[test]
+    fn test_vehicle_routing() {
+        use std::collections::HashSet;
+        use efficient_route_planning::distance_matrix;
+        use efficient_route_planning::vrp::{self, Problem};
+
+        let rn = grid_graph(10);
+        let position = |u: usize| (rn.nodes[u].latitude.to_degrees(), rn.nodes[u].longitude.to_degrees());
+        let delivery = |id: &str, u: usize, demand: usize, window: [usize; 2]| serde_json::json!({
+            "id": id, "lat": position(u).0, "lng": position(u).1, "demand": demand, "time_window": window, "service_time": 60,
+        });
+        let json = serde_json::json!({
+            "depot": { "lat": position(44).0, "lng": position(44).1 },
+            "vehicles": [
+                { "id": "small", "capacity": 10, "start_time": 1000, "end_time": 5000 },
+                { "id": "large", "capacity": 25, "start_time": 1000, "end_time": 5000 },
+            ],
+            "deliveries": [
+                delivery("a", 0, 5, [1000, 5000]), delivery("b", 9, 5, [1000, 2000]),
+                delivery("c", 90, 8, [1500, 5000]), delivery("d", 99, 4, [1000, 5000]),
+                delivery("e", 55, 6, [3000, 3500]), delivery("f", 23, 3, [1000, 5000]),
+                // Too late to reach it at all.
+                delivery("g", 72, 1, [0, 1001]),
+            ],
+        });
+        let problem = Problem::from_json(&json).unwrap();
+        let solution = vrp::solve(&rn, &problem, 2).unwrap();
+
+        assert_eq!(solution.unassigned, vec![6]);
+        let nodes = [44, 0, 9, 90, 99, 55, 23, 72];
+        let costs = distance_matrix::many_to_many(&rn, &nodes, &nodes, 2);
+        let mut served = HashSet::new();
+        for route in &solution.routes {
+            let vehicle = &problem.vehicles[route.vehicle];
+            assert!(route.load <= vehicle.capacity);
+            assert_eq!(route.load, route.stops.iter().map(|stop| problem.deliveries[stop.delivery].demand).sum::<usize>());
+            let (mut time, mut location, mut travel_time) = (vehicle.start_time, 0, 0);
+            for stop in &route.stops {
+                let delivery = &problem.deliveries[stop.delivery];
+                let travel = costs[location][stop.delivery + 1].unwrap();
+                assert_eq!(stop.arrival, time + travel);
+                assert!(stop.service_start >= delivery.time_window.0 && stop.service_start <= delivery.time_window.1);
+                assert!(stop.service_start >= stop.arrival);
+                assert_eq!(stop.departure, stop.service_start + delivery.service_time);
+                assert!(served.insert(stop.delivery));
+                time = stop.departure;
+                location = stop.delivery + 1;
+                travel_time += travel;
+            }
+            travel_time += costs[location][0].unwrap();
+            assert_eq!(route.travel_time, travel_time);
+            assert!(route.return_time <= vehicle.end_time);
+        }
+        assert_eq!(served.len(), 6);
+        assert_eq!(solution.travel_time, solution.routes.iter().map(|route| route.travel_time).sum::<usize>());
+
+        let output = solution.to_json(&problem);
+        assert_eq!(output["unassigned"], serde_json::json!(["g"]));
+        assert_eq!(output["routes"].as_array().unwrap().len(), 2);
+        assert!(Problem::from_json(&serde_json::json!({ "depot": {} })).is_err());
+
+        // Inverted times are rejected, and a vehicle that cannot come back in
+        // time even without deliveries is not used.
+        let mut inverted = json.clone();
+        inverted["vehicles"][0]["start_time"] = serde_json::json!(6000);
+        assert!(Problem::from_json(&inverted).is_err());
+        let mut inverted = json.clone();
+        inverted["deliveries"][0]["time_window"] = serde_json::json!([2000, 1000]);
+        assert!(Problem::from_json(&inverted).is_err());
+        // Without a service time there is none, a malformed one is an error.
+        let mut service_time = json.clone();
+        service_time["deliveries"][0].as_object_mut().unwrap().remove("service_time");
+        assert_eq!(Problem::from_json(&service_time).unwrap().deliveries[0].service_time, 0);
+        for malformed in [serde_json::json!("60"), serde_json::json!(-60), serde_json::json!(1.5)] {
+            service_time["deliveries"][0]["service_time"] = malformed;
+            assert!(Problem::from_json(&service_time).is_err());
+        }
+        let mut problem = problem;
+        problem.vehicles[0].start_time = 6000;
+        let solution = vrp::solve_with_costs(&problem, &costs);
+        assert!(solution.routes.iter().all(|route| route.vehicle == 1));
+        assert!(!solution.unassigned.is_empty());
+    }
+
+    #[test]
+    fn test_time_dependent_earliest_arrival() {
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::time_dependent::{TimeDependentNetwork, TravelTimeFunction, DAY};
+
+        let hour = 3600;
+        let f = TravelTimeFunction::new(vec![(6 * hour, 100), (8 * hour, 200), (10 * hour, 100)]).unwrap();
+        assert_eq!(f.evaluate(3 * hour), 100);
+        assert_eq!(f.evaluate(7 * hour), 150);
+        assert_eq!(f.evaluate(8 * hour + DAY), 200);
+        assert_eq!(TravelTimeFunction::constant(42).evaluate(12345), 42);
+        // Arriving earlier by leaving later is not allowed.
+        assert!(TravelTimeFunction::new(vec![(0, 500), (100, 100)]).is_err());
+        assert!(TravelTimeFunction::new(vec![(0, 500), (DAY, 100)]).is_err());
+        assert!(TravelTimeFunction::new(vec![]).is_err());
+
+        let rn = grid_graph(10);
+        let profile = [(0, 1.0), (6 * hour, 1.0), (8 * hour, 2.5), (10 * hour, 1.0)];
+        let mut network = TimeDependentNetwork::with_profile(&rn, &profile).unwrap();
+        // One arc with its own rush hour in the evening.
+        network.set_function(0, 0, TravelTimeFunction::new(vec![(17 * hour, rn.adjacent_arcs[0][0].cost), (18 * hour, 5000)]).unwrap());
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        let dijkstra = Dijkstra { arc_flags: None };
+
+        for (s, t) in [(0, 99), (9, 90), (45, 3)] {
+            let static_cost = dijkstra.compute_shortest_path_with_workspace(&mut ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0).unwrap();
+            for departure in [3 * hour, 7 * hour + 1234, 8 * hour, DAY - 100, 17 * hour + 1800] {
+                let route = network.compute_route(&rn, &mut ws, s, t, departure).unwrap();
+                assert_eq!(network.earliest_arrival(&rn, &mut ws, s, t, departure), Some(departure + route.cost));
+
+                // The arrival times by Bellman-Ford, which does not need FIFO.
+                let mut arrival = vec![usize::MAX; rn.nodes.len()];
+                arrival[s] = departure;
+                let mut changed = true;
+                while changed {
+                    changed = false;
+                    for u in 0..rn.nodes.len() {
+                        if arrival[u] == usize::MAX { continue; }
+                        for (j, arc) in rn.adjacent_arcs[u].iter().enumerate() {
+                            let time = arrival[u] + network.function(u, j).evaluate(arrival[u]);
+                            if time < arrival[arc.idx] { arrival[arc.idx] = time; changed = true; }
+                        }
+                    }
+                }
+                assert_eq!(departure + route.cost, arrival[t]);
+
+                // Following the route at these times gives the same arrival.
+                let mut time = departure;
+                for pair in route.nodes.windows(2) {
+                    let j = rn.adjacent_arcs[pair[0]].iter().position(|arc| arc.idx == pair[1]).unwrap();
+                    time += network.function(pair[0], j).evaluate(time);
+                }
+                assert_eq!(time, arrival[t]);
+
+                if departure == 3 * hour { assert_eq!(route.cost, static_cost); }
+                if departure == 8 * hour { assert!(route.cost > static_cost); }
+            }
+        }
+    }
+
+    #[test]
+    fn test_live_traffic() {
+        use efficient_route_planning::arc_flags::ArcFlagsAlgorithm;
+        use efficient_route_planning::astar_landmark_triangle_inequality::{LandmarkAlgorithm, LandmarkStorage};
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::region::Region;
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+        use efficient_route_planning::traffic::{self, LiveTraffic, TrafficUpdate};
+
+        // Row i of the grid is way 1000 + i, column j is way 2000 + j.
+        let size = 8;
+        let mut rn = grid_graph(size);
+        for (u, arcs) in rn.adjacent_arcs.iter_mut().enumerate() {
+            for arc in arcs.iter_mut() {
+                arc.way_id = if arc.idx / size == u / size { 1000 + u / size } else { 2000 + u % size };
+            }
+        }
+        let mut algorithms: Vec<Box<dyn ShortestPathAlgorithm>> = vec![
+            Box::new(LandmarkAlgorithm::without_preprocessing(4, LandmarkStorage::Full, 2)),
+            Box::new(ArcFlagsAlgorithm::with_region(Region::rectangle(48.0035, 48.008, 7.8, 7.812), 2)),
+        ];
+        for algorithm in algorithms.iter_mut() { algorithm.preprocess(&rn); }
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        let check = |rn: &efficient_route_planning::RoadNetwork, algorithms: &[Box<dyn ShortestPathAlgorithm>], ws: &mut SearchWorkspace| {
+            for algorithm in algorithms { assert_matches_dijkstra(rn, algorithm.as_ref(), ws); }
+        };
+
+        let mut live_traffic = LiveTraffic::new(&rn);
+        let route = Dijkstra { arc_flags: None }.compute_route(&rn, &mut ws, 0, 63).unwrap();
+        let (a, b) = (route.nodes[3], route.nodes[4]);
+        let feed = format!("# road works\narc {} {} close\narc {} {} close  # both ways\n\nway 1005 speed 5\n", a, b, b, a);
+        let updates = traffic::parse_feed(&feed).unwrap();
+        assert_eq!(updates[0], TrafficUpdate::CloseArc { from: a, to: b });
+        assert_eq!(updates[2], TrafficUpdate::SetWaySpeed { way_id: 1005, speed: 5 });
+        let changes = live_traffic.apply_all(&mut rn, &updates).unwrap();
+        assert_eq!(changes.decreased, 0);
+        assert!(changes.increased >= 2);
+        let detour = Dijkstra { arc_flags: None }.compute_route(&rn, &mut ws, 0, 63).unwrap();
+        assert!(detour.cost >= route.cost);
+        assert!(!detour.nodes.windows(2).any(|pair| pair == [a, b]));
+
+        // Only increases: ALT stays valid, arc flags go stale.
+        for algorithm in algorithms.iter_mut() { algorithm.costs_changed(&changes); }
+        assert!(!algorithms[0].is_stale());
+        assert!(algorithms[1].is_stale());
+        check(&rn, &algorithms, &mut ws);
+
+        // Closing a way closes its arcs in both directions, opening it again
+        // counts as a decrease.
+        let closed = live_traffic.apply(&mut rn, TrafficUpdate::CloseWay { way_id: 2007 }).unwrap();
+        assert_eq!(closed.increased, 2 * (size - 1));
+        let opened = live_traffic.apply(&mut rn, TrafficUpdate::OpenWay { way_id: 2007 }).unwrap();
+        assert_eq!(opened.decreased, 2 * (size - 1));
+        for algorithm in algorithms.iter_mut() { algorithm.costs_changed(&opened); }
+        assert!(algorithms[0].is_stale());
+        check(&rn, &algorithms, &mut ws);
+
+        // Cheaper arcs, then back to the network as read.
+        live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 0, to: 1, cost: 1 }).unwrap();
+        check(&rn, &algorithms, &mut ws);
+        live_traffic.apply(&mut rn, TrafficUpdate::Reset).unwrap();
+        assert_eq!(Dijkstra { arc_flags: None }.compute_route(&rn, &mut ws, 0, 63), Some(route));
+        for algorithm in algorithms.iter_mut() {
+            algorithm.preprocess(&rn);
+            assert!(!algorithm.is_stale());
+        }
+        check(&rn, &algorithms, &mut ws);
+
+        assert!(traffic::parse_feed("arc 1 2 slow").is_err());
+        assert!(traffic::parse_feed("way x close").is_err());
+        assert!(live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: 0, to: 9 }).is_err());
+        assert!(live_traffic.apply(&mut rn, TrafficUpdate::CloseWay { way_id: 42 }).is_err());
+    }
+
+    #[test]
+    fn test_customizable_contraction_hierarchy() {
+        use efficient_route_planning::customizable_contraction_hierarchy::CustomizableContractionHierarchy;
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+        use efficient_route_planning::traffic::{LiveTraffic, TrafficUpdate};
+
+        let mut rn = grid_graph(12);
+        let mut cch = CustomizableContractionHierarchy::new();
+        cch.preprocess(&rn);
+        assert!(cch.num_edges() * 2 >= rn.adjacent_arcs.iter().map(|arcs| arcs.len()).sum::<usize>());
+        let mut ranks: Vec<usize> = (0..rn.nodes.len()).map(|u| cch.rank(u)).collect();
+        ranks.sort_unstable();
+        assert!(ranks.into_iter().eq(0..rn.nodes.len()));
+
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        assert_matches_dijkstra(&rn, &cch, &mut ws);
+        assert!(ws.stats().settled_nodes > 0);
+
+        // New costs, some only in one direction, and a closure: customize
+        // again with the same topology.
+        let mut live_traffic = LiveTraffic::new(&rn);
+        let mut changes = live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 13, to: 14, cost: 1 }).unwrap();
+        changes += live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 50, to: 62, cost: 5000 }).unwrap();
+        changes += live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: 70, to: 71 }).unwrap();
+        changes += live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: 71, to: 70 }).unwrap();
+        cch.costs_changed(&changes);
+        assert!(cch.is_stale());
+        assert_matches_dijkstra(&rn, &cch, &mut ws);
+        let edges = cch.num_edges();
+        cch.customize(&rn);
+        assert!(!cch.is_stale());
+        assert_eq!(cch.num_edges(), edges);
+        assert_matches_dijkstra(&rn, &cch, &mut ws);
+
+        // Cut the grid in two: no route between the halves.
+        for i in 0..12 {
+            let u = i * 12 + 5;
+            live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: u, to: u + 1 }).unwrap();
+        }
+        cch.customize(&rn);
+        assert_eq!(cch.compute_route(&rn, &mut ws, 0, 11), None);
+        assert!(cch.compute_route(&rn, &mut ws, 11, 0).is_some());
+        assert_matches_dijkstra(&rn, &cch, &mut ws);
+    }
+
+    #[test]
+    fn test_multi_level_overlay() {
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::multi_level_overlay::{MultiLevelOverlay, OverlayParameters};
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+        use efficient_route_planning::traffic::{LiveTraffic, TrafficUpdate};
+
+        let mut rn = grid_graph(16);
+        let mut crp = MultiLevelOverlay::new(OverlayParameters { cell_sizes: vec![8, 32, 128], num_threads: 3 });
+        let stats = crp.preprocess(&rn);
+        assert!(stats.searches > 0 && stats.size_in_bytes > 0);
+        assert_eq!(crp.num_levels(), 3);
+        assert_eq!(crp.num_cells(0), 32);
+        assert_eq!(crp.num_cells(2), 2);
+        // The cells are nested, and boundary nodes get fewer level by level.
+        for u in 0..rn.nodes.len() {
+            for v in 0..rn.nodes.len() {
+                if crp.cell(0, u) == crp.cell(0, v) { assert_eq!(crp.cell(1, u), crp.cell(1, v)); }
+            }
+        }
+        assert!(crp.num_boundary_nodes(0) > crp.num_boundary_nodes(1) && crp.num_boundary_nodes(1) > crp.num_boundary_nodes(2));
+
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        assert_matches_dijkstra(&rn, &crp, &mut ws);
+        let dijkstra_settled = {
+            Dijkstra { arc_flags: None }.compute_route(&rn, &mut ws, 0, 255);
+            ws.stats().settled_nodes
+        };
+        crp.compute_route(&rn, &mut ws, 0, 255);
+        assert!(ws.stats().settled_nodes < dijkstra_settled);
+
+        // A new metric with one-way changes and closures, same partition.
+        let mut live_traffic = LiveTraffic::new(&rn);
+        let mut changes = live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 17, to: 18, cost: 1 }).unwrap();
+        changes += live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 120, to: 136, cost: 5000 }).unwrap();
+        for i in 0..15 {
+            let u = i * 16 + 7;
+            changes += live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: u, to: u + 1 }).unwrap();
+        }
+        crp.costs_changed(&changes);
+        assert!(crp.is_stale());
+        assert_matches_dijkstra(&rn, &crp, &mut ws);
+        crp.customize(&rn);
+        assert!(!crp.is_stale());
+        assert_matches_dijkstra(&rn, &crp, &mut ws);
+    }
+
+    #[test]
+    #[should_panic(expected = "the cell sizes must be positive")]
+    fn test_multi_level_overlay_zero_cell_size() {
+        use efficient_route_planning::multi_level_overlay::{MultiLevelOverlay, OverlayParameters};
+        MultiLevelOverlay::new(OverlayParameters { cell_sizes: vec![0, 8], num_threads: 1 });
+    }
+
+    #[test]
+    #[should_panic(expected = "the cell sizes must be strictly increasing")]
+    fn test_multi_level_overlay_decreasing_cell_sizes() {
+        use efficient_route_planning::multi_level_overlay::{MultiLevelOverlay, OverlayParameters};
+        MultiLevelOverlay::new(OverlayParameters { cell_sizes: vec![32, 8], num_threads: 1 });
+    }
+
+    #[test]
+    fn test_hub_labels() {
+        use efficient_route_planning::dijkstra::{Dijkstra, SearchWorkspace};
+        use efficient_route_planning::distance_matrix;
+        use efficient_route_planning::hub_labels::HubLabels;
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+        use efficient_route_planning::traffic::{LiveTraffic, TrafficUpdate};
+
+        let mut rn = grid_graph(12);
+        // Some one-way costs, so both directions of the labels matter.
+        let mut live_traffic = LiveTraffic::new(&rn);
+        live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 13, to: 14, cost: 1 }).unwrap();
+        live_traffic.apply(&mut rn, TrafficUpdate::CloseArc { from: 70, to: 71 }).unwrap();
+        live_traffic.apply(&mut rn, TrafficUpdate::SetArcCost { from: 71, to: 70, cost: 0 }).unwrap();
+
+        let mut labels = HubLabels::new();
+        let stats = labels.preprocess(&rn);
+        assert_eq!(stats.searches, 2 * rn.nodes.len());
+        let report = labels.report();
+        assert_eq!(report.num_nodes, rn.nodes.len());
+        assert_eq!(report.size_in_bytes, stats.size_in_bytes);
+        // Pruning keeps the labels far below one entry per node pair.
+        assert!(report.max_label_size < rn.nodes.len() / 2);
+        assert!(report.average_label_size >= 1.0 && report.average_label_size <= report.max_label_size as f64);
+
+        let nodes: Vec<usize> = (0..rn.nodes.len()).collect();
+        assert_eq!(labels.distance_matrix(&nodes, &nodes), distance_matrix::many_to_many(&rn, &nodes, &nodes, 2));
+
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        assert_matches_dijkstra(&rn, &labels, &mut ws);
+
+        let changes = live_traffic.apply(&mut rn, TrafficUpdate::Reset).unwrap();
+        labels.costs_changed(&changes);
+        assert!(labels.is_stale());
+        let expected = Dijkstra { arc_flags: None }.compute_route(&rn, &mut ws, 13, 71);
+        assert_eq!(labels.compute_route(&rn, &mut ws, 13, 71), expected);
+    }
+
+    #[test]
+    fn test_reach() {
+        use efficient_route_planning::dijkstra::{QueryStats, SearchWorkspace};
+        use efficient_route_planning::reach::{ReachAlgorithm, INFINITE_REACH};
+        use efficient_route_planning::shortest_path_algorithm::ShortestPathAlgorithm;
+
+        let rn = grid_graph(12);
+        let mut exact = ReachAlgorithm::new(None, 2);
+        let stats = exact.preprocess(&rn);
+        assert_eq!(stats.searches, rn.nodes.len());
+        let mut approximate = ReachAlgorithm::new(Some(200), 2);
+        approximate.preprocess(&rn);
+        // Partial trees only give upper bounds, but some of them still finite.
+        assert!((0..rn.nodes.len()).all(|u| exact.reach(u) < INFINITE_REACH && approximate.reach(u) >= exact.reach(u)));
+        assert!((0..rn.nodes.len()).any(|u| approximate.reach(u) < INFINITE_REACH));
+        let mut real = ReachAlgorithm::with_landmarks(None, 4, 2);
+        real.preprocess(&rn);
+        assert_eq!(real.name(), "real");
+
+        let mut ws = SearchWorkspace::new(rn.nodes.len());
+        for algo in [&exact, &approximate, &real] { assert_matches_dijkstra(&rn, algo, &mut ws); }
+        let mut pruned = QueryStats::default();
+        for s in (0..rn.nodes.len()).step_by(7) {
+            for t in (0..rn.nodes.len()).step_by(5) {
+                exact.compute_route(&rn, &mut ws, s, t);
+                pruned += ws.stats();
+            }
+        }
+        assert!(pruned.skipped_arcs > 0);
+    }
+
+    #[test]
+    fn test_gtfs_transit() {
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::gtfs::{self, GtfsFeed, Timetable};
+        use efficient_route_planning::transit::TransitGraph;
+
+        let feed = GtfsFeed {
+            stops: "\u{feff}stop_id,stop_name,stop_lat,stop_lon\nA,\"Freiburg, Hbf\",47.9977,7.8421\nB,Emmendingen,48.1215,7.8504\nC,Offenburg,48.4766,7.9469\nD,Karlsruhe,48.9935,8.4003\n".to_string(),
+            trips: "route_id,service_id,trip_id\nRE,weekdays,slow\nRE,weekdays,late\nIC,weekdays,fast\nRE,sundays,sunday\n".to_string(),
+            stop_times: "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                slow,08:15:00,08:16:00,B,2\nslow,08:00:00,08:00:00,A,1\nslow,08:50:00,08:55:00,C,3\nslow,09:50:00,09:50:00,D,4\n\
+                fast,08:10:00,08:10:00,A,1\nfast,08:40:00,08:42:00,C,2\nfast,09:20:00,09:20:00,D,3\n\
+                late,23:50:00,23:50:00,A,1\nlate,24:30:00,24:30:00,C,2\n\
+                sunday,10:00:00,10:00:00,A,1\nsunday,11:00:00,11:00:00,D,2\n".to_string(),
+            calendar: "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                weekdays,1,1,1,1,1,0,0,20211001,20211231\nsundays,0,0,0,0,0,0,1,20211001,20211231\n".to_string(),
+            calendar_dates: "service_id,date,exception_type\nweekdays,20211101,2\n".to_string(),
+            transfers: String::new(),
+        };
+        let timetable = Timetable::from_feed(&feed).unwrap();
+        assert_eq!(timetable.stops[0].name, "Freiburg, Hbf");
+        assert_eq!(gtfs::weekday(20211025), 0);
+        assert_eq!(gtfs::weekday(20211031), 6);
+        let stop = |id: &str| timetable.stop_index(id).unwrap();
+        let (a, b, c, d) = (stop("A"), stop("B"), stop("C"), stop("D"));
+        assert_eq!(timetable.trips[0].stop_times.iter().map(|st| st.stop).collect::<Vec<_>>(), vec![a, b, c, d]);
+
+        let mut ws = SearchWorkspace::new(timetable.stops.len());
+        let monday = TransitGraph::new(&timetable, 20211025);
+        // The later fast train overtakes the slow one.
+        let route = monday.compute_route(&mut ws, a, d, 7 * 3600 + 30 * 60).unwrap();
+        assert_eq!(route.nodes, vec![a, c, d]);
+        assert_eq!(route.cost, 9 * 3600 + 20 * 60 - (7 * 3600 + 30 * 60));
+        assert_eq!(monday.earliest_arrival(&mut ws, a, b, 7 * 3600), Some(8 * 3600 + 15 * 60));
+        assert_eq!(monday.earliest_arrival(&mut ws, a, b, 8 * 3600 + 1), None);
+        // Past midnight of the service date.
+        assert_eq!(monday.earliest_arrival(&mut ws, a, c, 9 * 3600), Some(24 * 3600 + 30 * 60));
+        assert_eq!(monday.earliest_arrival(&mut ws, d, a, 0), None);
+
+        let sunday = TransitGraph::new(&timetable, 20211031);
+        assert_eq!(sunday.num_connections(), 1);
+        assert_eq!(sunday.earliest_arrival(&mut ws, a, d, 0), Some(11 * 3600));
+        // A holiday without the weekday trips.
+        assert_eq!(TransitGraph::new(&timetable, 20211101).num_connections(), 0);
+
+        // min_transfer_time is optional, but needed for transfers of type 2.
+        let transfers = "from_stop_id,to_stop_id,transfer_type\nA,A,1\nB,C,0\n".to_string();
+        let with_transfers = Timetable::from_feed(&GtfsFeed { transfers, ..feed.clone() }).unwrap();
+        assert_eq!(with_transfers.min_transfer_times, vec![0; 4]);
+        assert!(with_transfers.footpaths.iter().all(|footpaths| footpaths.is_empty()));
+        let transfers = "from_stop_id,to_stop_id,transfer_type\nA,A,2\n".to_string();
+        assert!(Timetable::from_feed(&GtfsFeed { transfers, ..feed.clone() }).is_err());
+        let stops = timetable.stop_network();
+        assert!(route.length(&stops) > stops.nodes[a].distance(&stops.nodes[d]));
+
+        assert_eq!(gtfs::previous_date(20211026), 20211025);
+        assert_eq!(gtfs::previous_date(20211101), 20211031);
+        assert_eq!(gtfs::previous_date(20200301), 20200229);
+        assert_eq!(gtfs::previous_date(20210301), 20210228);
+        assert_eq!(gtfs::previous_date(20210101), 20201231);
+
+        // A feed with the dates in calendar_dates.txt only, and a night trip
+        // that goes on after midnight.
+        let dir = std::env::temp_dir().join(format!("gtfs-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+        write("stops.txt", &feed.stops);
+        write("trips.txt", "route_id,service_id,trip_id\nN,saturday_night,night\n");
+        write("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+            night,23:30:00,23:30:00,A,1\nnight,24:10:00,24:10:00,B,2\nnight,24:40:00,24:40:00,C,3\n");
+        write("calendar_dates.txt", "service_id,date,exception_type\nsaturday_night,20211030,1\n");
+        let night = Timetable::read_gtfs_dir(dir.to_str().unwrap());
+        std::fs::remove_file(dir.join("calendar_dates.txt")).unwrap();
+        assert!(GtfsFeed::read_dir(dir.to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        let night = night.unwrap();
+        assert!(night.runs_on(0, 20211030) && !night.runs_on(0, 20211031));
+        let saturday = TransitGraph::new(&night, 20211030);
+        assert_eq!(saturday.earliest_arrival(&mut ws, a, c, 23 * 3600), Some(24 * 3600 + 40 * 60));
+        // On Sunday, the rest of the trip after midnight.
+        let sunday = TransitGraph::new(&night, 20211031);
+        assert_eq!(sunday.num_connections(), 1);
+        assert_eq!(sunday.earliest_arrival(&mut ws, b, c, 0), Some(40 * 60));
+        assert_eq!(sunday.earliest_arrival(&mut ws, a, c, 0), None);
+        assert_eq!(TransitGraph::new(&night, 20211101).num_connections(), 0);
+    }
+
+    #[test]
+    fn test_connection_scan_and_raptor() {
+        use efficient_route_planning::connection_scan::ConnectionScan;
+        use efficient_route_planning::gtfs::{GtfsFeed, Timetable};
+        use efficient_route_planning::osm;
+        use efficient_route_planning::raptor::Raptor;
+        use efficient_route_planning::transit::{Leg, Mode};
+
+        let feed = GtfsFeed {
+            stops: "stop_id,stop_name,stop_lat,stop_lon\nA,A,48.0,7.8\nB,B,48.1,7.8\nB2,B2,48.1009,7.8\nC,C,48.2,7.8\nD,D,48.3,7.9\n".to_string(),
+            trips: "trip_id,route_id,service_id\nt1,1,daily\nt2,2,daily\nt3,2,daily\nt4,3,daily\nt5,4,daily\nt6,5,daily\n".to_string(),
+            stop_times: "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                t1,08:00:00,08:00:00,A,1\nt1,08:20:00,08:20:00,B,2\n\
+                t2,08:22:00,08:22:00,B,1\nt2,08:40:00,08:40:00,C,2\n\
+                t3,08:30:00,08:30:00,B,1\nt3,08:50:00,08:50:00,C,2\n\
+                t4,08:26:00,08:26:00,B2,1\nt4,08:35:00,08:35:00,C,2\n\
+                t5,07:55:00,07:55:00,A,1\nt5,09:30:00,09:30:00,C,2\n\
+                t6,08:45:00,08:45:00,C,1\nt6,09:10:00,09:10:00,D,2\n".to_string(),
+            calendar: "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                daily,1,1,1,1,1,1,1,20210101,20211231\n".to_string(),
+            calendar_dates: String::new(),
+            transfers: "from_stop_id,to_stop_id,transfer_type,min_transfer_time\nB,B,2,300\nB,B2,2,240\nC,C,1,\n".to_string(),
+        };
+        let mut timetable = Timetable::from_feed(&feed).unwrap();
+        let stop = |id: &str| timetable.stop_index(id).unwrap();
+        let (a, b, b2, c, d) = (stop("A"), stop("B"), stop("B2"), stop("C"), stop("D"));
+        let trip = |id: &str| timetable.trips.iter().position(|trip| trip.id == id).unwrap();
+        let date = 20211025;
+
+        // Five minutes to change at B are too short for t2, but the footpath
+        // to B2 catches t4.
+        let csa = ConnectionScan::new(&timetable, date);
+        assert_eq!(csa.connections().len(), 6);
+        let journey = csa.compute_journey(&timetable, a, c, 7 * 3600 + 50 * 60).unwrap();
+        assert_eq!(journey.arrival, 8 * 3600 + 35 * 60);
+        assert_eq!(journey.legs.iter().map(|leg| leg.mode).collect::<Vec<_>>(), vec![Mode::Transit(trip("t1")), Mode::Walk, Mode::Transit(trip("t4"))]);
+        assert_eq!(journey.route(a).nodes, vec![a, b, b2, c]);
+        assert_eq!(journey.num_transfers(), 1);
+
+        let raptor = Raptor::new(&timetable, date);
+        let journeys = raptor.compute_journeys(&timetable, a, c, 7 * 3600 + 50 * 60, 5);
+        assert_eq!(journeys.iter().map(|journey| (journey.num_transfers(), journey.arrival)).collect::<Vec<_>>(),
+            vec![(0, 9 * 3600 + 30 * 60), (1, 8 * 3600 + 35 * 60)]);
+        assert_eq!(journeys[1], journey);
+        assert_eq!(raptor.compute_journeys(&timetable, a, c, 7 * 3600 + 50 * 60, 0).len(), 1);
+        assert_eq!(raptor.compute_journeys(&timetable, a, d, 7 * 3600 + 50 * 60, 5).last().unwrap().num_transfers(), 2);
+
+        // Without the transfer time t2 is fine, without the footpath t4 is not.
+        timetable.min_transfer_times[b] = 0;
+        timetable.footpaths[b].clear();
+        assert_eq!(ConnectionScan::new(&timetable, date).earliest_arrival(&timetable, a, c, 0), Some(8 * 3600 + 40 * 60));
+        timetable.min_transfer_times[b] = 600;
+        assert_eq!(Raptor::new(&timetable, date).earliest_arrival(&timetable, a, c, 0), Some(8 * 3600 + 50 * 60));
+
+        // Nearby stops get footpaths (B and B2 are 100 m apart).
+        timetable.add_footpaths(500.0, osm::WALKING_SPEED);
+        assert_eq!(timetable.footpaths[b].iter().map(|footpath| footpath.to).collect::<Vec<_>>(), vec![b2]);
+        assert_eq!(timetable.footpaths[b2][0].duration, 72);
+        assert!(timetable.footpaths[a].is_empty());
+
+        // Both agree on every query.
+        let (csa, raptor) = (ConnectionScan::new(&timetable, date), Raptor::new(&timetable, date));
+        for s in 0..timetable.stops.len() {
+            for t in 0..timetable.stops.len() {
+                for departure in (7 * 3600..9 * 3600).step_by(600) {
+                    let journey = csa.compute_journey(&timetable, s, t, departure);
+                    assert_eq!(journey.as_ref().map(|journey| journey.arrival), raptor.earliest_arrival(&timetable, s, t, departure));
+                    if let Some(journey) = journey {
+                        let mut time = departure;
+                        for leg in &journey.legs {
+                            assert!(leg.departure >= time && leg.arrival >= leg.departure);
+                            time = leg.arrival;
+                        }
+                        assert_eq!(time, journey.arrival);
+                    }
+                }
+            }
+        }
+
+        // The night trip of test_gtfs_transit, on Saturday and Sunday. After
+        // midnight, the rest of the trip of the day before runs too.
+        let night = Timetable::from_feed(&GtfsFeed {
+            trips: "route_id,service_id,trip_id\nN,weekend_nights,night\n".to_string(),
+            stop_times: "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                night,23:30:00,23:30:00,A,1\nnight,24:10:00,24:10:00,B,2\nnight,24:40:00,24:40:00,C,3\n".to_string(),
+            calendar: String::new(),
+            calendar_dates: "service_id,date,exception_type\nweekend_nights,20211030,1\nweekend_nights,20211031,1\n".to_string(),
+            transfers: String::new(),
+            ..feed
+        }).unwrap();
+        // (date, connections, arrival at C from A at 23:00 and from B at 0:00)
+        let dates = [(20211030, 2, Some(24 * 3600 + 40 * 60), Some(24 * 3600 + 40 * 60)),
+            (20211031, 3, Some(24 * 3600 + 40 * 60), Some(40 * 60)),
+            (20211101, 1, None, Some(40 * 60))];
+        for (date, num_connections, on_the_day, after_midnight) in dates {
+            let (csa, raptor) = (ConnectionScan::new(&night, date), Raptor::new(&night, date));
+            assert_eq!(csa.connections().len(), num_connections);
+            assert_eq!(csa.earliest_arrival(&night, a, c, 23 * 3600), on_the_day);
+            assert_eq!(raptor.earliest_arrival(&night, a, c, 23 * 3600), on_the_day);
+            assert_eq!(csa.earliest_arrival(&night, b, c, 0), after_midnight);
+            assert_eq!(raptor.earliest_arrival(&night, b, c, 0), after_midnight);
+            assert_eq!(csa.earliest_arrival(&night, a, b, 0), on_the_day.map(|_| 24 * 3600 + 10 * 60));
+        }
+        let journey = ConnectionScan::new(&night, 20211101).compute_journey(&night, b, c, 0).unwrap();
+        assert_eq!(journey.legs, vec![Leg { mode: Mode::Transit(0), from: b, to: c, departure: 10 * 60, arrival: 40 * 60 }]);
+    }
+
+    #[test]
+    fn test_multimodal() {
+        use efficient_route_planning::gtfs::{GtfsFeed, Timetable};
+        use efficient_route_planning::dijkstra::SearchWorkspace;
+        use efficient_route_planning::multimodal::{AccessMode, LegMode, MultimodalParameters, MultimodalRouter, Place};
+
+        // Grid node (i, j) is at 48.0 + 0.001 i, 7.8 + 0.0015 j, about 111 m
+        // apart, 80 s to walk.
+        let rn = grid_graph(10);
+        let feed = GtfsFeed {
+            stops: "stop_id,stop_name,stop_lat,stop_lon\nS1,S1,48.00005,7.8015\nS2,S2,48.004,7.806\nS3,S3,48.004,7.809\nS4,S4,48.009,7.812\n".to_string(),
+            trips: "trip_id,route_id,service_id\nA,1,daily\nB,2,daily\n".to_string(),
+            stop_times: "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                A,08:05:00,08:05:00,S1,1\nA,08:10:00,08:10:00,S2,2\nB,08:15:00,08:15:00,S3,1\nB,08:20:00,08:20:00,S4,2\n".to_string(),
+            calendar: "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                daily,1,1,1,1,1,1,1,20210101,20211231\n".to_string(),
+            ..GtfsFeed::default()
+        };
+        let timetable = Timetable::from_feed(&feed).unwrap();
+        let parameters = MultimodalParameters { max_walk: 600, max_transfer_walk: 300, max_drive: 600, num_threads: 2 };
+        let router = MultimodalRouter::new(&rn, timetable, 20211025, parameters);
+        let stop = |id: &str| router.timetable().stop_index(id).unwrap();
+        let (s1, s2, s3, s4) = (stop("S1"), stop("S2"), stop("S3"), stop("S4"));
+        assert_eq!((router.stop_node(s1), router.stop_node(s2), router.stop_node(s4)), (1, 44, 98));
+        assert!(router.timetable().footpaths[s2].iter().any(|footpath| footpath.to == s3));
+        assert!(router.timetable().footpaths[s1].is_empty());
+
+        // Walk to S1, ride A, walk to S3, ride B, walk to the corner.
+        let mut workspaces = [SearchWorkspace::new(rn.nodes.len()), SearchWorkspace::new(rn.nodes.len())];
+        let journey = router.compute_journey(&rn, &mut workspaces, 0, 99, 8 * 3600, AccessMode::Walk).unwrap();
+        let modes: Vec<LegMode> = journey.legs.iter().map(|leg| leg.mode).collect();
+        assert_eq!(modes, vec![LegMode::Walk, LegMode::Transit(0), LegMode::Walk, LegMode::Transit(1), LegMode::Walk]);
+        assert_eq!(journey.num_transfers(), 1);
+        assert_eq!((journey.legs[0].from, journey.legs[0].to), (Place::Node(0), Place::Stop(s1)));
+        assert_eq!(journey.legs[0].nodes, vec![0, 1]);
+        assert_eq!((journey.legs[4].from, journey.legs[4].to), (Place::Stop(s4), Place::Node(99)));
+        assert_eq!(journey.legs[4].nodes, vec![98, 99]);
+        let mut time = journey.departure;
+        for leg in &journey.legs {
+            assert!(leg.departure >= time && leg.arrival >= leg.departure);
+            time = leg.arrival;
+        }
+        assert_eq!(time, journey.arrival);
+        assert!(journey.arrival > 8 * 3600 + 20 * 60 && journey.arrival < 8 * 3600 + 22 * 60);
+
+        // Too far to walk without the trips, the car is faster.
+        assert_eq!(router.compute_journey(&rn, &mut workspaces, 0, 99, 9 * 3600, AccessMode::Walk), None);
+        let drive = router.compute_journey(&rn, &mut workspaces, 0, 99, 8 * 3600, AccessMode::Drive).unwrap();
+        assert_eq!(drive.legs[0].mode, LegMode::Drive);
+        assert!(drive.arrival < journey.arrival);
+        // The workspaces are reused from query to query.
+        assert_eq!(router.compute_journey(&rn, &mut workspaces, 0, 99, 8 * 3600, AccessMode::Walk), Some(journey));
+        // Close enough to walk.
+        let walk = router.compute_journey(&rn, &mut workspaces, 0, 2, 9 * 3600, AccessMode::Walk).unwrap();
+        assert_eq!(walk.legs.len(), 1);
+        assert_eq!(walk.legs[0].nodes, vec![0, 1, 2]);
+    }
 }
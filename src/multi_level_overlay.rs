@@ -0,0 +1,441 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Multi-level overlay graphs, as in customizable route planning (CRP), after
+// D. Delling, A. V. Goldberg, T. Pajor, R. F. Werneck: "Customizable Route
+// Planning in Road Networks", 2011.
+//
+// arc_flags.rs works with a single region given by the user. Here the whole
+// network is partitioned into cells automatically, on several nested levels:
+// cells of level 0 are the smallest, each cell of level l + 1 is a union of
+// cells of level l. A node with an arc to another cell of level l is a boundary
+// node of level l.
+//
+// - partition is metric-independent: it splits the nodes geometrically in
+//   halves (in the direction that cuts the fewest edges) until the parts are
+//   small enough for the cells of each level, see OverlayParameters.
+// - customize computes, for every cell, the clique matrix: the cost from each
+//   of its boundary nodes to each other one, on paths inside the cell. For
+//   level 0 this is a Dijkstra inside the cell. For higher levels it runs on
+//   the overlay of the level below (the boundary nodes of the subcells, with
+//   their clique arcs and the arcs between the subcells), so it never looks at
+//   the inside of the subcells again. Cells of a level are independent and are
+//   customized on several threads. Closed arcs (see traffic.rs) are left out.
+// - A query from s to t is a bidirectional Dijkstra on a graph that depends
+//   on s and t: at a node v it uses the highest level l at which the cell of v
+//   contains neither s nor t, and relaxes the clique arcs of that cell and the
+//   arcs that leave it. Nodes in the cell of s or t of level 0 use the arcs of
+//   the network. The clique arcs on the resulting path are unpacked by a
+//   search inside their cell, one level down at a time.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Instant;
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::parallel;
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
+use crate::traffic::CostChanges;
+
+// The cost of a pair of boundary nodes without a path inside the cell, and the
+// marker for "not a boundary node".
+const INFINITY: usize = usize::MAX;
+const NONE: usize = usize::MAX;
+
+// The cell sizes used by algorithm_by_name.
+pub const DEFAULT_CELL_SIZES: [usize; 3] = [256, 4096, 65536];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OverlayParameters {
+    // The maximum number of nodes of a cell, for each level from the lowest
+    // one up. Must be positive and strictly increasing.
+    pub cell_sizes: Vec<usize>,
+
+    // The number of threads used by customize.
+    pub num_threads: usize,
+}
+
+// An arc of the search graphs: the head (or tail, for backward searches), the
+// cost, and the level of the clique it belongs to (None for an arc of the
+// network).
+type OverlayArc = (usize, usize, Option<usize>);
+
+// The nodes reached by a search, with their cost, the previous node and the
+// kind of the arc from there (see OverlayArc).
+type Reached = HashMap<usize, (usize, usize, Option<usize>)>;
+
+struct Cell {
+    boundary: Vec<usize>,
+
+    // matrix[i * k + j] is the cost from boundary[i] to boundary[j] inside the
+    // cell, with k boundary nodes.
+    matrix: Vec<usize>,
+}
+
+struct Level {
+    // The cell of each node, and its position in the boundary of that cell
+    // (NONE if it is not a boundary node of this level).
+    cell: Vec<usize>,
+    boundary_index: Vec<usize>,
+    cells: Vec<Cell>,
+}
+
+pub struct MultiLevelOverlay {
+    parameters: OverlayParameters,
+    levels: Vec<Level>,
+
+    // reverse_arcs[v] are the arcs into v, as (tail, position in the
+    // adjacency list of the tail), so that their current cost can be looked up.
+    reverse_arcs: Vec<Vec<(usize, usize)>>,
+
+    // Whether arc costs changed since the last customization.
+    stale: bool,
+}
+
+impl MultiLevelOverlay {
+    // Not yet partitioned, see ShortestPathAlgorithm::preprocess. Panics if the
+    // cell sizes are not positive and strictly increasing (a cell size of 0
+    // would split a cell forever).
+    pub fn new(parameters: OverlayParameters) -> MultiLevelOverlay {
+        let sizes = &parameters.cell_sizes;
+        assert!(sizes.first().is_none_or(|&size| size >= 1), "the cell sizes must be positive, not {:?}", sizes);
+        assert!(sizes.windows(2).all(|w| w[0] < w[1]), "the cell sizes must be strictly increasing, not {:?}", sizes);
+        MultiLevelOverlay { parameters, levels: vec![], reverse_arcs: vec![], stale: false }
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    // The number of cells and of boundary nodes of the given level.
+    pub fn num_cells(&self, level: usize) -> usize {
+        self.levels[level].cells.len()
+    }
+
+    pub fn num_boundary_nodes(&self, level: usize) -> usize {
+        self.levels[level].cells.iter().map(|cell| cell.boundary.len()).sum()
+    }
+
+    // The cell of node u on the given level.
+    pub fn cell(&self, level: usize, u: usize) -> usize {
+        self.levels[level].cell[u]
+    }
+
+    // The metric-independent phase, see above. The network may change its
+    // arc costs afterwards, but not its nodes or arcs. Must be followed by
+    // customize.
+    pub fn partition(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        let n = rn.nodes.len();
+        let num_levels = self.parameters.cell_sizes.len();
+        let mut neighbors: Vec<Vec<usize>> = vec![vec![]; n];
+        self.reverse_arcs = vec![vec![]; n];
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for (j, arc) in arcs.iter().enumerate() {
+                neighbors[u].push(arc.idx);
+                neighbors[arc.idx].push(u);
+                self.reverse_arcs[arc.idx].push((u, j));
+            }
+        }
+
+        let coordinates: Vec<(f64, f64)> = rn.nodes.iter().map(|node| (node.longitude * node.latitude.cos(), node.latitude)).collect();
+        let mut cell = vec![vec![0; n]; num_levels];
+        let mut num_cells = vec![0; num_levels];
+        let mut side = vec![0u8; n];
+        if num_levels > 0 {
+            self.split_into_cells((0..n).collect(), num_levels - 1, &neighbors, &coordinates, &mut side, &mut cell, &mut num_cells);
+        }
+
+        self.levels = cell.into_iter().zip(num_cells).map(|(cell, num_cells)| {
+            let mut cells: Vec<Cell> = (0..num_cells).map(|_| Cell { boundary: vec![], matrix: vec![] }).collect();
+            let mut boundary_index = vec![NONE; n];
+            for u in 0..n {
+                if neighbors[u].iter().any(|&v| cell[v] != cell[u]) {
+                    boundary_index[u] = cells[cell[u]].boundary.len();
+                    cells[cell[u]].boundary.push(u);
+                }
+            }
+            Level { cell, boundary_index, cells }
+        }).collect();
+
+        PreprocessingStats { elapsed: now.elapsed(), size_in_bytes: self.size_in_bytes(), ..PreprocessingStats::default() }
+    }
+
+    // Assign the given nodes to cells of the given level and all levels below,
+    // halving them until they fit.
+    #[allow(clippy::too_many_arguments)]
+    fn split_into_cells(&self, nodes: Vec<usize>, level: usize, neighbors: &[Vec<usize>], coordinates: &[(f64, f64)],
+                        side: &mut [u8], cell: &mut [Vec<usize>], num_cells: &mut [usize]) {
+        if nodes.len() <= self.parameters.cell_sizes[level] {
+            for &u in &nodes { cell[level][u] = num_cells[level]; }
+            num_cells[level] += 1;
+            if level > 0 {
+                self.split_into_cells(nodes, level - 1, neighbors, coordinates, side, cell, num_cells);
+            }
+            return;
+        }
+
+        // Try a few cut directions and keep the one that cuts the fewest edges.
+        let mut best: Option<(usize, Vec<usize>, Vec<usize>)> = None;
+        for &(dx, dy) in &[(1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, -1.0)] {
+            let mut sorted = nodes.clone();
+            sorted.sort_by(|&u, &v| {
+                let key = |w: usize| dx * coordinates[w].0 + dy * coordinates[w].1;
+                key(u).total_cmp(&key(v))
+            });
+            let right = sorted.split_off(sorted.len() / 2);
+            let left = sorted;
+            for &u in &right { side[u] = 1; }
+            let cut = left.iter().map(|&u| neighbors[u].iter().filter(|&&v| side[v] == 1).count()).sum();
+            for &u in &right { side[u] = 0; }
+            if best.as_ref().is_none_or(|&(best_cut, _, _)| cut < best_cut) {
+                best = Some((cut, left, right));
+            }
+        }
+        let (_, left, right) = best.unwrap();
+        self.split_into_cells(left, level, neighbors, coordinates, side, cell, num_cells);
+        self.split_into_cells(right, level, neighbors, coordinates, side, cell, num_cells);
+    }
+
+    // The metric-dependent phase for the current arc costs, see above.
+    pub fn customize(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        let mut stats = PreprocessingStats::default();
+        for l in 0..self.levels.len() {
+            let mut matrices = vec![vec![]; self.levels[l].cells.len()];
+            let mut searches = 0;
+            parallel::run_jobs(
+                self.parameters.num_threads,
+                matrices.len(),
+                Vec::new,
+                |arcs, c| self.clique_matrix(rn, l, c, arcs),
+                |c, matrix| {
+                    searches += self.levels[l].cells[c].boundary.len();
+                    matrices[c] = matrix;
+                });
+            for (cell, matrix) in self.levels[l].cells.iter_mut().zip(matrices) {
+                cell.matrix = matrix;
+            }
+            stats.searches += searches;
+        }
+        self.stale = false;
+        stats.elapsed = now.elapsed();
+        stats.size_in_bytes = self.size_in_bytes();
+        stats
+    }
+
+    // The clique matrix of cell c of level l, from the matrices of level l - 1.
+    fn clique_matrix(&self, rn: &RoadNetwork, l: usize, c: usize, arcs: &mut Vec<OverlayArc>) -> Vec<usize> {
+        let boundary = &self.levels[l].cells[c].boundary;
+        let mut matrix = vec![INFINITY; boundary.len() * boundary.len()];
+        for (i, &b) in boundary.iter().enumerate() {
+            let reached = self.search_in_cell(rn, l, b, None, arcs);
+            for (j, &b2) in boundary.iter().enumerate() {
+                if let Some(&(cost, _, _)) = reached.get(&b2) { matrix[i * boundary.len() + j] = cost; }
+            }
+        }
+        matrix
+    }
+
+    // A Dijkstra from u inside its cell of level l, on the overlay of level
+    // l - 1 (on the network for level 0), until target is settled. The result
+    // has the cost, the previous node and the kind of arc for each settled node.
+    fn search_in_cell(&self, rn: &RoadNetwork, l: usize, u: usize, target: Option<usize>, arcs: &mut Vec<OverlayArc>) -> Reached {
+        let c = self.levels[l].cell[u];
+        let mut reached = Reached::new();
+        let mut settled = Reached::new();
+        let mut queue = BinaryHeap::new();
+        reached.insert(u, (0, NONE, None));
+        queue.push(Reverse((0, u)));
+        while let Some(Reverse((cost, v))) = queue.pop() {
+            if settled.contains_key(&v) || reached[&v].0 != cost { continue; }
+            settled.insert(v, reached[&v]);
+            if Some(v) == target { break; }
+            arcs.clear();
+            self.push_arcs(rn, v, l.checked_sub(1), true, arcs);
+            for &(w, arc_cost, clique) in arcs.iter() {
+                if self.levels[l].cell[w] != c { continue; }
+                if reached.get(&w).is_none_or(|&(d, _, _)| cost + arc_cost < d) {
+                    reached.insert(w, (cost + arc_cost, v, clique));
+                    queue.push(Reverse((cost + arc_cost, w)));
+                }
+            }
+        }
+        settled
+    }
+
+    // The arcs out of u (into u if not forward) in the search graph where u
+    // is on the given level, see above: the clique arcs of its cell and the
+    // arcs that leave the cell, or all arcs of the network for None.
+    fn push_arcs(&self, rn: &RoadNetwork, u: usize, level: Option<usize>, forward: bool, arcs: &mut Vec<OverlayArc>) {
+        let (c, i) = match level {
+            Some(l) if self.levels[l].boundary_index[u] != NONE => (self.levels[l].cell[u], self.levels[l].boundary_index[u]),
+            _ => {
+                self.push_network_arcs(rn, u, forward, |_| true, arcs);
+                return;
+            },
+        };
+        let l = level.unwrap();
+        let cell = &self.levels[l].cells[c];
+        let k = cell.boundary.len();
+        for (j, &v) in cell.boundary.iter().enumerate() {
+            let cost = if forward { cell.matrix[i * k + j] } else { cell.matrix[j * k + i] };
+            if j != i && cost != INFINITY { arcs.push((v, cost, Some(l))); }
+        }
+        self.push_network_arcs(rn, u, forward, |v| self.levels[l].cell[v] != c, arcs);
+    }
+
+    fn push_network_arcs<F: Fn(usize) -> bool>(&self, rn: &RoadNetwork, u: usize, forward: bool, keep: F, arcs: &mut Vec<OverlayArc>) {
+        if forward {
+            arcs.extend(rn.adjacent_arcs[u].iter().filter(|arc| !arc.closed && keep(arc.idx)).map(|arc| (arc.idx, arc.cost, None)));
+        } else {
+            arcs.extend(self.reverse_arcs[u].iter()
+                .map(|&(v, j)| (v, &rn.adjacent_arcs[v][j]))
+                .filter(|&(v, arc)| !arc.closed && keep(v))
+                .map(|(v, arc)| (v, arc.cost, None)));
+        }
+    }
+
+    // The highest level on which the cell of v contains neither s nor t.
+    fn query_level(&self, v: usize, s: usize, t: usize) -> Option<usize> {
+        (0..self.levels.len()).rev().find(|&l| {
+            let cell = &self.levels[l].cell;
+            cell[v] != cell[s] && cell[v] != cell[t]
+        })
+    }
+
+    // The shortest route from s to t, see above. The settled nodes in the
+    // workspace are those of both searches.
+    pub fn compute_shortest_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        let now = Instant::now();
+        ws.start_query();
+        // For each direction: the reached nodes with cost, previous node (the
+        // next one for backward) and the kind of arc, and the queue.
+        let mut reached = [Reached::new(), Reached::new()];
+        let mut settled: [HashMap<usize, usize>; 2] = [HashMap::new(), HashMap::new()];
+        let mut queues = [BinaryHeap::new(), BinaryHeap::new()];
+        reached[0].insert(s, (0, NONE, None));
+        reached[1].insert(t, (0, NONE, None));
+        queues[0].push(Reverse((0, s)));
+        queues[1].push(Reverse((0, t)));
+        let mut best: Option<(usize, usize)> = None;
+        let mut arcs = vec![];
+
+        loop {
+            // The stopping criterion of bidirectional Dijkstra.
+            let keys = [queues[0].peek().map(|e| e.0.0), queues[1].peek().map(|e| e.0.0)];
+            let (Some(key0), Some(key1)) = (keys[0], keys[1]) else { break };
+            if best.is_some_and(|(cost, _)| key0 + key1 >= cost) { break; }
+            let d = if key0 <= key1 { 0 } else { 1 };
+            let Reverse((cost, v)) = queues[d].pop().unwrap();
+            if settled[d].contains_key(&v) || reached[d][&v].0 != cost { continue; }
+            settled[d].insert(v, cost);
+            ws.settle(v);
+
+            arcs.clear();
+            self.push_arcs(rn, v, self.query_level(v, s, t), d == 0, &mut arcs);
+            for &(w, arc_cost, clique) in &arcs {
+                ws.stats_mut().relaxed_arcs += 1;
+                let new_cost = cost + arc_cost;
+                if reached[d].get(&w).is_none_or(|&(g, _, _)| new_cost < g) {
+                    reached[d].insert(w, (new_cost, v, clique));
+                    queues[d].push(Reverse((new_cost, w)));
+                    ws.stats_mut().queue_pushes += 1;
+                }
+                if let Some(&(other, _, _)) = reached[1 - d].get(&w) {
+                    let total = reached[d][&w].0 + other;
+                    if best.is_none_or(|(cost, _)| total < cost) { best = Some((total, w)); }
+                }
+            }
+        }
+        ws.stats_mut().elapsed = now.elapsed();
+        let (cost, meeting) = if s == t { (0, s) } else { best? };
+
+        // The path as (node, kind of the arc to the next node).
+        let mut hops = vec![];
+        let mut v = meeting;
+        while v != s {
+            let (_, previous, clique) = reached[0][&v];
+            hops.push((previous, clique));
+            v = previous;
+        }
+        hops.reverse();
+        let mut v = meeting;
+        while v != t {
+            let (_, next, clique) = reached[1][&v];
+            hops.push((v, clique));
+            v = next;
+        }
+        hops.push((t, None));
+
+        let mut nodes = vec![s];
+        let mut arcs = vec![];
+        for pair in hops.windows(2) {
+            self.unpack(rn, pair[0].0, pair[1].0, pair[0].1, &mut nodes, &mut arcs);
+        }
+        Some(Route { cost, nodes })
+    }
+
+    // Append the nodes of the arc from u to v, without u. For a clique arc of
+    // level l this searches the cell on the overlay of level l - 1, and
+    // unpacks the arcs found there.
+    fn unpack(&self, rn: &RoadNetwork, u: usize, v: usize, clique: Option<usize>, nodes: &mut Vec<usize>, arcs: &mut Vec<OverlayArc>) {
+        let l = match clique {
+            Some(l) => l,
+            None => { nodes.push(v); return; },
+        };
+        let reached = self.search_in_cell(rn, l, u, Some(v), arcs);
+        let mut hops = vec![];
+        let mut w = v;
+        while w != u {
+            let (_, previous, clique) = reached[&w];
+            hops.push((previous, w, clique));
+            w = previous;
+        }
+        for &(a, b, clique) in hops.iter().rev() {
+            self.unpack(rn, a, b, clique, nodes, arcs);
+        }
+    }
+
+    // The memory used by the cells, clique matrices and reverse arcs.
+    pub fn size_in_bytes(&self) -> usize {
+        let words: usize = self.levels.iter().map(|level| {
+            level.cell.len() + level.boundary_index.len() + level.cells.iter().map(|cell| cell.boundary.len() + cell.matrix.len()).sum::<usize>()
+        }).sum();
+        let reverse_arcs: usize = self.reverse_arcs.iter().map(|arcs| arcs.len()).sum();
+        words * std::mem::size_of::<usize>() + reverse_arcs * std::mem::size_of::<(usize, usize)>()
+    }
+}
+
+impl ShortestPathAlgorithm for MultiLevelOverlay {
+    fn name(&self) -> &str {
+        "crp"
+    }
+
+    // Both phases. After a change of the arc costs only, customize is enough.
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let mut stats = self.partition(rn);
+        let customization = self.customize(rn);
+        stats.elapsed += customization.elapsed;
+        stats.searches = customization.searches;
+        stats.size_in_bytes = customization.size_in_bytes;
+        stats
+    }
+
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        if self.stale {
+            let cost = Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0);
+            return Route::from_workspace(ws, cost, t);
+        }
+        self.compute_shortest_route(rn, ws, s, t)
+    }
+
+    // Any change needs a new customization (but not a new partition).
+    fn costs_changed(&mut self, changes: &CostChanges) {
+        if !changes.is_empty() { self.stale = true; }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
+}
@@ -0,0 +1,314 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Journeys that combine the road network with a transit timetable: walk from
+// the start to a stop, ride one or more trips (changing on foot between
+// stops), and walk from the last stop to the destination. Or drive to the
+// first stop instead of walking (park and ride).
+//
+// - Walking uses the foot profile of the road network (see foot_profile): the
+//   same roads at walking speed, in both directions, without motorways and
+//   trunk roads.
+// - Each stop is linked to its nearest node of the foot profile, the walk
+//   from the stop to that node (straight line) is added to every walk to or
+//   from the stop.
+// - Footpaths between stops are walks in the foot profile of at most
+//   max_transfer_walk seconds, added to the ones of the timetable.
+// - A query runs a Dijkstra from the start (walking or driving, up to a time
+//   limit) and a walking Dijkstra from the destination, up to max_walk. The
+//   stops they reach are the sources and targets of a connection scan (see
+//   connection_scan.rs). Walking (or driving) all the way is considered too.
+
+use std::collections::HashMap;
+use crate::{Arc, Node, RoadNetwork};
+use crate::connection_scan::ConnectionScan;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::gtfs::Timetable;
+use crate::osm;
+use crate::parallel;
+use crate::transit::Mode;
+
+// How a journey gets from the start to the first stop.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessMode {
+    Walk,
+    // By car on the road network (park and ride).
+    Drive,
+}
+
+// How a leg of a journey gets from one place to the next: as transit::Mode,
+// or driving to the first stop.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LegMode {
+    Walk,
+    Drive,
+    // On the trip with this index in Timetable::trips.
+    Transit(usize),
+}
+
+impl From<AccessMode> for LegMode {
+    fn from(mode: AccessMode) -> Self {
+        match mode {
+            AccessMode::Walk => LegMode::Walk,
+            AccessMode::Drive => LegMode::Drive,
+        }
+    }
+}
+
+impl From<Mode> for LegMode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Walk => LegMode::Walk,
+            Mode::Transit(trip) => LegMode::Transit(trip),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MultimodalParameters {
+    // The longest walk (in seconds) from the start to the first stop and from
+    // the last stop to the destination, and between two stops.
+    pub max_walk: usize,
+    pub max_transfer_walk: usize,
+
+    // The longest drive to the first stop, for park and ride.
+    pub max_drive: usize,
+
+    // The number of threads used for the footpaths between stops.
+    pub num_threads: usize,
+}
+
+impl Default for MultimodalParameters {
+    fn default() -> Self {
+        MultimodalParameters { max_walk: 15 * 60, max_transfer_walk: 5 * 60, max_drive: 30 * 60, num_threads: parallel::available_threads() }
+    }
+}
+
+// Where a leg starts or ends: a node of the road network or a stop of the
+// timetable (the index).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Place {
+    Node(usize),
+    Stop(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultimodalLeg {
+    pub mode: LegMode,
+    pub from: Place,
+    pub to: Place,
+    pub departure: usize,
+    pub arrival: usize,
+
+    // For walking and driving, the nodes of the road network on the way.
+    pub nodes: Vec<usize>,
+}
+
+// As transit::Journey, with legs on the road network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultimodalJourney {
+    pub departure: usize,
+    pub arrival: usize,
+    pub legs: Vec<MultimodalLeg>,
+}
+
+impl MultimodalJourney {
+    pub fn num_transfers(&self) -> usize {
+        self.legs.iter().filter(|leg| matches!(leg.mode, LegMode::Transit(_))).count().saturating_sub(1)
+    }
+}
+
+// The road network at walking speed: both directions of every road except
+// motorways and trunk roads (the fastest ones, see osm.rs).
+pub fn foot_profile(rn: &RoadNetwork) -> RoadNetwork {
+    let mut walking = RoadNetwork::new();
+    for &node in &rn.nodes {
+        walking.add_node(node);
+    }
+    for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+        for arc in arcs.iter().filter(|arc| !arc.closed && arc.speed < osm::MAX_SPEED) {
+            let v = arc.idx;
+            let cost = rn.nodes[u].cost(&rn.nodes[v], osm::WALKING_SPEED);
+            for (tail, head) in [(u, v), (v, u)] {
+                if !walking.adjacent_arcs[tail].iter().any(|arc| arc.idx == head) {
+                    let head_node_id = rn.nodes[head].osm_id;
+                    walking.adjacent_arcs[tail].push(Arc { way_id: arc.way_id, ..Arc::new(head_node_id, head, cost, osm::WALKING_SPEED) });
+                }
+            }
+        }
+    }
+    walking
+}
+
+pub struct MultimodalRouter {
+    walking: RoadNetwork,
+    timetable: Timetable,
+    csa: ConnectionScan,
+    parameters: MultimodalParameters,
+
+    // For each stop, the nearest node of the foot profile and the time to
+    // walk there. The stops at each node.
+    stop_links: Vec<(usize, usize)>,
+    stops_at_node: HashMap<usize, Vec<usize>>,
+}
+
+impl MultimodalRouter {
+    // The router for the given network and the trips of the timetable that
+    // run on the given date (YYYYMMDD). The network must have nodes with
+    // roads to walk on.
+    pub fn new(rn: &RoadNetwork, mut timetable: Timetable, date: u32, parameters: MultimodalParameters) -> MultimodalRouter {
+        let walking = foot_profile(rn);
+        let stop_links = link_stops(&walking, &timetable);
+        let mut stops_at_node: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (stop, &(node, _)) in stop_links.iter().enumerate() {
+            stops_at_node.entry(node).or_default().push(stop);
+        }
+
+        // The footpaths, one walk in the foot profile per stop.
+        let n = walking.nodes.len();
+        parallel::run_jobs(
+            parameters.num_threads,
+            timetable.stops.len(),
+            || SearchWorkspace::new(n),
+            |ws, u| {
+                let (node, link) = stop_links[u];
+                Dijkstra { arc_flags: None }.compute_within_budget_with_workspace(ws, &walking.adjacent_arcs, node, parameters.max_transfer_walk);
+                let mut footpaths = vec![];
+                for &w in ws.settled_nodes() {
+                    for &v in stops_at_node.get(&w).into_iter().flatten().filter(|&&v| v != u) {
+                        let duration = link + ws.distance(w).unwrap() + stop_links[v].1;
+                        if duration <= parameters.max_transfer_walk { footpaths.push((v, duration)); }
+                    }
+                }
+                footpaths
+            },
+            |u, footpaths| {
+                for (v, duration) in footpaths {
+                    timetable.add_footpath(u, v, duration);
+                }
+            });
+
+        let csa = ConnectionScan::new(&timetable, date);
+        MultimodalRouter { walking, timetable, csa, parameters, stop_links, stops_at_node }
+    }
+
+    pub fn timetable(&self) -> &Timetable {
+        &self.timetable
+    }
+
+    pub fn walking_network(&self) -> &RoadNetwork {
+        &self.walking
+    }
+
+    // The node of the foot profile a stop is linked to.
+    pub fn stop_node(&self, stop: usize) -> usize {
+        self.stop_links[stop].0
+    }
+
+    // The journey with the earliest arrival at node t when leaving node s at
+    // the given time (seconds since midnight of the date), going to the first
+    // stop with the given mode (driving on rn). None if t cannot be reached
+    // within the limits of the parameters. The two workspaces must have been
+    // created for rn, the search from s uses the first and the one from t the
+    // second.
+    pub fn compute_journey(&self, rn: &RoadNetwork, workspaces: &mut [SearchWorkspace; 2], s: usize, t: usize, departure: usize,
+                           access: AccessMode) -> Option<MultimodalJourney> {
+        let (access_network, max_access) = match access {
+            AccessMode::Walk => (&self.walking, self.parameters.max_walk),
+            AccessMode::Drive => (rn, self.parameters.max_drive),
+        };
+        let dijkstra = Dijkstra { arc_flags: None };
+        let [access_ws, egress_ws] = workspaces;
+        dijkstra.compute_within_budget_with_workspace(access_ws, &access_network.adjacent_arcs, s, max_access);
+        // The foot profile is symmetric, so this is the walk to t.
+        dijkstra.compute_within_budget_with_workspace(egress_ws, &self.walking.adjacent_arcs, t, self.parameters.max_walk);
+
+        let within = |ws: &SearchWorkspace, u: usize, budget: usize| ws.distance(u).filter(|&d| ws.is_settled(u) && d <= budget);
+        let stops_within = |ws: &SearchWorkspace, budget: usize| {
+            ws.settled_nodes().iter()
+                .filter(|&&u| within(ws, u, budget).is_some())
+                .flat_map(|u| self.stops_at_node.get(u).into_iter().flatten())
+                .map(|&stop| (stop, ws.distance(self.stop_links[stop].0).unwrap() + self.stop_links[stop].1))
+                .collect::<Vec<_>>()
+        };
+        let sources: Vec<(usize, usize)> = stops_within(access_ws, max_access).into_iter().map(|(stop, d)| (stop, departure + d)).collect();
+        let targets = stops_within(egress_ws, self.parameters.max_walk);
+
+        // Without transit: all the way by the access mode, or walking.
+        let mut best: Option<MultimodalJourney> = None;
+        let mut consider = |journey: MultimodalJourney| {
+            if best.as_ref().is_none_or(|best| journey.arrival < best.arrival) { best = Some(journey); }
+        };
+        if let Some(d) = within(access_ws, t, max_access) {
+            let leg = MultimodalLeg { mode: access.into(), from: Place::Node(s), to: Place::Node(t), departure, arrival: departure + d, nodes: access_ws.path(t).unwrap() };
+            consider(MultimodalJourney { departure, arrival: departure + d, legs: vec![leg] });
+        }
+        if let Some(d) = within(egress_ws, s, self.parameters.max_walk) {
+            let mut nodes = egress_ws.path(s).unwrap();
+            nodes.reverse();
+            let leg = MultimodalLeg { mode: LegMode::Walk, from: Place::Node(s), to: Place::Node(t), departure, arrival: departure + d, nodes };
+            consider(MultimodalJourney { departure, arrival: departure + d, legs: vec![leg] });
+        }
+
+        if let Some((scan, last_stop)) = self.csa.scan(&self.timetable, &sources, &targets) {
+            let transit_legs = scan.legs(&self.csa, last_stop);
+            let first_stop = transit_legs.first().map_or(last_stop, |leg| leg.from);
+            let mut legs = vec![];
+
+            let (first_node, link) = self.stop_links[first_stop];
+            legs.push(MultimodalLeg { mode: access.into(), from: Place::Node(s), to: Place::Stop(first_stop), departure,
+                arrival: departure + access_ws.distance(first_node).unwrap() + link, nodes: access_ws.path(first_node).unwrap() });
+            // The search from s is no longer needed, its workspace is reused
+            // for the walks between stops.
+            let walk_ws = access_ws;
+            for leg in transit_legs {
+                let nodes = match leg.mode {
+                    Mode::Walk => {
+                        let (from, to) = (self.stop_links[leg.from].0, self.stop_links[leg.to].0);
+                        dijkstra.compute_shortest_path_with_workspace(walk_ws, &self.walking.adjacent_arcs, from, Some(to), |_,_| 0);
+                        walk_ws.path(to).unwrap_or_default()
+                    },
+                    Mode::Transit(_) => vec![],
+                };
+                legs.push(MultimodalLeg { mode: leg.mode.into(), from: Place::Stop(leg.from), to: Place::Stop(leg.to), departure: leg.departure, arrival: leg.arrival, nodes });
+            }
+            let (last_node, link) = self.stop_links[last_stop];
+            let mut nodes = egress_ws.path(last_node).unwrap();
+            nodes.reverse();
+            let arrival = scan.arrival[last_stop] + link + egress_ws.distance(last_node).unwrap();
+            legs.push(MultimodalLeg { mode: LegMode::Walk, from: Place::Stop(last_stop), to: Place::Node(t), departure: scan.arrival[last_stop], arrival, nodes });
+            consider(MultimodalJourney { departure, arrival, legs });
+        }
+        best
+    }
+}
+
+// The nearest node with a road to walk on for each stop. The nodes are
+// sorted by latitude, so only the ones in a narrow band around the stop are
+// looked at.
+fn link_stops(walking: &RoadNetwork, timetable: &Timetable) -> Vec<(usize, usize)> {
+    let mut by_latitude: Vec<usize> = (0..walking.nodes.len()).filter(|&u| !walking.adjacent_arcs[u].is_empty()).collect();
+    assert!(!by_latitude.is_empty(), "the network has no roads to walk on");
+    by_latitude.sort_unstable_by(|&u, &v| walking.nodes[u].latitude.total_cmp(&walking.nodes[v].latitude));
+    let meters_per_radian = 6371.0 * 1000.0;
+
+    timetable.stops.iter().map(|stop| {
+        let point = Node { osm_id: 0, latitude: stop.latitude, longitude: stop.longitude };
+        let i = by_latitude.partition_point(|&u| walking.nodes[u].latitude < point.latitude);
+        let (mut nearest, mut distance) = (by_latitude[i.min(by_latitude.len() - 1)], f64::INFINITY);
+        // Go up and down from i until the latitude alone is too far off.
+        let mut visit = |u: usize| {
+            if (walking.nodes[u].latitude - point.latitude).abs() * meters_per_radian > distance { return false; }
+            let d = point.distance(&walking.nodes[u]);
+            if d < distance { nearest = u; distance = d; }
+            true
+        };
+        for &u in &by_latitude[i..] {
+            if !visit(u) { break; }
+        }
+        for &u in by_latitude[..i].iter().rev() {
+            if !visit(u) { break; }
+        }
+        (nearest, point.cost(&walking.nodes[nearest], osm::WALKING_SPEED))
+    }).collect()
+}
@@ -8,19 +8,26 @@
 
 use crate::Node;
 use crate::RoadNetwork;
+use crate::region::Region;
 
 use std::str;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::borrow::Cow;
 use std::error::Error;
 use std::io::BufRead;
 use std::f64::consts::PI;
 use quick_xml::events::attributes::Attributes;
 use quick_xml::events::attributes::Attribute;
+use quick_xml::events::BytesStart;
 use quick_xml::Reader;
 use quick_xml::events::Event;
 
 pub const MAX_SPEED: usize = 110;
 
+// The speed of a pedestrian in km/h, e.g. for footpaths between transit stops.
+pub const WALKING_SPEED: usize = 5;
+
 //pub mod osm {
     fn add_node_from_event(rn: &mut RoadNetwork, attrs: &mut Attributes)  -> Result<(), Box<dyn Error>> {
         let mut n = Node { osm_id: 0, latitude: 0.0, longitude: 0.0, };
@@ -33,13 +40,13 @@ pub const MAX_SPEED: usize = 110;
                 _ => continue,
             }
         }
-        rn.node_id_to_index.entry(n.osm_id).or_insert(rn.nodes.len() as usize);
+        rn.node_id_to_index.entry(n.osm_id).or_insert(rn.nodes.len());
         rn.nodes.push(n);
         rn.adjacent_arcs.push(vec!());
         Ok(())
     }
 
-    fn add_edge_from_event<B: BufRead>(rn: &mut RoadNetwork, reader: &mut Reader<B>)  -> Result<(), Box<dyn Error>> {
+    fn add_edge_from_event<B: BufRead>(rn: &mut RoadNetwork, reader: &mut Reader<B>, way_id: usize)  -> Result<(), Box<dyn Error>> {
         let mut buf = Vec::new();
 
         // Save all the "nd ref" in this vec
@@ -66,7 +73,7 @@ pub const MAX_SPEED: usize = 110;
                             // RoadTypes"
                             if let Some(Ok(Attribute {key: b"k", value:Cow::Borrowed( b"highway") })) = iter.next() {
                                 if let Some(Ok(Attribute {key: b"v", value: v2})) = iter.next() {
-                                    if let Some(c) =  road_type_value(&v2.as_ref()) {
+                                    if let Some(c) =  road_type_value(v2.as_ref()) {
                                         speed = c as usize;
                                         break;
                                     }
@@ -86,7 +93,7 @@ pub const MAX_SPEED: usize = 110;
 
         if speed > 0 {
             for i in v_nodes.windows(2) {
-                rn.add_edge_calc_cost_from_speed(i[0], i[1], speed);
+                rn.add_way_edge(i[0], i[1], speed, way_id);
             }
         }
         Ok(())
@@ -105,7 +112,10 @@ pub const MAX_SPEED: usize = 110;
             match reader.read_event(&mut buf)? {
                 Event::Empty(e)|Event::Start(e) => match e.name() {
                     b"node" => add_node_from_event(&mut rn, &mut e.attributes())?,
-                    b"way" => add_edge_from_event(&mut rn, &mut reader)?,
+                    b"way" => {
+                        let way_id = attribute_value(&e, b"id")?.map_or(Ok(0), |id| id.parse())?;
+                        add_edge_from_event(&mut rn, &mut reader, way_id)?
+                    },
                     _ => (),
                 },
                 Event::Eof => break, // exits the loop when reaching end of file
@@ -117,6 +127,161 @@ pub const MAX_SPEED: usize = 110;
         }
         Ok(rn)
     }
+
+    fn attribute_value(e: &BytesStart, key: &[u8]) -> Result<Option<String>, Box<dyn Error>> {
+        for attr in e.attributes() {
+            let attr = attr?;
+            if attr.key == key {
+                return Ok(Some(str::from_utf8(&attr.value)?.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    // Read the children of a <relation> up to </relation>. Returns the ids of
+    // the member ways and whether the tags say boundary=administrative and
+    // name=<the given name>.
+    fn read_boundary_relation<B: BufRead>(reader: &mut Reader<B>, name: &str) -> Result<(Vec<usize>, bool), Box<dyn Error>> {
+        let mut buf = Vec::new();
+        let mut way_ids = vec![];
+        let (mut is_boundary, mut has_name) = (false, false);
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::End(e) if e.name() == b"relation" => break,
+                Event::Empty(e)|Event::Start(e) => match e.name() {
+                    b"member" => {
+                        if let (Some("way"), Some(way_id)) = (attribute_value(&e, b"type")?.as_deref(), attribute_value(&e, b"ref")?) {
+                            way_ids.push(way_id.parse()?);
+                        }
+                    },
+                    b"tag" => {
+                        match (attribute_value(&e, b"k")?.as_deref(), attribute_value(&e, b"v")?) {
+                            (Some("boundary"), Some(v)) => is_boundary = v == "administrative",
+                            (Some("name"), Some(v)) => has_name = v == name,
+                            _ => (),
+                        }
+                    },
+                    _ => (),
+                },
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+        Ok((way_ids, is_boundary && has_name))
+    }
+
+    // Read the polygon of the administrative boundary relation with the given
+    // name (e.g. "Freiburg im Breisgau") from the given OSM file. The relation
+    // is at the end of the file, after the ways and the nodes it refers to,
+    // so the file is read three times: once for the relation, once for the node
+    // ids of its ways, and once for the coordinates of these nodes.
+    pub fn read_boundary_from_osm_file(filename: &str, name: &str) -> Result<Region, Box<dyn Error>> {
+        let mut buf = Vec::new();
+
+        // Pass 1: the member ways of the relation.
+        let mut member_way_ids = None;
+        let mut reader = Reader::from_file(filename)?;
+        reader.trim_text(true);
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(e) if e.name() == b"relation" => {
+                    let (way_ids, is_match) = read_boundary_relation(&mut reader, name)?;
+                    if is_match { member_way_ids = Some(way_ids); break; }
+                },
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+        let member_way_ids: HashSet<usize> = match member_way_ids {
+            Some(way_ids) => way_ids.into_iter().collect(),
+            None => return Err(format!("No boundary relation named {} in {}", name, filename).into()),
+        };
+
+        // Pass 2: the node ids of these ways.
+        let mut ways: Vec<Vec<usize>> = vec![];
+        let mut reader = Reader::from_file(filename)?;
+        reader.trim_text(true);
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(e) if e.name() == b"way" => {
+                    let way_id: usize = attribute_value(&e, b"id")?.ok_or("way without id")?.parse()?;
+                    if member_way_ids.contains(&way_id) {
+                        let mut way_buf = Vec::new();
+                        let mut nodes = vec![];
+                        loop {
+                            match reader.read_event(&mut way_buf)? {
+                                Event::End(e) if e.name() == b"way" => break,
+                                Event::Empty(e)|Event::Start(e) if e.name() == b"nd" => {
+                                    if let Some(node_id) = attribute_value(&e, b"ref")? { nodes.push(node_id.parse()?); }
+                                },
+                                Event::Eof => break,
+                                _ => (),
+                            }
+                            way_buf.clear();
+                        }
+                        ways.push(nodes);
+                    }
+                },
+                Event::Start(e) if e.name() == b"relation" => break,
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+
+        // Pass 3: the coordinates of their nodes, in degrees.
+        let needed: HashSet<usize> = ways.iter().flatten().copied().collect();
+        let mut coordinates: HashMap<usize, (f64, f64)> = HashMap::new();
+        let mut reader = Reader::from_file(filename)?;
+        reader.trim_text(true);
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Empty(e)|Event::Start(e) if e.name() == b"node" => {
+                    let node_id: usize = attribute_value(&e, b"id")?.ok_or("node without id")?.parse()?;
+                    if needed.contains(&node_id) {
+                        let lat = attribute_value(&e, b"lat")?.ok_or("node without lat")?.parse()?;
+                        let lng = attribute_value(&e, b"lon")?.ok_or("node without lon")?.parse()?;
+                        coordinates.insert(node_id, (lat, lng));
+                    }
+                },
+                Event::Start(e) if e.name() == b"way" => break,
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+
+        let rings = join_ways_to_rings(ways).into_iter()
+            .map(|ring| ring.iter().filter_map(|node_id| coordinates.get(node_id).copied()).collect())
+            .collect();
+        Ok(Region::polygon(rings))
+    }
+
+    // The ways of a boundary relation are pieces of its rings, in no particular
+    // order or direction. Glue them together at their end nodes until each ring
+    // is closed. Pieces that cannot be closed are dropped.
+    fn join_ways_to_rings(mut ways: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+        let mut rings = vec![];
+        ways.retain(|way| way.len() >= 2);
+        while let Some(mut ring) = ways.pop() {
+            while ring.first() != ring.last() {
+                let end = *ring.last().unwrap();
+                match ways.iter().position(|way| way.first() == Some(&end) || way.last() == Some(&end)) {
+                    Some(i) => {
+                        let mut way = ways.swap_remove(i);
+                        if way.first() != Some(&end) { way.reverse(); }
+                        ring.extend_from_slice(&way[1..]);
+                    },
+                    None => break,
+                }
+            }
+            if ring.first() == ring.last() { rings.push(ring); }
+        }
+        rings
+    }
+
 /*
 #[derive(Debug)]
 enum RoadTypes {
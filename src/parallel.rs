@@ -0,0 +1,53 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Helpers to run independent searches of a preprocessing step (one Dijkstra per
+// landmark, one per boundary node, ...) on several threads.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+// The number of threads to use when the caller does not say otherwise.
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// Run jobs 0..num_jobs on num_threads threads. Each thread creates its scratch
+// space once with `init` (e.g. a g_score array of size #nodes) and reuses it for
+// all the jobs it picks up. The results are handed to `consume` on the calling
+// thread, in the order in which they finish, so the caller can merge them into
+// its own data structures without any locking.
+pub fn run_jobs<S, R, I, W, C>(num_threads: usize, num_jobs: usize, init: I, work: W, mut consume: C)
+where
+    R: Send,
+    I: Fn() -> S + Sync,
+    W: Fn(&mut S, usize) -> R + Sync,
+    C: FnMut(usize, R),
+{
+    let num_threads = num_threads.clamp(1, std::cmp::max(1, num_jobs));
+    let next_job = AtomicUsize::new(0);
+
+    // A bounded channel, so finished results do not pile up in memory faster
+    // than the calling thread can merge them.
+    let (sender, receiver) = mpsc::sync_channel(num_threads);
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            let sender = sender.clone();
+            let (next_job, init, work) = (&next_job, &init, &work);
+            scope.spawn(move || {
+                let mut scratch = init();
+                loop {
+                    let job = next_job.fetch_add(1, Ordering::Relaxed);
+                    if job >= num_jobs { break; }
+                    let result = work(&mut scratch, job);
+                    if sender.send((job, result)).is_err() { break; }
+                }
+            });
+        }
+        drop(sender);
+        for (job, result) in receiver {
+            consume(job, result);
+        }
+    });
+}
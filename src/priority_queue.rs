@@ -0,0 +1,253 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Priority queues for Dijkstra (see dijkstra.rs). The search only needs to
+// insert a node with a key (its cost, or cost + heuristic for A*), and to take
+// out the node with the smallest key. Which data structure is fastest depends
+// on the graph, so the search is generic over this trait and main.rs has a
+// benchmark for all of them.
+//
+// NOTE: a node may be pushed again with a smaller key while its old entry is
+// still in the queue. Queues with decrease-key update the entry, the others
+// keep both ("lazy deletion") and the search skips the old one when it is
+// popped, since the node is settled by then.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
+
+pub trait PriorityQueue {
+    // Create an empty queue for a graph with the given number of nodes.
+    fn new(num_nodes: usize) -> Self where Self: Sized;
+
+    // Insert node u with the given key, or lower the key of u if the queue
+    // supports decrease-key.
+    fn push(&mut self, u: usize, key: usize);
+
+    // Remove an entry with the smallest key and return it as (node, key).
+    fn pop(&mut self) -> Option<(usize, usize)>;
+
+    fn clear(&mut self);
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct State {
+    idx: usize,
+    f_score: usize,
+}
+
+// The priority queue depends on `Ord`.
+// Explicitly implement the trait so the queue becomes a min-heap
+// instead of a max-heap.
+impl Ord for State {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Notice that the we flip the ordering on costs.
+        // In case of a tie we compare positions - this step is necessary
+        // to make implementations of `PartialEq` and `Ord` consistent.
+        other.f_score.cmp(&self.f_score)
+            .then_with(|| self.idx.cmp(&other.idx))
+    }
+}
+
+// `PartialOrd` needs to be implemented as well.
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// std::collections::BinaryHeap with lazy deletion. This is what the search
+// used from the start, and still the default.
+#[derive(Default)]
+pub struct LazyBinaryHeap {
+    heap: BinaryHeap<State>,
+}
+
+impl PriorityQueue for LazyBinaryHeap {
+    fn new(_num_nodes: usize) -> LazyBinaryHeap {
+        LazyBinaryHeap { heap: BinaryHeap::new() }
+    }
+
+    fn push(&mut self, u: usize, key: usize) {
+        self.heap.push(State { idx: u, f_score: key });
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        self.heap.pop().map(|State { idx, f_score }| (idx, f_score))
+    }
+
+    fn clear(&mut self) {
+        self.heap.clear();
+    }
+}
+
+// Binary heap with decrease-key. Each node is in the heap at most once, and
+// position[u] says where (usize::MAX if u is not in the heap).
+pub struct IndexedBinaryHeap {
+    heap: Vec<(usize, usize)>,
+    position: Vec<usize>,
+}
+
+impl IndexedBinaryHeap {
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        self.position[self.heap[i].1] = i;
+        self.position[self.heap[j].1] = j;
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.heap[i] >= self.heap[parent] { break; }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let (left, right) = (2 * i + 1, 2 * i + 2);
+            let mut smallest = i;
+            if left < self.heap.len() && self.heap[left] < self.heap[smallest] { smallest = left; }
+            if right < self.heap.len() && self.heap[right] < self.heap[smallest] { smallest = right; }
+            if smallest == i { break; }
+            self.swap(i, smallest);
+            i = smallest;
+        }
+    }
+}
+
+impl PriorityQueue for IndexedBinaryHeap {
+    fn new(num_nodes: usize) -> IndexedBinaryHeap {
+        IndexedBinaryHeap { heap: vec![], position: vec![usize::MAX; num_nodes] }
+    }
+
+    fn push(&mut self, u: usize, key: usize) {
+        let i = self.position[u];
+        if i == usize::MAX {
+            self.heap.push((key, u));
+            self.position[u] = self.heap.len() - 1;
+            self.sift_up(self.heap.len() - 1);
+        } else if key < self.heap[i].0 {
+            self.heap[i].0 = key;
+            self.sift_up(i);
+        }
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        if self.heap.is_empty() { return None; }
+        let last = self.heap.len() - 1;
+        self.swap(0, last);
+        let (key, u) = self.heap.pop().unwrap();
+        self.position[u] = usize::MAX;
+        if !self.heap.is_empty() { self.sift_down(0); }
+        Some((u, key))
+    }
+
+    fn clear(&mut self) {
+        for &(_, u) in &self.heap {
+            self.position[u] = usize::MAX;
+        }
+        self.heap.clear();
+    }
+}
+
+// Radix heap (Ahuja, Mehlhorn, Orlin, Tarjan). Entry with key k goes to bucket
+// i = number of the highest bit in which k differs from the last popped key,
+// so popping only redistributes one bucket. This needs monotone keys, which is
+// the case for Dijkstra and for A* with a consistent heuristic. A key below the
+// last popped one is treated as equal to it.
+pub struct RadixHeap {
+    buckets: Vec<Vec<(usize, usize)>>,
+    last: usize,
+    len: usize,
+}
+
+impl RadixHeap {
+    fn bucket(&self, key: usize) -> usize {
+        (usize::BITS - (key ^ self.last).leading_zeros()) as usize
+    }
+}
+
+impl PriorityQueue for RadixHeap {
+    fn new(_num_nodes: usize) -> RadixHeap {
+        RadixHeap { buckets: vec![vec![]; usize::BITS as usize + 1], last: 0, len: 0 }
+    }
+
+    fn push(&mut self, u: usize, key: usize) {
+        let key = std::cmp::max(key, self.last);
+        let i = self.bucket(key);
+        self.buckets[i].push((key, u));
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        if self.len == 0 { return None; }
+        if self.buckets[0].is_empty() {
+            let i = (1..self.buckets.len()).find(|&i| !self.buckets[i].is_empty()).unwrap();
+            self.last = self.buckets[i].iter().map(|&(key, _)| key).min().unwrap();
+            let entries = std::mem::take(&mut self.buckets[i]);
+            for (key, u) in entries {
+                let j = self.bucket(key);
+                self.buckets[j].push((key, u));
+            }
+        }
+        self.len -= 1;
+        self.buckets[0].pop().map(|(key, u)| (u, key))
+    }
+
+    fn clear(&mut self) {
+        self.buckets.iter_mut().for_each(|bucket| bucket.clear());
+        self.last = 0;
+        self.len = 0;
+    }
+}
+
+// Bucket queue (Dial): one bucket per integer key, from the smallest key in
+// the queue upwards. Our arc costs are small integers (seconds), so the keys
+// in the queue at any time span a small range and the buckets are few.
+pub struct BucketQueue {
+    buckets: VecDeque<Vec<usize>>,
+
+    // The key of buckets[0].
+    first_key: usize,
+    len: usize,
+}
+
+impl PriorityQueue for BucketQueue {
+    fn new(_num_nodes: usize) -> BucketQueue {
+        BucketQueue { buckets: VecDeque::new(), first_key: 0, len: 0 }
+    }
+
+    fn push(&mut self, u: usize, key: usize) {
+        if self.len == 0 {
+            self.buckets.clear();
+            self.first_key = key;
+        }
+        while key < self.first_key {
+            self.buckets.push_front(vec![]);
+            self.first_key -= 1;
+        }
+        let i = key - self.first_key;
+        if self.buckets.len() <= i {
+            self.buckets.resize(i + 1, vec![]);
+        }
+        self.buckets[i].push(u);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        if self.len == 0 { return None; }
+        while self.buckets[0].is_empty() {
+            self.buckets.pop_front();
+            self.first_key += 1;
+        }
+        self.len -= 1;
+        self.buckets[0].pop().map(|u| (u, self.first_key))
+    }
+
+    fn clear(&mut self) {
+        self.buckets.clear();
+        self.len = 0;
+    }
+}
@@ -0,0 +1,210 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// RAPTOR (Round-bAsed Public Transit Optimized Router), after D. Delling,
+// T. Pajor, R. F. Werneck: "Round-Based Public Transit Routing", 2012.
+//
+// Trips with the same sequence of stops are grouped into patterns (routes in
+// the paper), sorted such that no trip of a pattern overtakes another one, so
+// the first trip we can catch at a stop is also the first one at every later
+// stop. Round k finds the earliest arrivals with k trips: it goes along every
+// pattern through a stop improved in round k - 1, from the first such stop,
+// hops on the earliest trip it can catch there and updates the stops after
+// it. Then it walks the footpaths from the stops improved in this round.
+// Labels are only kept if they are better than the ones of all earlier rounds
+// and than the best arrival at the target so far, so round k gives a journey
+// with k trips exactly when it arrives earlier than with fewer trips: the
+// result is the Pareto set over arrival time and number of transfers.
+//
+// Minimum transfer times and footpaths are as in connection_scan.rs.
+
+use std::collections::HashMap;
+use crate::gtfs::{StopTime, Timetable};
+use crate::transit::{Journey, Leg, Mode};
+
+// The trips (indices in Timetable::trips) of a pattern, none overtaking
+// another one, sorted by departure, with their stop times on the date.
+struct Pattern {
+    stops: Vec<usize>,
+    trips: Vec<usize>,
+    times: Vec<Vec<StopTime>>,
+}
+
+// How a stop was reached in a round: on a trip of a pattern (the indices in
+// Raptor::patterns and Pattern::trips) entered at a position of its stops (in
+// the round before), or by walking from a stop (in the same round).
+#[derive(Copy, Clone, Debug)]
+enum Reached {
+    Source,
+    Ride { p: usize, trip: usize, enter: usize },
+    Walk { from: usize },
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Label {
+    arrival: usize,
+    // When we can take a trip at the stop, see connection_scan.rs.
+    ready: usize,
+    reached: Reached,
+}
+
+pub struct Raptor {
+    patterns: Vec<Pattern>,
+
+    // For each stop, the patterns through it, with its position in them.
+    patterns_of_stop: Vec<Vec<(usize, usize)>>,
+}
+
+impl Raptor {
+    // The patterns of the trips of the given date (YYYYMMDD), see
+    // Timetable::trips_on.
+    pub fn new(timetable: &Timetable, date: u32) -> Raptor {
+        let mut trips: Vec<(usize, Vec<StopTime>)> = timetable.trips_on(date).into_iter()
+            .map(|trip| (trip.trip, timetable.stop_times_on(trip)))
+            .collect();
+        trips.sort_unstable_by_key(|(_, stop_times)| stop_times[0].departure);
+
+        // Put each trip into the first pattern with its stops where it is not
+        // earlier than the last trip at any stop.
+        let mut patterns: Vec<Pattern> = vec![];
+        let mut patterns_by_stops: HashMap<Vec<usize>, Vec<usize>> = HashMap::new();
+        for (trip, stop_times) in trips {
+            let stops: Vec<usize> = stop_times.iter().map(|stop_time| stop_time.stop).collect();
+            let candidates = patterns_by_stops.entry(stops.clone()).or_default();
+            let fits = |p: &Pattern| {
+                let last = p.times.last().unwrap();
+                last.iter().zip(&stop_times).all(|(a, b)| a.arrival <= b.arrival && a.departure <= b.departure)
+            };
+            match candidates.iter().find(|&&p| fits(&patterns[p])) {
+                Some(&p) => {
+                    patterns[p].trips.push(trip);
+                    patterns[p].times.push(stop_times);
+                },
+                None => {
+                    candidates.push(patterns.len());
+                    patterns.push(Pattern { stops, trips: vec![trip], times: vec![stop_times] });
+                },
+            }
+        }
+
+        let mut patterns_of_stop = vec![vec![]; timetable.stops.len()];
+        for (p, pattern) in patterns.iter().enumerate() {
+            for (i, &stop) in pattern.stops.iter().enumerate() {
+                patterns_of_stop[stop].push((p, i));
+            }
+        }
+        Raptor { patterns, patterns_of_stop }
+    }
+
+    pub fn num_patterns(&self) -> usize {
+        self.patterns.len()
+    }
+
+    // The earliest arrival at stop t when being at stop s at the given time,
+    // with any number of transfers, or None if t cannot be reached that day.
+    pub fn earliest_arrival(&self, timetable: &Timetable, s: usize, t: usize, departure: usize) -> Option<usize> {
+        self.compute_journeys(timetable, s, t, departure, usize::MAX).last().map(|journey| journey.arrival)
+    }
+
+    // The Pareto set of journeys from s to t with at most max_transfers
+    // transfers, see above: by increasing number of transfers and decreasing
+    // arrival time.
+    pub fn compute_journeys(&self, timetable: &Timetable, s: usize, t: usize, departure: usize, max_transfers: usize) -> Vec<Journey> {
+        let n = timetable.stops.len();
+        let mut journeys = vec![];
+        // labels[k][u] is the label of u from round k, if round k improved it.
+        let mut labels: Vec<Vec<Option<Label>>> = vec![vec![None; n]];
+        let mut best = vec![usize::MAX; n];
+
+        labels[0][s] = Some(Label { arrival: departure, ready: departure, reached: Reached::Source });
+        best[s] = departure;
+        let mut marked = vec![s];
+        walk(timetable, &mut labels[0], &mut best, &mut marked, s);
+        if best[t] < usize::MAX { journeys.push(self.journey(&labels, s, t, 0, departure)); }
+
+        let mut k = 0;
+        while !marked.is_empty() && k <= max_transfers {
+            k += 1;
+            // The first position of a marked stop in each pattern.
+            let mut first_position: HashMap<usize, usize> = HashMap::new();
+            for &u in &marked {
+                for &(p, i) in &self.patterns_of_stop[u] {
+                    let position = first_position.entry(p).or_insert(i);
+                    if i < *position { *position = i; }
+                }
+            }
+
+            let mut round: Vec<Option<Label>> = vec![None; n];
+            let mut improved = vec![];
+            for (p, first) in first_position {
+                let pattern = &self.patterns[p];
+                // The index in pattern.trips of the trip we are on, and where
+                // we entered it.
+                let mut current: Option<(usize, usize)> = None;
+                for (i, &u) in pattern.stops.iter().enumerate().skip(first) {
+                    if let Some((trip, enter)) = current {
+                        let arrival = pattern.times[trip][i].arrival;
+                        if arrival < best[u] && arrival < best[t] {
+                            if round[u].is_none() { improved.push(u); }
+                            best[u] = arrival;
+                            round[u] = Some(Label { arrival, ready: arrival + timetable.min_transfer_times[u],
+                                reached: Reached::Ride { p, trip, enter } });
+                        }
+                    }
+                    // Hop on an earlier trip if we can catch one here.
+                    if let Some(label) = labels[k - 1][u] {
+                        let earliest = pattern.times.partition_point(|times| times[i].departure < label.ready);
+                        if earliest < pattern.trips.len() && current.is_none_or(|(trip, _)| earliest < trip) {
+                            current = Some((earliest, i));
+                        }
+                    }
+                }
+            }
+
+            marked = improved.clone();
+            for u in improved {
+                walk(timetable, &mut round, &mut best, &mut marked, u);
+            }
+            labels.push(round);
+            if labels[k][t].is_some() { journeys.push(self.journey(&labels, s, t, k, departure)); }
+        }
+        journeys
+    }
+
+    // The journey to t with the label of round k, following the labels back.
+    fn journey(&self, labels: &[Vec<Option<Label>>], s: usize, t: usize, k: usize, departure: usize) -> Journey {
+        let arrival = labels[k][t].unwrap().arrival;
+        let mut legs = vec![];
+        let (mut v, mut k) = (t, k);
+        while v != s || k > 0 {
+            let label = labels[k][v].unwrap();
+            let leg = match label.reached {
+                Reached::Source => unreachable!(),
+                Reached::Ride { p, trip, enter } => {
+                    // We entered the trip with the label of the round before.
+                    k -= 1;
+                    let (trip, enter) = (self.patterns[p].trips[trip], self.patterns[p].times[trip][enter]);
+                    Leg { mode: Mode::Transit(trip), from: enter.stop, to: v, departure: enter.departure, arrival: label.arrival }
+                },
+                Reached::Walk { from } => Leg { mode: Mode::Walk, from, to: v, departure: labels[k][from].unwrap().arrival, arrival: label.arrival },
+            };
+            legs.push(leg);
+            v = leg.from;
+        }
+        legs.reverse();
+        Journey { departure, arrival, legs }
+    }
+}
+
+// Walk the footpaths from u, in the same round.
+fn walk(timetable: &Timetable, round: &mut [Option<Label>], best: &mut [usize], marked: &mut Vec<usize>, u: usize) {
+    let arrival = round[u].unwrap().arrival;
+    for footpath in &timetable.footpaths[u] {
+        let v = footpath.to;
+        if arrival + footpath.duration < best[v] {
+            if round[v].is_none() { marked.push(v); }
+            best[v] = arrival + footpath.duration;
+            round[v] = Some(Label { arrival: best[v], ready: best[v], reached: Reached::Walk { from: u } });
+        }
+    }
+}
@@ -0,0 +1,224 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Reach-based pruning, after R. Gutman: "Reach-based Routing: A New Approach
+// to Shortest Path Algorithms Optimized for Road Networks", 2004, and A. V.
+// Goldberg, H. Kaplan, R. F. Werneck: "Reach for A*", 2006.
+//
+// The reach of a node v on a shortest path from s to t is min(d(s, v), d(v, t)),
+// the reach of v is the maximum over all shortest paths through v. Nodes on
+// small roads have a small reach: they are only in the middle of short paths.
+// A query from s to t does not need to look at v if reach(v) < d(s, v) and
+// reach(v) < d(v, t), so it skips an arc into v if the reach of v is below the
+// cost with which v would be reached and below a lower bound on the cost from
+// v to t. Upper bounds on the reaches are enough for that.
+//
+// - Exact reaches need one full shortest path tree per node, fine for small
+//   graphs only. The reach of v in the tree of s is min(d(s, v), height(v)),
+//   where the height is the cost to the farthest node below v. We take the
+//   height in the graph of all shortest paths from s (arcs with
+//   d(s, u) + cost = d(s, v)), not just in the tree, so ties do not matter.
+// - Approximate reaches grow each tree only up to a radius R. Where the part
+//   below v leaves the tree we take d(s, v) instead of min(d(s, v), height(v)).
+//   That misses paths whose middle part is longer than R on both sides, so at
+//   the end every node with a bound of at least R - C, for the cost C of the
+//   most expensive arc, gets reach infinity. The smaller R, the faster the
+//   preprocessing and the fewer nodes are pruned.
+//
+// As lower bound on the cost from v to t, ReachAlgorithm::new (RE) takes the
+// straight-line distance at the best speed of the network, and
+// ReachAlgorithm::with_landmarks (REAL) the landmark distances, which it also
+// uses as heuristic for A* (see astar_landmark_triangle_inequality.rs).
+
+use std::time::Instant;
+use crate::RoadNetwork;
+use crate::astar_landmark_triangle_inequality::{LandmarkAlgorithm, LandmarkStorage};
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::parallel;
+use crate::shortest_path_algorithm::{PreprocessingStats, Route, ShortestPathAlgorithm};
+use crate::traffic::CostChanges;
+
+// The reach of nodes without a bound.
+pub const INFINITE_REACH: usize = usize::MAX;
+
+// The radius (in seconds) used by algorithm_by_name.
+pub const DEFAULT_REACH_RADIUS: usize = 300;
+
+// The straight-line distance is only an approximation (see Node::distance),
+// the geometric lower bound keeps this share of it to be on the safe side.
+const GEOMETRIC_SLACK: f64 = 0.9;
+
+pub struct ReachAlgorithm {
+    // An upper bound on the reach of each node, or INFINITE_REACH.
+    reach: Vec<usize>,
+
+    // The radius of the partial trees, None for exact reaches.
+    radius: Option<usize>,
+
+    // For REAL, the landmarks for the lower bounds and the heuristic.
+    landmarks: Option<LandmarkAlgorithm>,
+
+    // The smallest cost per meter of an arc, for the geometric lower bound.
+    min_cost_per_meter: f64,
+
+    num_threads: usize,
+
+    // Whether some arc cost changed since the precomputation.
+    stale: bool,
+}
+
+impl ReachAlgorithm {
+    // Reach-pruned Dijkstra (RE) with partial trees of the given radius, or
+    // exact reaches if None. The reaches are computed by
+    // ShortestPathAlgorithm::preprocess.
+    pub fn new(radius: Option<usize>, num_threads: usize) -> ReachAlgorithm {
+        ReachAlgorithm { reach: vec![], radius, landmarks: None, min_cost_per_meter: 0.0, num_threads, stale: false }
+    }
+
+    // Reach-pruned A* with the given number of landmarks (REAL).
+    pub fn with_landmarks(radius: Option<usize>, num_landmarks: usize, num_threads: usize) -> ReachAlgorithm {
+        let landmarks = LandmarkAlgorithm::without_preprocessing(num_landmarks, LandmarkStorage::Full, num_threads);
+        ReachAlgorithm { landmarks: Some(landmarks), ..ReachAlgorithm::new(radius, num_threads) }
+    }
+
+    pub fn reach(&self, u: usize) -> usize {
+        self.reach[u]
+    }
+
+    // Compute upper bounds on the reaches, see above. The trees of the
+    // different sources are independent, so they are distributed over
+    // num_threads threads.
+    pub fn compute_reach(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        self.stale = false;
+        let mut stats = PreprocessingStats::default();
+        let n = rn.nodes.len();
+        let arcs = &rn.adjacent_arcs;
+        let radius = self.radius.unwrap_or(usize::MAX);
+
+        let mut reach = vec![0; n];
+        parallel::run_jobs(
+            self.num_threads,
+            n,
+            || (SearchWorkspace::new(n), vec![0; n], vec![false; n]),
+            |(ws, height, open), s| {
+                let dijkstra = Dijkstra { arc_flags: None };
+                dijkstra.compute_within_budget_with_workspace(ws, arcs, s, radius);
+                let distance = |u: usize| ws.distance(u).filter(|&d| ws.is_settled(u) && d <= radius);
+                let tree: Vec<usize> = ws.settled_nodes().iter().copied().filter(|&u| distance(u).is_some()).collect();
+                for &v in &tree {
+                    height[v] = 0;
+                    open[v] = arcs[v].iter().any(|arc| !arc.closed && distance(arc.idx).is_none());
+                }
+                // Heights and open flags go from the nodes farther away to the
+                // closer ones. Only arcs of cost 0 can go back in the settle
+                // order, then we need more rounds.
+                loop {
+                    let (mut changed, mut zero_cost) = (false, false);
+                    for &v in tree.iter().rev() {
+                        let d = distance(v).unwrap();
+                        for arc in arcs[v].iter().filter(|arc| !arc.closed) {
+                            if distance(arc.idx) != Some(d + arc.cost) { continue; }
+                            zero_cost |= arc.cost == 0;
+                            if arc.cost + height[arc.idx] > height[v] { height[v] = arc.cost + height[arc.idx]; changed = true; }
+                            if open[arc.idx] && !open[v] { open[v] = true; changed = true; }
+                        }
+                    }
+                    if !changed || !zero_cost { break; }
+                }
+                let local_reach: Vec<(usize, usize)> = tree.iter()
+                    .map(|&v| {
+                        let d = distance(v).unwrap();
+                        (v, if open[v] { d } else { d.min(height[v]) })
+                    })
+                    .filter(|&(_, r)| r > 0)
+                    .collect();
+                (local_reach, ws.stats())
+            },
+            |_, (local_reach, search_stats)| {
+                for (v, r) in local_reach {
+                    if r > reach[v] { reach[v] = r; }
+                }
+                stats.add_search(search_stats);
+            });
+
+        if let Some(radius) = self.radius {
+            let max_cost = arcs.iter().flatten().filter(|arc| !arc.closed).map(|arc| arc.cost).max().unwrap_or(0);
+            for r in reach.iter_mut() {
+                if *r >= radius.saturating_sub(max_cost) { *r = INFINITE_REACH; }
+            }
+        }
+        self.reach = reach;
+
+        self.min_cost_per_meter = f64::INFINITY;
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for arc in arcs.iter().filter(|arc| !arc.closed) {
+                let distance = rn.nodes[u].distance(&rn.nodes[arc.idx]);
+                if distance > 0.0 { self.min_cost_per_meter = self.min_cost_per_meter.min(arc.cost as f64 / distance); }
+            }
+        }
+
+        stats.size_in_bytes = self.reach.len() * std::mem::size_of::<usize>();
+        stats.elapsed = now.elapsed();
+        stats
+    }
+
+    // Compute the shortest path from s to t, skipping the nodes that cannot
+    // be on it by their reach. The settled nodes and the path can be read
+    // from the workspace afterwards.
+    pub fn compute_shortest_path(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<usize> {
+        let dijkstra = Dijkstra { arc_flags: None };
+        match &self.landmarks {
+            Some(alt) => dijkstra.compute_shortest_path_with_pruning(ws, &rn.adjacent_arcs, s, t,
+                |&u, &t| alt.lower_bound(u, t),
+                |v, g| self.reach[v] < g && self.reach[v] < alt.lower_bound(v, t)),
+            None => dijkstra.compute_shortest_path_with_pruning(ws, &rn.adjacent_arcs, s, t,
+                |_,_| 0,
+                |v, g| self.reach[v] < g && self.reach[v] < self.geometric_lower_bound(rn, v, t)),
+        }
+    }
+
+    // A lower bound on the cost from u to t: the straight-line distance at the
+    // smallest cost per meter of any arc.
+    fn geometric_lower_bound(&self, rn: &RoadNetwork, u: usize, t: usize) -> usize {
+        (rn.nodes[u].distance(&rn.nodes[t]) * self.min_cost_per_meter * GEOMETRIC_SLACK) as usize
+    }
+}
+
+impl ShortestPathAlgorithm for ReachAlgorithm {
+    fn name(&self) -> &str {
+        if self.landmarks.is_some() { "real" } else { "reach" }
+    }
+
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats {
+        let now = Instant::now();
+        let mut stats = self.compute_reach(rn);
+        if let Some(alt) = &mut self.landmarks {
+            let alt_stats = alt.preprocess(rn);
+            stats.searches += alt_stats.searches;
+            stats.search_stats += alt_stats.search_stats;
+            stats.size_in_bytes += alt_stats.size_in_bytes;
+        }
+        stats.elapsed = now.elapsed();
+        stats
+    }
+
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route> {
+        let cost = if self.stale {
+            Dijkstra { arc_flags: None }.compute_shortest_path_with_workspace(ws, &rn.adjacent_arcs, s, Some(t), |_,_| 0)
+        } else {
+            self.compute_shortest_path(rn, ws, s, t)
+        };
+        Route::from_workspace(ws, cost, t)
+    }
+
+    // Reaches can grow with any change (traffic moves to other roads), so
+    // they are stale after any change.
+    fn costs_changed(&mut self, changes: &CostChanges) {
+        if !changes.is_empty() { self.stale = true; }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale
+    }
+}
@@ -0,0 +1,128 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Regions for the arc flags algorithm. A region is either a lat/lng rectangle
+// (as in the lecture) or a polygon, e.g. a city boundary read from a GeoJSON
+// file or from an OSM boundary relation (see osm::read_boundary_from_osm_file).
+
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs;
+use serde_json::Value;
+use crate::Node;
+
+#[derive(Clone, Debug)]
+pub enum Region {
+    // Latitudes and longitudes in radian, like the coordinates of a Node.
+    Rectangle { lat_min: f64, lat_max: f64, lng_min: f64, lng_max: f64 },
+
+    // One or more closed rings of (latitude, longitude) points in radian. A
+    // point is inside if it is inside an odd number of rings (even-odd rule).
+    // That way, outer rings and holes need not be told apart, and a region
+    // made of several separate parts is just a list of rings.
+    Polygon { rings: Vec<Vec<(f64, f64)>> },
+}
+
+impl Region {
+    // Create a rectangle from latitudes and longitudes in degrees.
+    pub fn rectangle(lat_min: f64, lat_max: f64, lng_min: f64, lng_max: f64) -> Region {
+        Region::Rectangle {
+            lat_min: (PI/180.0)*lat_min,
+            lat_max: (PI/180.0)*lat_max,
+            lng_min: (PI/180.0)*lng_min,
+            lng_max: (PI/180.0)*lng_max,
+        }
+    }
+
+    // Create a polygon from rings of (latitude, longitude) points in degrees.
+    // The rings need not repeat their first point at the end.
+    pub fn polygon(rings: Vec<Vec<(f64, f64)>>) -> Region {
+        let rings = rings.into_iter()
+            .map(|ring| ring.into_iter().map(|(lat, lng)| ((PI/180.0)*lat, (PI/180.0)*lng)).collect::<Vec<_>>())
+            .filter(|ring| ring.len() >= 3)
+            .collect();
+        Region::Polygon { rings }
+    }
+
+    pub fn contains(&self, node: &Node) -> bool {
+        match self {
+            Region::Rectangle { lat_min, lat_max, lng_min, lng_max } => {
+                node.latitude < *lat_max && node.latitude > *lat_min && node.longitude < *lng_max && node.longitude > *lng_min
+            },
+            Region::Polygon { rings } => {
+                let mut inside = false;
+                for ring in rings {
+                    if ring_contains(ring, node.latitude, node.longitude) { inside = !inside; }
+                }
+                inside
+            },
+        }
+    }
+
+    // Read a region from a GeoJSON file. The file can contain a Polygon or
+    // MultiPolygon geometry, a Feature with such a geometry, or a
+    // FeatureCollection, in which case the polygons of all features are used.
+    pub fn from_geojson_file(filename: &str) -> Result<Region, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(&fs::read_to_string(filename)?)?;
+        let mut rings = vec![];
+        add_geojson_rings(&json, &mut rings)?;
+        if rings.is_empty() {
+            return Err(format!("No polygon found in {}", filename).into());
+        }
+        Ok(Region::polygon(rings))
+    }
+}
+
+// Point in polygon test by counting crossings of a ray in +longitude direction.
+fn ring_contains(ring: &[(f64, f64)], lat: f64, lng: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (lat_i, lng_i) = ring[i];
+        let (lat_j, lng_j) = ring[j];
+        if (lat_i > lat) != (lat_j > lat) {
+            let lng_crossing = lng_i + (lat - lat_i) / (lat_j - lat_i) * (lng_j - lng_i);
+            if lng < lng_crossing { inside = !inside; }
+        }
+        j = i;
+    }
+    inside
+}
+
+fn add_geojson_rings(json: &Value, rings: &mut Vec<Vec<(f64, f64)>>) -> Result<(), Box<dyn Error>> {
+    match json["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in json["features"].as_array().ok_or("FeatureCollection without features")? {
+                add_geojson_rings(feature, rings)?;
+            }
+        },
+        Some("Feature") => add_geojson_rings(&json["geometry"], rings)?,
+        Some("Polygon") => {
+            for ring in json["coordinates"].as_array().ok_or("Polygon without coordinates")? {
+                rings.push(geojson_ring(ring)?);
+            }
+        },
+        Some("MultiPolygon") => {
+            for polygon in json["coordinates"].as_array().ok_or("MultiPolygon without coordinates")? {
+                for ring in polygon.as_array().ok_or("Invalid MultiPolygon")? {
+                    rings.push(geojson_ring(ring)?);
+                }
+            }
+        },
+        // Other geometries (points, lines) do not define an area.
+        _ => (),
+    }
+    Ok(())
+}
+
+// GeoJSON positions are [longitude, latitude] in degrees.
+fn geojson_ring(ring: &Value) -> Result<Vec<(f64, f64)>, Box<dyn Error>> {
+    let mut points = vec![];
+    for position in ring.as_array().ok_or("Invalid ring")? {
+        match (position[1].as_f64(), position[0].as_f64()) {
+            (Some(lat), Some(lng)) => points.push((lat, lng)),
+            _ => return Err("Invalid position".into()),
+        }
+    }
+    Ok(points)
+}
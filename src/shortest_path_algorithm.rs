@@ -0,0 +1,115 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// The common interface of all our shortest path algorithms (Dijkstra, A* with
+// landmarks, arc flags, ...), so that main.rs and the server can pick one at
+// runtime and compare them.
+
+use crate::RoadNetwork;
+use crate::arc_flags::ArcFlagsAlgorithm;
+use crate::astar_landmark_triangle_inequality::{LandmarkAlgorithm, LandmarkStorage};
+use crate::customizable_contraction_hierarchy::CustomizableContractionHierarchy;
+use crate::hub_labels::HubLabels;
+use crate::multi_level_overlay::{MultiLevelOverlay, OverlayParameters, DEFAULT_CELL_SIZES};
+use crate::reach::{ReachAlgorithm, DEFAULT_REACH_RADIUS};
+use std::time::Duration;
+use crate::dijkstra::{Dijkstra, QueryStats, SearchWorkspace};
+use crate::parallel;
+use crate::region::Region;
+use crate::traffic::CostChanges;
+
+// The names accepted by algorithm_by_name.
+pub const ALGORITHM_NAMES: [&str; 9] = ["dijkstra", "alt", "alt-16bit", "arc-flags", "cch", "crp", "hub-labels", "reach", "real"];
+
+// The number of landmarks used by algorithm_by_name.
+pub const DEFAULT_NUM_LANDMARKS: usize = 42;
+
+// The result of a point-to-point query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    // The cost of the route = travel time in seconds.
+    pub cost: usize,
+
+    // The indices of the nodes on the route, from the source to the target.
+    pub nodes: Vec<usize>,
+}
+
+impl Route {
+    // The route that ends at t in the given workspace, after a query that
+    // reached t with the given cost.
+    pub fn from_workspace(ws: &SearchWorkspace, cost: Option<usize>, t: usize) -> Option<Route> {
+        Some(Route { cost: cost?, nodes: ws.path(t)? })
+    }
+
+    // The length of the route in meters.
+    pub fn length(&self, rn: &RoadNetwork) -> f64 {
+        self.nodes.windows(2).map(|pair| rn.nodes[pair[0]].distance(&rn.nodes[pair[1]])).sum()
+    }
+}
+
+// What a preprocessing step did. The searches it ran are added up in
+// searches and search_stats.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PreprocessingStats {
+    pub elapsed: Duration,
+    pub searches: usize,
+    pub search_stats: QueryStats,
+
+    // The memory used by the precomputed data.
+    pub size_in_bytes: usize,
+}
+
+impl PreprocessingStats {
+    pub fn add_search(&mut self, stats: QueryStats) {
+        self.searches += 1;
+        self.search_stats += stats;
+    }
+}
+
+pub trait ShortestPathAlgorithm: Send + Sync {
+    // A short name for output and for picking the algorithm, e.g. "alt".
+    fn name(&self) -> &str;
+
+    // Do the preprocessing for the given network, if the algorithm has any.
+    // This must be called before the first query, and again whenever the
+    // network changes.
+    fn preprocess(&mut self, rn: &RoadNetwork) -> PreprocessingStats;
+
+    // Compute the shortest route from s to t, or None if t is not reachable.
+    // The workspace must have been created for rn, the settled nodes can be
+    // read from it afterwards.
+    // NOTE: the QueryStats of the query are not part of the Route, they are
+    // only in the workspace: every implementation resets ws.stats() when the
+    // query starts and fills them (including elapsed) for this query alone,
+    // also when it returns None. The next query on the same workspace
+    // overwrites them, so read them right after the call (see
+    // benchmark_algorithm in main.rs).
+    fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize) -> Option<Route>;
+
+    // Tell the algorithm that arc costs changed after the preprocessing (see
+    // traffic.rs). An algorithm whose precomputed data no longer fits becomes
+    // stale: it answers queries with plain Dijkstra until the next preprocess.
+    fn costs_changed(&mut self, _changes: &CostChanges) {}
+
+    fn is_stale(&self) -> bool {
+        false
+    }
+}
+
+// The algorithm with the given name, with default settings and not yet
+// preprocessed. Arc flags need a region, the other algorithms ignore it.
+pub fn algorithm_by_name(name: &str, region: Option<Region>) -> Option<Box<dyn ShortestPathAlgorithm>> {
+    let num_threads = parallel::available_threads();
+    match name {
+        "dijkstra" => Some(Box::new(Dijkstra { arc_flags: None })),
+        "alt" => Some(Box::new(LandmarkAlgorithm::without_preprocessing(DEFAULT_NUM_LANDMARKS, LandmarkStorage::Full, num_threads))),
+        "alt-16bit" => Some(Box::new(LandmarkAlgorithm::without_preprocessing(DEFAULT_NUM_LANDMARKS, LandmarkStorage::Quantized, num_threads))),
+        "arc-flags" => Some(Box::new(ArcFlagsAlgorithm::with_region(region?, num_threads))),
+        "cch" => Some(Box::new(CustomizableContractionHierarchy::new())),
+        "crp" => Some(Box::new(MultiLevelOverlay::new(OverlayParameters { cell_sizes: DEFAULT_CELL_SIZES.to_vec(), num_threads }))),
+        "hub-labels" => Some(Box::new(HubLabels::new())),
+        "reach" => Some(Box::new(ReachAlgorithm::new(Some(DEFAULT_REACH_RADIUS), num_threads))),
+        "real" => Some(Box::new(ReachAlgorithm::with_landmarks(Some(DEFAULT_REACH_RADIUS), DEFAULT_NUM_LANDMARKS, num_threads))),
+        _ => None,
+    }
+}
@@ -0,0 +1,126 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Time-dependent travel times: the travel time of an arc depends on when we
+// enter it, e.g. longer during rush hour. Each arc has a piecewise-linear
+// travel time function over one day, and an earliest-arrival query answers
+// "leaving s at time T, when can we be at t at the earliest?".
+//
+// This is plain Dijkstra with the travel times evaluated at the arrival time
+// at the tail of each arc. That is exact as long as the functions have the
+// FIFO property: entering an arc later never means leaving it earlier, i.e.
+// T + f(T) does not decrease with T, or equivalently the slope of f is never
+// below -1. TravelTimeFunction::new checks this.
+//
+// The functions are kept here and not in the Arcs, in the same layout as the
+// adjacency lists (as the arc flags, see arc_flags.rs), so the network itself
+// stays as it is.
+
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::shortest_path_algorithm::Route;
+
+// The length of the period of the travel time functions, in seconds.
+pub const DAY: usize = 24 * 60 * 60;
+
+// A periodic piecewise-linear function: the travel time in seconds when
+// entering the arc at a given time of day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TravelTimeFunction {
+    // (time of day, travel time) in seconds, sorted by time, all times below
+    // DAY. Between the last point and the first one of the next day the
+    // function is also linear.
+    points: Vec<(usize, usize)>,
+}
+
+impl TravelTimeFunction {
+    // The function through the given points (time of day, travel time), or an
+    // error if there are no points, two points at the same time, or a part
+    // that violates the FIFO property (see above).
+    pub fn new(mut points: Vec<(usize, usize)>) -> Result<TravelTimeFunction, String> {
+        if points.is_empty() { return Err("a travel time function needs at least one point".to_string()); }
+        for point in points.iter_mut() { point.0 %= DAY; }
+        points.sort_unstable();
+        for i in 0..points.len() {
+            let (t1, f1) = points[i];
+            let (t2, f2) = if i + 1 < points.len() { points[i + 1] } else { (points[0].0 + DAY, points[0].1) };
+            if t1 == t2 { return Err(format!("two points at time {}", t1)); }
+            // The slope (f2 - f1) / (t2 - t1) must not be below -1.
+            if f1 > f2 + (t2 - t1) { return Err(format!("the travel time falls faster than time passes after time {}", t1)); }
+        }
+        Ok(TravelTimeFunction { points })
+    }
+
+    pub fn constant(travel_time: usize) -> TravelTimeFunction {
+        TravelTimeFunction { points: vec![(0, travel_time)] }
+    }
+
+    // The function for an arc with the given free-flow travel time and a
+    // daily profile of (time of day, factor), e.g. factor 2.0 at 8:00 for an
+    // arc that takes twice as long in the morning rush hour.
+    pub fn from_profile(free_flow: usize, profile: &[(usize, f64)]) -> Result<TravelTimeFunction, String> {
+        TravelTimeFunction::new(profile.iter().map(|&(time, factor)| (time, (free_flow as f64 * factor).round() as usize)).collect())
+    }
+
+    // The travel time when entering the arc at the given time (in seconds,
+    // any day).
+    pub fn evaluate(&self, time: usize) -> usize {
+        let time = (time % DAY) as i64;
+        let (n, day) = (self.points.len(), DAY as i64);
+        // The points before and after time, shifted by a day where needed.
+        let i = self.points.partition_point(|&(t, _)| t as i64 <= time);
+        let (t1, f1) = if i == 0 { (self.points[n - 1].0 as i64 - day, self.points[n - 1].1) } else { (self.points[i - 1].0 as i64, self.points[i - 1].1) };
+        let (t2, f2) = if i == n { (self.points[0].0 as i64 + day, self.points[0].1) } else { (self.points[i].0 as i64, self.points[i].1) };
+        let value = f1 as f64 + (f2 as f64 - f1 as f64) * (time - t1) as f64 / (t2 - t1) as f64;
+        // Rounding keeps the FIFO property for integer times.
+        value.round() as usize
+    }
+}
+
+pub struct TimeDependentNetwork {
+    // functions[u][j] is the function of rn.adjacent_arcs[u][j].
+    functions: Vec<Vec<TravelTimeFunction>>,
+}
+
+impl TimeDependentNetwork {
+    // Constant travel times, as in the network.
+    pub fn new(rn: &RoadNetwork) -> TimeDependentNetwork {
+        let functions = rn.adjacent_arcs.iter()
+            .map(|arcs| arcs.iter().map(|arc| TravelTimeFunction::constant(arc.cost)).collect())
+            .collect();
+        TimeDependentNetwork { functions }
+    }
+
+    // The same daily profile (see TravelTimeFunction::from_profile) for all
+    // arcs, with the cost in the network as free-flow travel time.
+    pub fn with_profile(rn: &RoadNetwork, profile: &[(usize, f64)]) -> Result<TimeDependentNetwork, String> {
+        let functions = rn.adjacent_arcs.iter()
+            .map(|arcs| arcs.iter().map(|arc| TravelTimeFunction::from_profile(arc.cost, profile)).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(TimeDependentNetwork { functions })
+    }
+
+    // Set the function of arc j of node u (rn.adjacent_arcs[u][j]).
+    pub fn set_function(&mut self, u: usize, j: usize, function: TravelTimeFunction) {
+        self.functions[u][j] = function;
+    }
+
+    pub fn function(&self, u: usize, j: usize) -> &TravelTimeFunction {
+        &self.functions[u][j]
+    }
+
+    // The earliest arrival time at t when leaving s at the given departure
+    // time, or None if t is not reachable.
+    pub fn earliest_arrival(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize, departure: usize) -> Option<usize> {
+        self.compute_route(rn, ws, s, t, departure).map(|route| departure + route.cost)
+    }
+
+    // The route with the earliest arrival at t when leaving s at the given
+    // departure time. Its cost is the travel time.
+    pub fn compute_route(&self, rn: &RoadNetwork, ws: &mut SearchWorkspace, s: usize, t: usize, departure: usize) -> Option<Route> {
+        let dijkstra = Dijkstra { arc_flags: None };
+        let cost = dijkstra.compute_shortest_path_with_arc_costs(ws, &rn.adjacent_arcs, s, t,
+            |u, j, elapsed| self.functions[u][j].evaluate(departure + elapsed));
+        Route::from_workspace(ws, cost, t)
+    }
+}
@@ -0,0 +1,195 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Live traffic and road closures: change the cost of arcs, or close them, in
+// an existing network without reading it again. Arcs are picked by the OSM ids
+// of their two nodes or by the OSM way they belong to. Closed arcs are skipped
+// by all searches (see dijkstra.rs), so Dijkstra and A* see every change right
+// away.
+//
+// Precomputed data may not fit the new costs any more. Tell the algorithm with
+// ShortestPathAlgorithm::costs_changed: ALT stays correct as long as no cost
+// went down, in one direction or both (the landmark distances are then still
+// lower bounds), and only becomes stale on a decrease. Arc flags become stale
+// on any change. Stale algorithms answer queries with plain Dijkstra until
+// they are preprocessed again.
+//
+// The feed is a text file with one update per line, "#" starts a comment:
+//
+//   arc <from> <to> cost <seconds>   override the cost of the arcs from -> to
+//   arc <from> <to> close            close the arcs from -> to
+//   arc <from> <to> open             open them again
+//   way <id> speed <km/h>            recompute the costs of the way's arcs
+//   way <id> close                   close all arcs of the way
+//   way <id> open                    open them again
+//   reset                            restore all arcs as they were read
+//
+// <from> and <to> are OSM node ids, <id> is an OSM way id. Arcs are directed,
+// give both directions to change a road both ways.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use crate::RoadNetwork;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrafficUpdate {
+    SetArcCost { from: usize, to: usize, cost: usize },
+    CloseArc { from: usize, to: usize },
+    OpenArc { from: usize, to: usize },
+    SetWaySpeed { way_id: usize, speed: usize },
+    CloseWay { way_id: usize },
+    OpenWay { way_id: usize },
+    Reset,
+}
+
+// How many arcs became more expensive (or closed) and how many became cheaper
+// (or opened) by some updates.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CostChanges {
+    pub increased: usize,
+    pub decreased: usize,
+}
+
+impl CostChanges {
+    pub fn is_empty(&self) -> bool {
+        self.increased == 0 && self.decreased == 0
+    }
+}
+
+impl std::ops::AddAssign for CostChanges {
+    fn add_assign(&mut self, other: CostChanges) {
+        self.increased += other.increased;
+        self.decreased += other.decreased;
+    }
+}
+
+// Parse a feed in the format described at the top of this file.
+pub fn parse_feed(text: &str) -> Result<Vec<TrafficUpdate>, Box<dyn Error>> {
+    let mut updates = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let update = match words.as_slice() {
+            [] => continue,
+            ["arc", from, to, "cost", cost] => TrafficUpdate::SetArcCost { from: from.parse()?, to: to.parse()?, cost: cost.parse()? },
+            ["arc", from, to, "close"] => TrafficUpdate::CloseArc { from: from.parse()?, to: to.parse()? },
+            ["arc", from, to, "open"] => TrafficUpdate::OpenArc { from: from.parse()?, to: to.parse()? },
+            ["way", way_id, "speed", speed] => TrafficUpdate::SetWaySpeed { way_id: way_id.parse()?, speed: speed.parse()? },
+            ["way", way_id, "close"] => TrafficUpdate::CloseWay { way_id: way_id.parse()? },
+            ["way", way_id, "open"] => TrafficUpdate::OpenWay { way_id: way_id.parse()? },
+            ["reset"] => TrafficUpdate::Reset,
+            _ => return Err(format!("line {}: cannot parse \"{}\"", i + 1, line.trim()).into()),
+        };
+        updates.push(update);
+    }
+    Ok(updates)
+}
+
+pub fn read_feed_file(filename: &str) -> Result<Vec<TrafficUpdate>, Box<dyn Error>> {
+    parse_feed(&fs::read_to_string(filename)?)
+}
+
+// Applies updates to a network. It must be created for the network it is
+// used with, and the arcs of the network must not be added or removed
+// afterwards (arcs are remembered by their position in the adjacency lists).
+pub struct LiveTraffic {
+    // The arcs of each way, as (node, position in adjacent_arcs).
+    way_arcs: HashMap<usize, Vec<(usize, usize)>>,
+
+    // The cost, speed and closed flag of every changed arc before its first
+    // change, for reset.
+    original_arcs: HashMap<(usize, usize), (usize, usize, bool)>,
+}
+
+impl LiveTraffic {
+    pub fn new(rn: &RoadNetwork) -> LiveTraffic {
+        let mut way_arcs: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+        for (u, arcs) in rn.adjacent_arcs.iter().enumerate() {
+            for (j, arc) in arcs.iter().enumerate() {
+                if arc.way_id != 0 { way_arcs.entry(arc.way_id).or_default().push((u, j)); }
+            }
+        }
+        LiveTraffic { way_arcs, original_arcs: HashMap::new() }
+    }
+
+    // Apply one update. An error if it refers to a node or way that is not in
+    // the network, or to a node pair without an arc.
+    pub fn apply(&mut self, rn: &mut RoadNetwork, update: TrafficUpdate) -> Result<CostChanges, String> {
+        let mut changes = CostChanges::default();
+        match update {
+            TrafficUpdate::SetArcCost { from, to, cost } => {
+                for (u, j) in self.arcs_between(rn, from, to)? {
+                    self.change_arc(rn, u, j, cost, rn.adjacent_arcs[u][j].speed, rn.adjacent_arcs[u][j].closed, &mut changes);
+                }
+            },
+            TrafficUpdate::CloseArc { from, to } | TrafficUpdate::OpenArc { from, to } => {
+                let closed = matches!(update, TrafficUpdate::CloseArc { .. });
+                for (u, j) in self.arcs_between(rn, from, to)? {
+                    self.change_arc(rn, u, j, rn.adjacent_arcs[u][j].cost, rn.adjacent_arcs[u][j].speed, closed, &mut changes);
+                }
+            },
+            TrafficUpdate::SetWaySpeed { way_id, speed } => {
+                if speed == 0 { return Err(format!("way {}: the speed must be positive", way_id)); }
+                for (u, j) in self.arcs_of_way(way_id)? {
+                    let arc = rn.adjacent_arcs[u][j];
+                    let cost = rn.nodes[u].cost(&rn.nodes[arc.idx], speed);
+                    self.change_arc(rn, u, j, cost, speed, arc.closed, &mut changes);
+                }
+            },
+            TrafficUpdate::CloseWay { way_id } | TrafficUpdate::OpenWay { way_id } => {
+                let closed = matches!(update, TrafficUpdate::CloseWay { .. });
+                for (u, j) in self.arcs_of_way(way_id)? {
+                    self.change_arc(rn, u, j, rn.adjacent_arcs[u][j].cost, rn.adjacent_arcs[u][j].speed, closed, &mut changes);
+                }
+            },
+            TrafficUpdate::Reset => {
+                for ((u, j), (cost, speed, closed)) in std::mem::take(&mut self.original_arcs) {
+                    set_arc(rn, u, j, cost, speed, closed, &mut changes);
+                }
+            },
+        }
+        Ok(changes)
+    }
+
+    // Apply the updates in order, stopping at the first error.
+    pub fn apply_all(&mut self, rn: &mut RoadNetwork, updates: &[TrafficUpdate]) -> Result<CostChanges, String> {
+        let mut changes = CostChanges::default();
+        for &update in updates {
+            changes += self.apply(rn, update)?;
+        }
+        Ok(changes)
+    }
+
+    fn arcs_between(&self, rn: &RoadNetwork, from: usize, to: usize) -> Result<Vec<(usize, usize)>, String> {
+        let u = *rn.node_id_to_index.get(&from).ok_or(format!("unknown node {}", from))?;
+        let arcs: Vec<(usize, usize)> = (0..rn.adjacent_arcs[u].len()).filter(|&j| rn.adjacent_arcs[u][j].head_node_id == to).map(|j| (u, j)).collect();
+        if arcs.is_empty() { return Err(format!("no arc from {} to {}", from, to)); }
+        Ok(arcs)
+    }
+
+    fn arcs_of_way(&self, way_id: usize) -> Result<Vec<(usize, usize)>, String> {
+        self.way_arcs.get(&way_id).cloned().ok_or(format!("unknown way {}", way_id))
+    }
+
+    // As set_arc, but remember how the arc was read first, for Reset.
+    #[allow(clippy::too_many_arguments)]
+    fn change_arc(&mut self, rn: &mut RoadNetwork, u: usize, j: usize, cost: usize, speed: usize, closed: bool, changes: &mut CostChanges) {
+        let arc = &rn.adjacent_arcs[u][j];
+        self.original_arcs.entry((u, j)).or_insert((arc.cost, arc.speed, arc.closed));
+        set_arc(rn, u, j, cost, speed, closed, changes);
+    }
+}
+
+// Set the cost, speed and closed flag of rn.adjacent_arcs[u][j], and count the
+// change. A closed arc counts as infinitely expensive.
+fn set_arc(rn: &mut RoadNetwork, u: usize, j: usize, cost: usize, speed: usize, closed: bool, changes: &mut CostChanges) {
+    let arc = &mut rn.adjacent_arcs[u][j];
+    let effective_cost = |cost: usize, closed: bool| if closed { usize::MAX } else { cost };
+    let (old, new) = (effective_cost(arc.cost, arc.closed), effective_cost(cost, closed));
+    if new > old { changes.increased += 1; }
+    if new < old { changes.decreased += 1; }
+    arc.cost = cost;
+    arc.speed = speed;
+    arc.closed = closed;
+}
@@ -0,0 +1,139 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Earliest-arrival queries on a public transit timetable (see gtfs.rs), in the
+// time-dependent model: one node per stop, and an arc from stop u to stop v
+// if some trip goes from u to v without stopping in between. The travel time
+// of such an arc depends on when we are at u: we wait for the next departure
+// to v and ride to v. As in time_dependent.rs, this is plain Dijkstra with the
+// travel times evaluated at the arrival time at u.
+//
+// An arc keeps the departures of all its trips, sorted, each with the earliest
+// arrival at v of that departure or any later one. Taking a later trip that
+// overtakes an earlier one is then part of the arc, and the arcs are FIFO.
+//
+// The graph is built for one date, with the trips that run on it, and the
+// part after midnight of the trips that ran the day before (their times are
+// past 24:00:00 of their service date, we shift them by a day). Transfers take
+// no time and there is no walking between stops. The connection scan
+// (connection_scan.rs) and RAPTOR (raptor.rs) also know minimum transfer times
+// and footpaths, and give whole journeys.
+
+use crate::Arc;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::gtfs::Timetable;
+use crate::shortest_path_algorithm::Route;
+
+// How a leg of a journey gets from one place to the next.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Walk,
+    // On the trip with this index in Timetable::trips.
+    Transit(usize),
+}
+
+// A part of a journey with one mode, from one stop to another.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Leg {
+    pub mode: Mode,
+    pub from: usize,
+    pub to: usize,
+    pub departure: usize,
+    pub arrival: usize,
+}
+
+// The result of a transit query: the legs in order, with waiting between
+// them. departure is the time of the query, not of the first leg.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Journey {
+    pub departure: usize,
+    pub arrival: usize,
+    pub legs: Vec<Leg>,
+}
+
+impl Journey {
+    pub fn num_transfers(&self) -> usize {
+        self.legs.iter().filter(|leg| matches!(leg.mode, Mode::Transit(_))).count().saturating_sub(1)
+    }
+
+    // As Route: the travel time from the departure, and the stops where the
+    // legs start and end.
+    pub fn route(&self, s: usize) -> Route {
+        let mut nodes = vec![s];
+        nodes.extend(self.legs.iter().map(|leg| leg.to));
+        Route { cost: self.arrival - self.departure, nodes }
+    }
+}
+
+pub struct TransitGraph {
+    // The arcs between the stops, for the search. Their cost is the shortest
+    // ride over the arc.
+    adjacent_arcs: Vec<Vec<Arc>>,
+
+    // departures[u][j] are the (departure, earliest arrival) pairs of arc j
+    // of stop u, see above.
+    departures: Vec<Vec<Vec<(usize, usize)>>>,
+}
+
+impl TransitGraph {
+    // The graph of the trips that run on the given date (YYYYMMDD), see above.
+    pub fn new(timetable: &Timetable, date: u32) -> TransitGraph {
+        let n = timetable.stops.len();
+        let mut adjacent_arcs: Vec<Vec<Arc>> = vec![vec![]; n];
+        let mut departures: Vec<Vec<Vec<(usize, usize)>>> = vec![vec![]; n];
+        for trip in timetable.trips_on(date) {
+            for pair in timetable.stop_times_on(trip).windows(2) {
+                let (u, v) = (pair[0].stop, pair[1].stop);
+                if u == v { continue; }
+                let ride = pair[1].arrival.saturating_sub(pair[0].departure);
+                let j = match adjacent_arcs[u].iter().position(|arc| arc.idx == v) {
+                    Some(j) => j,
+                    None => {
+                        adjacent_arcs[u].push(Arc::new(v, v, ride, 0));
+                        departures[u].push(vec![]);
+                        adjacent_arcs[u].len() - 1
+                    },
+                };
+                let arc = &mut adjacent_arcs[u][j];
+                arc.cost = arc.cost.min(ride);
+                departures[u][j].push((pair[0].departure, pair[0].departure + ride));
+            }
+        }
+        for departures in departures.iter_mut().flatten() {
+            departures.sort_unstable();
+            for i in (1..departures.len()).rev() {
+                departures[i - 1].1 = departures[i - 1].1.min(departures[i].1);
+            }
+        }
+        TransitGraph { adjacent_arcs, departures }
+    }
+
+    pub fn num_connections(&self) -> usize {
+        self.departures.iter().flatten().map(|departures| departures.len()).sum()
+    }
+
+    // The earliest arrival time at stop t when being at stop s at the given
+    // time (seconds since midnight of the date of the graph), or None if t
+    // cannot be reached that day.
+    pub fn earliest_arrival(&self, ws: &mut SearchWorkspace, s: usize, t: usize, departure: usize) -> Option<usize> {
+        self.compute_route(ws, s, t, departure).map(|route| departure + route.cost)
+    }
+
+    // The stops on the way with the earliest arrival at t. Its cost is the
+    // time from the given departure time to the arrival, waiting included.
+    pub fn compute_route(&self, ws: &mut SearchWorkspace, s: usize, t: usize, departure: usize) -> Option<Route> {
+        let dijkstra = Dijkstra { arc_flags: None };
+        let cost = dijkstra.compute_shortest_path_with_arc_costs(ws, &self.adjacent_arcs, s, t,
+            |u, j, elapsed| self.travel_time(u, j, departure + elapsed));
+        Route::from_workspace(ws, cost, t)
+    }
+
+    // Waiting plus riding time over arc j of stop u when at u at the given
+    // time, or usize::MAX if there is no later departure (the search then
+    // skips the arc).
+    fn travel_time(&self, u: usize, j: usize, time: usize) -> usize {
+        let departures = &self.departures[u][j];
+        let i = departures.partition_point(|&(departure, _)| departure < time);
+        if i == departures.len() { usize::MAX } else { departures[i].1 - time }
+    }
+}
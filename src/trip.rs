@@ -0,0 +1,173 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Trip optimization: the order in which to visit a set of stops (10 to 50,
+// say) so that the total travel time is small, i.e. a heuristic for the
+// travelling salesman problem (TSP) on the stop-to-stop cost matrix.
+//
+// Open trips (the trip ends at the last stop) and trips with a fixed first or
+// last stop are reduced to a closed tour by adding a dummy stop: the dummy is
+// connected to every stop with cost 0, except that only the fixed first stop
+// can follow it and only the fixed last stop can come before it. Cutting the
+// closed tour at the dummy gives the trip.
+//
+// The tour is built by nearest insertion and then improved by 2-opt (reverse a
+// part of the tour) and Or-opt (move one to three consecutive stops elsewhere)
+// until neither finds an improvement. The costs need not be symmetric, so each
+// move is evaluated on the full tour cost.
+
+use crate::RoadNetwork;
+use crate::dijkstra::{Dijkstra, SearchWorkspace};
+use crate::distance_matrix::{self, DistanceMatrix};
+use crate::shortest_path_algorithm::Route;
+use crate::waypoints::{self, MultiLegRoute};
+
+// The cost used for pairs without a path and for the forbidden arcs of the
+// dummy stop. Large enough to avoid them, small enough not to overflow a sum.
+const INFINITY: usize = 1 << 40;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TripOptions {
+    // Return to the first stop at the end.
+    pub round_trip: bool,
+
+    // The stop (index into the stops) to start at, and the one to end at. The
+    // end is ignored for round trips.
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+pub struct Trip {
+    // The stops (indices into the given stops) in the order of the trip.
+    pub order: Vec<usize>,
+
+    // The route through the stops in this order (back to the first one for a
+    // round trip), with one leg per pair of consecutive stops.
+    pub route: MultiLegRoute,
+}
+
+// Optimize the order of the given stops (node indices), see above. The cost
+// matrix is computed on num_threads threads. None if some stop cannot be
+// reached in the required order, or for a start or end that is not a stop.
+pub fn optimize_trip(rn: &RoadNetwork, stops: &[usize], options: TripOptions, num_threads: usize) -> Option<Trip> {
+    let costs = distance_matrix::many_to_many(rn, stops, stops, num_threads);
+    let order = solve(&costs, options)?;
+    let mut nodes: Vec<usize> = order.iter().map(|&i| stops[i]).collect();
+    if options.round_trip { nodes.push(stops[order[0]]); }
+
+    let mut ws = SearchWorkspace::new(rn.nodes.len());
+    let route = if nodes.len() < 2 {
+        let nodes = nodes.first().map(|&u| vec![u]).unwrap_or_default();
+        MultiLegRoute { route: Route { cost: 0, nodes }, distance: 0.0, legs: vec![] }
+    } else {
+        waypoints::compute_route_via_nodes(rn, &Dijkstra { arc_flags: None }, &mut ws, &nodes)?
+    };
+    Some(Trip { order, route })
+}
+
+// The order of the stops for the given cost matrix (costs[i][j] from stop i to
+// stop j), see above. None if there is no order without a missing path, or
+// if the start or the end is not one of the stops.
+pub fn solve(costs: &DistanceMatrix, options: TripOptions) -> Option<Vec<usize>> {
+    let n = costs.len();
+    if options.start.is_some_and(|start| start >= n) || options.end.is_some_and(|end| end >= n) { return None; }
+    if n == 0 { return Some(vec![]); }
+
+    // Stop n is the dummy stop for open trips.
+    let dummy = if options.round_trip { None } else { Some(n) };
+    let cost = |i: usize, j: usize| -> usize {
+        match (Some(i) == dummy, Some(j) == dummy) {
+            (false, false) => costs[i][j].unwrap_or(INFINITY),
+            (false, true) => if options.end.is_none_or(|end| end == i) { 0 } else { INFINITY },
+            (true, false) => if options.start.is_none_or(|start| start == j) { 0 } else { INFINITY },
+            (true, true) => 0,
+        }
+    };
+    let num_stops = if dummy.is_some() { n + 1 } else { n };
+
+    let mut tour = nearest_insertion(num_stops, &cost);
+    while two_opt(&mut tour, &cost) || or_opt(&mut tour, &cost) {}
+    if tour_cost(&tour, &cost) >= INFINITY { return None; }
+
+    // Rotate the tour so that it starts with the dummy or the first stop.
+    let first = dummy.or(options.start).unwrap_or(0);
+    let i = tour.iter().position(|&u| u == first).unwrap();
+    tour.rotate_left(i);
+    if dummy.is_some() { tour.remove(0); }
+    Some(tour)
+}
+
+// The cost of the closed tour.
+fn tour_cost<C: Fn(usize, usize) -> usize>(tour: &[usize], cost: &C) -> usize {
+    (0..tour.len()).map(|i| cost(tour[i], tour[(i + 1) % tour.len()])).sum()
+}
+
+// Start with one stop, and repeatedly insert the stop closest to the tour at
+// the position where it adds the least cost.
+fn nearest_insertion<C: Fn(usize, usize) -> usize>(num_stops: usize, cost: &C) -> Vec<usize> {
+    let mut tour = vec![0];
+    let mut in_tour = vec![false; num_stops];
+    in_tour[0] = true;
+    // distance[k] is the smallest cost between k and a stop in the tour.
+    let mut distance: Vec<usize> = (0..num_stops).map(|k| std::cmp::min(cost(0, k), cost(k, 0))).collect();
+    for _ in 1..num_stops {
+        let k = (0..num_stops).filter(|&k| !in_tour[k]).min_by_key(|&k| distance[k]).unwrap();
+        let position = (0..tour.len())
+            .min_by_key(|&i| {
+                let (a, b) = (tour[i], tour[(i + 1) % tour.len()]);
+                (cost(a, k) + cost(k, b)).saturating_sub(cost(a, b))
+            })
+            .unwrap();
+        tour.insert(position + 1, k);
+        in_tour[k] = true;
+        for (j, d) in distance.iter_mut().enumerate() {
+            *d = std::cmp::min(*d, std::cmp::min(cost(k, j), cost(j, k)));
+        }
+    }
+    tour
+}
+
+// Apply the best 2-opt move (reversing tour[i..=j]) if it improves the tour.
+// The first stop stays in place, which is no restriction for a closed tour.
+fn two_opt<C: Fn(usize, usize) -> usize>(tour: &mut [usize], cost: &C) -> bool {
+    let mut best = (tour_cost(tour, cost), 0, 0);
+    for i in 1..tour.len() {
+        for j in i + 1..tour.len() {
+            tour[i..=j].reverse();
+            let new_cost = tour_cost(tour, cost);
+            if new_cost < best.0 { best = (new_cost, i, j); }
+            tour[i..=j].reverse();
+        }
+    }
+    let (_, i, j) = best;
+    if i == 0 { return false; }
+    tour[i..=j].reverse();
+    true
+}
+
+// Apply the best Or-opt move (moving one to three consecutive stops to
+// another position) if it improves the tour.
+fn or_opt<C: Fn(usize, usize) -> usize>(tour: &mut Vec<usize>, cost: &C) -> bool {
+    let current_cost = tour_cost(tour, cost);
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for length in 1..=3 {
+        for i in 1..tour.len() {
+            if i + length > tour.len() { break; }
+            let mut rest = tour.clone();
+            let segment: Vec<usize> = rest.drain(i..i + length).collect();
+            for position in 1..=rest.len() {
+                if position == i { continue; }
+                let mut candidate = rest.clone();
+                candidate.splice(position..position, segment.iter().copied());
+                let new_cost = tour_cost(&candidate, cost);
+                if new_cost < best.as_ref().map_or(current_cost, |(best_cost, _)| *best_cost) {
+                    best = Some((new_cost, candidate));
+                }
+            }
+        }
+    }
+    match best {
+        Some((_, candidate)) => { *tour = candidate; true },
+        None => false,
+    }
+}
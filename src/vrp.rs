@@ -0,0 +1,347 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Vehicle routing with capacities and time windows: assign deliveries to
+// vehicles that start and end at a common depot, such that
+// - the deliveries of a vehicle fit into its capacity,
+// - each delivery starts within its time window (a vehicle that arrives early
+//   waits), and the vehicle is back at the depot by the end of its shift,
+// and the total travel time is small. Travel times come from the road network
+// (see distance_matrix.rs).
+//
+// The solution is built by cheapest insertion: repeatedly insert the delivery
+// that adds the least travel time at a feasible position of some route. It is
+// then improved by local search, with two kinds of moves: relocate (move one
+// delivery to another position, in the same or another route) and exchange
+// (swap two deliveries of different routes). Deliveries that fit nowhere are
+// reported as unassigned.
+//
+// The problem file is JSON, with times in seconds (e.g. since midnight),
+// positions in degrees and ids as strings:
+// { "depot": { "lat": 48.0, "lng": 7.8 },
+//   "vehicles": [{ "id": "truck1", "capacity": 100, "start_time": 28800, "end_time": 64800 }, ...],
+//   "deliveries": [{ "id": "c1", "lat": 48.01, "lng": 7.82, "demand": 10,
+//                    "time_window": [32400, 36000], "service_time": 300 }, ...] }
+
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs;
+use serde_json::{json, Value};
+use crate::RoadNetwork;
+use crate::distance_matrix::{self, DistanceMatrix};
+
+pub struct Vehicle {
+    pub id: String,
+    pub capacity: usize,
+
+    // The vehicle leaves the depot at start_time and must be back by end_time.
+    pub start_time: usize,
+    pub end_time: usize,
+}
+
+pub struct Delivery {
+    pub id: String,
+
+    // The position in radian.
+    pub latitude: f64,
+    pub longitude: f64,
+
+    pub demand: usize,
+
+    // The service must start within [time_window.0, time_window.1].
+    pub time_window: (usize, usize),
+    pub service_time: usize,
+}
+
+pub struct Problem {
+    // The position of the depot in radian.
+    pub depot: (f64, f64),
+    pub vehicles: Vec<Vehicle>,
+    pub deliveries: Vec<Delivery>,
+}
+
+// A delivery in a VehicleRoute, with the times of the schedule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stop {
+    // The index of the delivery in Problem::deliveries.
+    pub delivery: usize,
+    pub arrival: usize,
+    pub service_start: usize,
+    pub departure: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VehicleRoute {
+    // The index of the vehicle in Problem::vehicles.
+    pub vehicle: usize,
+    pub stops: Vec<Stop>,
+    pub load: usize,
+    pub travel_time: usize,
+
+    // When the vehicle is back at the depot.
+    pub return_time: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Solution {
+    // One route per usable vehicle (in the order of Problem::vehicles),
+    // possibly without stops. A vehicle that cannot even leave the depot and
+    // come back within its shift is unusable and has no route.
+    pub routes: Vec<VehicleRoute>,
+    pub unassigned: Vec<usize>,
+    pub travel_time: usize,
+}
+
+impl Problem {
+    pub fn from_json_file(filename: &str) -> Result<Problem, Box<dyn Error>> {
+        let json: Value = serde_json::from_str(&fs::read_to_string(filename)?)?;
+        Problem::from_json(&json)
+    }
+
+    // Read the problem from JSON in the format described at the top of this file.
+    pub fn from_json(json: &Value) -> Result<Problem, Box<dyn Error>> {
+        let depot = (number(&json["depot"], "lat")? * PI / 180.0, number(&json["depot"], "lng")? * PI / 180.0);
+        let vehicles = json["vehicles"].as_array().ok_or("vehicles must be an array")?.iter()
+            .map(|vehicle| {
+                let (start_time, end_time) = (integer(vehicle, "start_time")?, integer(vehicle, "end_time")?);
+                if start_time > end_time { return Err("start_time must not be after end_time".into()); }
+                Ok(Vehicle {
+                    id: string(vehicle, "id")?,
+                    capacity: integer(vehicle, "capacity")?,
+                    start_time,
+                    end_time,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        let deliveries = json["deliveries"].as_array().ok_or("deliveries must be an array")?.iter()
+            .map(|delivery| {
+                let time_window = delivery["time_window"].as_array().filter(|window| window.len() == 2)
+                    .ok_or("time_window must be [start, end]")?;
+                let time_window = (time_window[0].as_u64().ok_or("time_window must be [start, end]")? as usize,
+                                   time_window[1].as_u64().ok_or("time_window must be [start, end]")? as usize);
+                if time_window.0 > time_window.1 { return Err("time_window must not end before it starts".into()); }
+                Ok(Delivery {
+                    id: string(delivery, "id")?,
+                    latitude: number(delivery, "lat")? * PI / 180.0,
+                    longitude: number(delivery, "lng")? * PI / 180.0,
+                    demand: integer(delivery, "demand")?,
+                    time_window,
+                    // Optional, but if it is there it must be valid.
+                    service_time: if delivery.get("service_time").is_some() { integer(delivery, "service_time")? } else { 0 },
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Ok(Problem { depot, vehicles, deliveries })
+    }
+}
+
+impl Solution {
+    // The solution as JSON, with the ids and times of the problem:
+    // { "travel_time": ..., "unassigned": [id, ...],
+    //   "routes": [{ "vehicle": id, "load": ..., "travel_time": ..., "return_time": ...,
+    //                "stops": [{ "id": ..., "arrival": ..., "service_start": ..., "departure": ... }, ...] }, ...] }
+    pub fn to_json(&self, problem: &Problem) -> Value {
+        let routes: Vec<Value> = self.routes.iter().map(|route| {
+            let stops: Vec<Value> = route.stops.iter().map(|stop| json!({
+                "id": problem.deliveries[stop.delivery].id,
+                "arrival": stop.arrival,
+                "service_start": stop.service_start,
+                "departure": stop.departure,
+            })).collect();
+            json!({
+                "vehicle": problem.vehicles[route.vehicle].id,
+                "load": route.load,
+                "travel_time": route.travel_time,
+                "return_time": route.return_time,
+                "stops": stops,
+            })
+        }).collect();
+        let unassigned: Vec<&str> = self.unassigned.iter().map(|&d| problem.deliveries[d].id.as_str()).collect();
+        json!({ "travel_time": self.travel_time, "unassigned": unassigned, "routes": routes })
+    }
+}
+
+fn number(json: &Value, key: &str) -> Result<f64, Box<dyn Error>> {
+    Ok(json[key].as_f64().ok_or(format!("{} must be a number", key))?)
+}
+
+fn integer(json: &Value, key: &str) -> Result<usize, Box<dyn Error>> {
+    Ok(json[key].as_u64().ok_or(format!("{} must be a non-negative integer", key))? as usize)
+}
+
+fn string(json: &Value, key: &str) -> Result<String, Box<dyn Error>> {
+    Ok(json[key].as_str().ok_or(format!("{} must be a string", key))?.to_string())
+}
+
+// Solve the problem on the given network: snap the depot and the deliveries
+// to the closest nodes, compute the travel times between them on num_threads
+// threads, and solve with these.
+pub fn solve(rn: &RoadNetwork, problem: &Problem, num_threads: usize) -> Option<Solution> {
+    let positions = std::iter::once(problem.depot)
+        .chain(problem.deliveries.iter().map(|delivery| (delivery.latitude, delivery.longitude)));
+    let nodes = positions.map(|(lat, lng)| rn.get_nearest_node(lat, lng)).collect::<Option<Vec<usize>>>()?;
+    let costs = distance_matrix::many_to_many(rn, &nodes, &nodes, num_threads);
+    Some(solve_with_costs(problem, &costs))
+}
+
+// Solve the problem with the given travel times: costs[0] is the depot and
+// costs[i + 1] delivery i.
+pub fn solve_with_costs(problem: &Problem, costs: &DistanceMatrix) -> Solution {
+    let solver = Solver { problem, costs };
+    let mut routes: Vec<Vec<usize>> = vec![vec![]; problem.vehicles.len()];
+    let mut unassigned: Vec<usize> = (0..problem.deliveries.len()).collect();
+    loop {
+        solver.insert_cheapest(&mut routes, &mut unassigned);
+        if !solver.improve(&mut routes) { break; }
+    }
+
+    // The routes of usable vehicles stay feasible, the others stay empty.
+    let routes: Vec<VehicleRoute> = routes.iter().enumerate()
+        .filter_map(|(vehicle, route)| solver.schedule(vehicle, route))
+        .collect();
+    let travel_time = routes.iter().map(|route| route.travel_time).sum();
+    Solution { routes, unassigned, travel_time }
+}
+
+struct Solver<'a> {
+    problem: &'a Problem,
+    costs: &'a DistanceMatrix,
+}
+
+impl Solver<'_> {
+    // The travel time between two locations (0 is the depot, i + 1 delivery i).
+    fn cost(&self, from: usize, to: usize) -> Option<usize> {
+        self.costs[from][to]
+    }
+
+    // The schedule of the given vehicle for the deliveries in this order, or
+    // None if it violates the capacity or a time window.
+    fn schedule(&self, vehicle: usize, route: &[usize]) -> Option<VehicleRoute> {
+        let v = &self.problem.vehicles[vehicle];
+        let load: usize = route.iter().map(|&d| self.problem.deliveries[d].demand).sum();
+        if load > v.capacity { return None; }
+
+        let mut stops = vec![];
+        let (mut time, mut travel_time, mut location) = (v.start_time, 0, 0);
+        for &d in route {
+            let delivery = &self.problem.deliveries[d];
+            let travel = self.cost(location, d + 1)?;
+            let arrival = time + travel;
+            if arrival > delivery.time_window.1 { return None; }
+            let service_start = std::cmp::max(arrival, delivery.time_window.0);
+            time = service_start + delivery.service_time;
+            travel_time += travel;
+            location = d + 1;
+            stops.push(Stop { delivery: d, arrival, service_start, departure: time });
+        }
+        let travel = self.cost(location, 0)?;
+        if time + travel > v.end_time { return None; }
+        Some(VehicleRoute { vehicle, stops, load, travel_time: travel_time + travel, return_time: time + travel })
+    }
+
+    fn travel_time(&self, vehicle: usize, route: &[usize]) -> Option<usize> {
+        self.schedule(vehicle, route).map(|schedule| schedule.travel_time)
+    }
+
+    // The travel time of each route, None for the routes of unusable vehicles.
+    // The moves skip these, so they stay empty.
+    fn travel_times(&self, routes: &[Vec<usize>]) -> Vec<Option<usize>> {
+        routes.iter().enumerate().map(|(v, route)| self.travel_time(v, route)).collect()
+    }
+
+    // Insert unassigned deliveries, cheapest first, until none fits anywhere.
+    fn insert_cheapest(&self, routes: &mut [Vec<usize>], unassigned: &mut Vec<usize>) {
+        let mut current = self.travel_times(routes);
+        loop {
+            // (added travel time, new travel time, position in unassigned, vehicle, position in route)
+            let mut best: Option<(usize, usize, usize, usize, usize)> = None;
+            for (k, &d) in unassigned.iter().enumerate() {
+                for (v, route) in routes.iter().enumerate() {
+                    let Some(current_time) = current[v] else { continue; };
+                    for i in 0..=route.len() {
+                        let mut candidate = route.clone();
+                        candidate.insert(i, d);
+                        if let Some(travel_time) = self.travel_time(v, &candidate) {
+                            let added = travel_time - std::cmp::min(travel_time, current_time);
+                            if best.is_none_or(|best| added < best.0) {
+                                best = Some((added, travel_time, k, v, i));
+                            }
+                        }
+                    }
+                }
+            }
+            match best {
+                Some((_, travel_time, k, v, i)) => {
+                    routes[v].insert(i, unassigned.remove(k));
+                    current[v] = Some(travel_time);
+                },
+                None => return,
+            }
+        }
+    }
+
+    // Apply improving relocate and exchange moves until there are none.
+    // Returns whether anything changed.
+    fn improve(&self, routes: &mut [Vec<usize>]) -> bool {
+        let mut improved = false;
+        while self.relocate(routes) || self.exchange(routes) {
+            improved = true;
+        }
+        improved
+    }
+
+    // Apply the first move of one delivery that lowers the total travel time.
+    fn relocate(&self, routes: &mut [Vec<usize>]) -> bool {
+        let times = self.travel_times(routes);
+        for a in 0..routes.len() {
+            let Some(time_a) = times[a] else { continue; };
+            for i in 0..routes[a].len() {
+                let mut from = routes[a].clone();
+                let d = from.remove(i);
+                let Some(from_time) = self.travel_time(a, &from) else { continue; };
+                for b in 0..routes.len() {
+                    let Some(time_b) = times[b] else { continue; };
+                    let to = if a == b { &from } else { &routes[b] };
+                    for j in 0..=to.len() {
+                        if a == b && j == i { continue; }
+                        let mut candidate = to.clone();
+                        candidate.insert(j, d);
+                        let Some(new_time) = self.travel_time(b, &candidate) else { continue; };
+                        let (before, after) = if a == b { (time_a, new_time) } else { (time_a + time_b, from_time + new_time) };
+                        if after < before {
+                            if a != b { routes[a] = from; }
+                            routes[b] = candidate;
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Apply the first swap of two deliveries of different routes that lowers
+    // the total travel time.
+    fn exchange(&self, routes: &mut [Vec<usize>]) -> bool {
+        for a in 0..routes.len() {
+            for b in a + 1..routes.len() {
+                let (Some(before_a), Some(before_b)) = (self.travel_time(a, &routes[a]), self.travel_time(b, &routes[b])) else { continue; };
+                let before = before_a + before_b;
+                for i in 0..routes[a].len() {
+                    for j in 0..routes[b].len() {
+                        let (mut route_a, mut route_b) = (routes[a].clone(), routes[b].clone());
+                        std::mem::swap(&mut route_a[i], &mut route_b[j]);
+                        if let (Some(time_a), Some(time_b)) = (self.travel_time(a, &route_a), self.travel_time(b, &route_b)) {
+                            if time_a + time_b < before {
+                                routes[a] = route_a;
+                                routes[b] = route_b;
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,54 @@
+// Author: Vinh-An Trinh
+// Copyright 2021
+
+// Routes through an ordered list of waypoints: each waypoint is snapped to the
+// closest node, and each leg (from one waypoint to the next) is a separate
+// point-to-point query with any ShortestPathAlgorithm.
+
+use crate::RoadNetwork;
+use crate::dijkstra::SearchWorkspace;
+use crate::shortest_path_algorithm::{Route, ShortestPathAlgorithm};
+
+// One leg of a MultiLegRoute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leg {
+    // The nodes the waypoints at both ends were snapped to.
+    pub from: usize,
+    pub to: usize,
+
+    // The travel time in seconds and the length in meters.
+    pub cost: usize,
+    pub distance: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiLegRoute {
+    // All legs one after the other. The cost is the sum of the leg costs.
+    pub route: Route,
+    pub distance: f64,
+    pub legs: Vec<Leg>,
+}
+
+// The route through the nodes closest to the given waypoints ((latitude,
+// longitude) in radian), in this order. None if there are less than two
+// waypoints or if one of the legs has no path.
+pub fn compute_route_via_waypoints(rn: &RoadNetwork, algorithm: &dyn ShortestPathAlgorithm, ws: &mut SearchWorkspace, waypoints: &[(f64, f64)]) -> Option<MultiLegRoute> {
+    let nodes = waypoints.iter().map(|&(lat, lng)| rn.get_nearest_node(lat, lng)).collect::<Option<Vec<usize>>>()?;
+    compute_route_via_nodes(rn, algorithm, ws, &nodes)
+}
+
+// Same as compute_route_via_waypoints, for waypoints that are already nodes.
+pub fn compute_route_via_nodes(rn: &RoadNetwork, algorithm: &dyn ShortestPathAlgorithm, ws: &mut SearchWorkspace, waypoints: &[usize]) -> Option<MultiLegRoute> {
+    if waypoints.len() < 2 { return None; }
+    let mut route = Route { cost: 0, nodes: vec![waypoints[0]] };
+    let mut legs = vec![];
+    for pair in waypoints.windows(2) {
+        let leg = algorithm.compute_route(rn, ws, pair[0], pair[1])?;
+        let distance = leg.length(rn);
+        legs.push(Leg { from: pair[0], to: pair[1], cost: leg.cost, distance });
+        route.cost += leg.cost;
+        route.nodes.extend_from_slice(&leg.nodes[1..]);
+    }
+    let distance = legs.iter().map(|leg| leg.distance).sum();
+    Some(MultiLegRoute { route, distance, legs })
+}